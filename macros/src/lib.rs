@@ -3,19 +3,143 @@ extern crate proc_macro;
 use proc_macro::TokenStream;
 use quote::quote;
 use syn;
+use syn::spanned::Spanned;
 
-#[proc_macro_derive(EntityName)]
+/// Implements `EntityName` with the snake cased name of the type, another
+/// name can be given with `#[entity(name = "...")]`, e.g. to give commands
+/// the name of their entity.
+#[proc_macro_derive(EntityName, attributes(entity))]
 pub fn entity_name_derive(input: TokenStream) -> TokenStream {
     let ast = syn::parse(input).unwrap();
     impl_name_macro(&ast)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
 }
 
-fn impl_name_macro(ast: &syn::DeriveInput) -> TokenStream {
+fn impl_name_macro(ast: &syn::DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
     let name = &ast.ident;
-    let gen = quote! {
-        impl EntityName for #name {
-            const NAME: &'static str = stringify!(#name);
+    let mut entity_name = snake_case(&name.to_string());
+    for meta in nested_attrs("entity", &ast.attrs)? {
+        match meta {
+            syn::NestedMeta::Meta(syn::Meta::NameValue(syn::MetaNameValue {
+                path,
+                lit: syn::Lit::Str(value),
+                ..
+            })) if path.is_ident("name") => entity_name = value.value(),
+            other => return Err(syn::Error::new(other.span(), "expected `name = \"...\"`")),
+        }
+    }
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics EntityName for #name #ty_generics #where_clause {
+            const NAME: &'static str = #entity_name;
+        }
+    })
+}
+
+/// `HTTPServer2` becomes `http_server2`
+fn snake_case(ident: &str) -> String {
+    let chars: Vec<char> = ident.chars().collect();
+    let mut snake = String::new();
+    for (i, c) in chars.iter().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            let prev = chars[i - 1];
+            let next_lower = chars.get(i + 1).is_some_and(|n| n.is_lowercase());
+            if prev != '_' && (!prev.is_uppercase() || next_lower) {
+                snake.push('_');
+            }
+        }
+        snake.extend(c.to_lowercase());
+    }
+    snake
+}
+
+/// Implements `Model` for a struct with a field marked `#[model(id)]` and the
+/// change type given with `#[model(change = "Change")]`. Changes are applied
+/// by an inherent method of the struct, `apply` unless another one is given
+/// with `#[model(change = "Change", apply = "method")]`.
+#[proc_macro_derive(Model, attributes(model))]
+pub fn model_derive(input: TokenStream) -> TokenStream {
+    let ast = syn::parse(input).unwrap();
+    impl_model_macro(&ast)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+fn impl_model_macro(ast: &syn::DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let name = &ast.ident;
+    let fields = match &ast.data {
+        syn::Data::Struct(data) => &data.fields,
+        _ => {
+            return Err(syn::Error::new(
+                ast.span(),
+                "Model can only be derived for structs",
+            ))
         }
     };
-    gen.into()
+    let mut id = None;
+    for field in fields.iter() {
+        for meta in nested_attrs("model", &field.attrs)? {
+            match meta {
+                syn::NestedMeta::Meta(syn::Meta::Path(path)) if path.is_ident("id") => {
+                    id = Some(field)
+                }
+                other => return Err(syn::Error::new(other.span(), "expected `id`")),
+            }
+        }
+    }
+    let id = id.ok_or_else(|| syn::Error::new(ast.span(), "missing `#[model(id)]` field"))?;
+    let (id_field, id_type) = (id.ident.as_ref().unwrap(), &id.ty);
+
+    let mut change = None;
+    let mut apply = syn::Ident::new("apply", name.span());
+    for meta in nested_attrs("model", &ast.attrs)? {
+        match meta {
+            syn::NestedMeta::Meta(syn::Meta::NameValue(syn::MetaNameValue {
+                path,
+                lit: syn::Lit::Str(value),
+                ..
+            })) => {
+                if path.is_ident("change") {
+                    change = Some(value.parse::<syn::Type>()?);
+                } else if path.is_ident("apply") {
+                    apply = value.parse()?;
+                } else {
+                    return Err(syn::Error::new(path.span(), "expected `change` or `apply`"));
+                }
+            }
+            other => return Err(syn::Error::new(other.span(), "expected `name = \"value\"`")),
+        }
+    }
+    let change = change
+        .ok_or_else(|| syn::Error::new(ast.span(), "missing `#[model(change = \"...\")]`"))?;
+
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics Model for #name #ty_generics #where_clause {
+            type Id = #id_type;
+            type Change = #change;
+            fn id(&self) -> Self::Id {
+                self.#id_field.clone()
+            }
+            fn apply_change(&mut self, change: &Self::Change) {
+                self.#apply(change)
+            }
+        }
+    })
+}
+
+/// The arguments of every `#[name(...)]` attribute
+fn nested_attrs(name: &str, attrs: &[syn::Attribute]) -> syn::Result<Vec<syn::NestedMeta>> {
+    let mut metas = vec![];
+    for attr in attrs.iter().filter(|a| a.path.is_ident(name)) {
+        match attr.parse_meta()? {
+            syn::Meta::List(list) => metas.extend(list.nested),
+            other => {
+                let msg = format!("expected `{}(...)`", name);
+                return Err(syn::Error::new(other.span(), msg));
+            }
+        }
+    }
+    Ok(metas)
 }
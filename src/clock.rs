@@ -0,0 +1,45 @@
+use chrono::prelude::*;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// Tells the time to the parts of the system that timestamp things
+pub trait Clock: fmt::Debug + Send + Sync + 'static {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// A clock that can be shared among actors
+pub type SharedClock = Arc<dyn Clock>;
+
+/// The real wall clock
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A clock that only moves when told to, clones share the same time
+#[derive(Debug, Clone)]
+pub struct MockClock(Arc<Mutex<DateTime<Utc>>>);
+
+impl MockClock {
+    pub fn new(start: DateTime<Utc>) -> Self {
+        MockClock(Arc::new(Mutex::new(start)))
+    }
+
+    pub fn advance(&self, by: chrono::Duration) {
+        *self.0.lock().unwrap() += by;
+    }
+
+    pub fn set(&self, to: DateTime<Utc>) {
+        *self.0.lock().unwrap() = to;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.0.lock().unwrap()
+    }
+}
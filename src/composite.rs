@@ -0,0 +1,150 @@
+//! Large models can be split in parts, each with its own vocabulary of
+//! changes, composing the change of the model from the changes of its parts
+//! with `composite_change!`.
+use riker::actors::Message;
+
+/// A part of a model that changes on its own
+pub trait Part {
+    type Change: Message;
+    fn apply_change(&mut self, change: &Self::Change);
+}
+
+/// Declares the change of a model as an enum wrapping the changes of its
+/// parts, each variant names the field of the model holding the part.
+/// Every part change converts `into` the composite change and `apply_to`
+/// dispatches it to the right part, ready to be called from
+/// `Model::apply_change`.
+///
+/// ```ignore
+/// composite_change! {
+///     #[derive(Clone, Debug)]
+///     pub enum AccountChange for Account {
+///         Profile(ProfileChange) => profile,
+///         Balance(BalanceChange) => balance,
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! composite_change {
+    (
+        $(#[$meta:meta])*
+        $vis:vis enum $name:ident for $model:ty {
+            $($variant:ident($change:ty) => $field:ident),+ $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        $vis enum $name {
+            $($variant($change)),+
+        }
+
+        $(
+            impl From<$change> for $name {
+                fn from(change: $change) -> Self {
+                    $name::$variant(change)
+                }
+            }
+        )+
+
+        impl $name {
+            /// Applies the change to the part of the model it belongs to
+            $vis fn apply_to(&self, model: &mut $model) {
+                match self {
+                    $($name::$variant(change) => {
+                        $crate::Part::apply_change(&mut model.$field, change)
+                    })+
+                }
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::CommitStore;
+    use crate::{EntityId, Event, MemStore, Model};
+    use chrono::Utc;
+    use futures::executor::block_on;
+
+    #[derive(Clone, Debug, Default)]
+    struct Profile {
+        name: String,
+    }
+    #[derive(Clone, Debug)]
+    enum ProfileChange {
+        Rename(String),
+    }
+    impl Part for Profile {
+        type Change = ProfileChange;
+        fn apply_change(&mut self, change: &ProfileChange) {
+            match change {
+                ProfileChange::Rename(name) => self.name = name.clone(),
+            }
+        }
+    }
+
+    #[derive(Clone, Debug, Default)]
+    struct Balance {
+        cents: i64,
+    }
+    #[derive(Clone, Debug)]
+    enum BalanceChange {
+        Deposit(i64),
+        Withdraw(i64),
+    }
+    impl Part for Balance {
+        type Change = BalanceChange;
+        fn apply_change(&mut self, change: &BalanceChange) {
+            match change {
+                BalanceChange::Deposit(n) => self.cents += n,
+                BalanceChange::Withdraw(n) => self.cents -= n,
+            }
+        }
+    }
+
+    #[derive(Clone, Debug, Default)]
+    struct Account {
+        id: EntityId,
+        profile: Profile,
+        balance: Balance,
+    }
+
+    composite_change! {
+        #[derive(Clone, Debug)]
+        enum AccountChange for Account {
+            Profile(ProfileChange) => profile,
+            Balance(BalanceChange) => balance,
+        }
+    }
+
+    impl Model for Account {
+        type Id = EntityId;
+        type Change = AccountChange;
+        fn id(&self) -> EntityId {
+            self.id
+        }
+        fn apply_change(&mut self, change: &AccountChange) {
+            change.apply_to(self)
+        }
+    }
+
+    #[test]
+    fn compose_changes_of_parts() {
+        let store = MemStore::new();
+        let account = Account::default();
+        let id = account.id();
+        let changes: Vec<AccountChange> = vec![
+            ProfileChange::Rename("savings".into()).into(),
+            BalanceChange::Deposit(500).into(),
+            BalanceChange::Withdraw(120).into(),
+        ];
+        block_on(store.commit(Event::Create(account).into())).unwrap();
+        for change in changes {
+            block_on(store.commit(Event::Change(id, change).into())).unwrap();
+        }
+
+        let account = block_on(store.snapshot(id, Utc::now())).unwrap();
+        assert_eq!(account.profile.name, "savings");
+        assert_eq!(account.balance.cents, 380);
+    }
+}
@@ -1,21 +1,267 @@
-use crate::store::{Commit, CommitStore, Store, StoreRef};
-use crate::EntityId;
+use crate::entity_manager::{ask, ManagerError, Peers};
+use crate::lifecycle::{notify, Lifecycle, SystemBus};
+use crate::store::{
+    check_history, AtTag, Batch, CancelToken, Checkpoint, Commit, CommitError, CommitResult,
+    CommitStore, CommitsSince, Comparator, History, Import, Limit, RecentHistory, Revert,
+    SnapshotPage, Store, StoreConfig, StoreMsg, StoreRef,
+};
+use crate::testing::EffectSink;
+use crate::{EntityId, Event, EventKind, SharedClock};
 use async_trait::async_trait;
 use chrono::prelude::*;
 use futures::lock::Mutex;
 use riker::actors::*;
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::HashMap;
 use std::fmt;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use thiserror::Error;
+use uuid::Uuid;
 
 /// An Aggregate is the projected data of a series of events of an entity,
 /// given an initial state update events are applied to it until it reaches the desired state.
 pub trait Model: Message {
+    /// Identifies the entity, usually an `EntityId` or an `Id<Self>` to tell
+    /// ids of different models apart
+    type Id: Message + Eq + Hash + fmt::Display;
     type Change: Message;
-    fn id(&self) -> EntityId;
+    fn id(&self) -> Self::Id;
     fn apply_change(&mut self, change: &Self::Change);
+
+    /// Position of a change in the order of the domain, e.g. a logical version
+    /// carried by the change, that stores replay changes in instead of the
+    /// order they were committed. Changes without one go before the ones
+    /// that have it so models should give it to all of their changes or none.
+    fn change_order(_change: &Self::Change) -> Option<u64> {
+        None
+    }
+
+    /// Bytes the model owns on the heap, e.g. the contents of its strings and
+    /// vectors, stores that keep a memory budget add it to the size of the
+    /// commits that create it. Models that don't tell count for their stack size.
+    fn heap_size(&self) -> usize {
+        0
+    }
+
+    /// Like `heap_size` but for a change to the model
+    fn change_heap_size(_change: &Self::Change) -> usize {
+        0
+    }
+
+    /// Rules every state of the model has to follow, stores configured with
+    /// an `InvariantPolicy` check them on the state a commit would lead to.
+    fn check_invariants(&self) -> std::result::Result<(), String> {
+        Ok(())
+    }
+
+    /// The change that turns this state into the target one, models that
+    /// provide it can be reverted to a past state by appending it.
+    fn reset_change(&self, _target: &Self) -> Option<Self::Change> {
+        None
+    }
+}
+
+/// Models that can be written to durable storage and read back along with
+/// their ids and changes, which makes their events and commits serializable.
+/// Implemented for every model that supports serde, backends that persist
+/// commits can ask for it instead of spelling out every bound.
+pub trait SerializableModel:
+    Model<Id: Serialize + DeserializeOwned, Change: Serialize + DeserializeOwned>
+    + Serialize
+    + DeserializeOwned
+{
 }
 
-pub type Result<E> = std::result::Result<Commit<<E as ES>::Model>, <E as ES>::Error>;
+impl<M> SerializableModel for M
+where
+    M: Model + Serialize + DeserializeOwned,
+    M::Id: Serialize + DeserializeOwned,
+    M::Change: Serialize + DeserializeOwned,
+{
+}
+
+pub type Result<E> = std::result::Result<CommandOutcome<<E as ES>::Model>, <E as ES>::Error>;
+
+/// Reply to a `TryCmd`, the id of the entity the command was handled by or
+/// why it didn't go through.
+pub type CommandResult<E> =
+    std::result::Result<<<E as ES>::Model as Model>::Id, CommandError<<E as ES>::Error>>;
+
+/// Why a command didn't change anything
+#[derive(Error, Clone, Debug, PartialEq)]
+pub enum CommandError<Err: fmt::Debug> {
+    /// The handler rejected the command with the error
+    #[error("Command was rejected: {0}")]
+    Rejected(Err),
+    /// The outcome of the command couldn't be persisted
+    #[error("Couldn't persist the outcome of the command: {0}")]
+    Commit(CommitError),
+    /// The command couldn't reach its entity
+    #[error(transparent)]
+    Manager(#[from] ManagerError),
+}
+
+impl<Err: fmt::Debug + fmt::Display> From<CommandError<Err>> for ManagerError {
+    fn from(err: CommandError<Err>) -> Self {
+        match err {
+            CommandError::Rejected(err) => ManagerError::Rejected(err.to_string()),
+            CommandError::Commit(err) => ManagerError::Commit(err),
+            CommandError::Manager(err) => err,
+        }
+    }
+}
+
+/// What the sender of a command expects as reply
+#[derive(Debug, Clone, Copy)]
+enum Reply {
+    Id,
+    Outcome,
+    Result,
+}
+
+impl Reply {
+    /// Tells the sender how handling the command went in the shape it
+    /// expects, senders of the id learn why it didn't go through as a
+    /// `ManagerError`.
+    fn send<E: ES>(
+        self,
+        sender: &BasicActorRef,
+        handled: std::result::Result<CommandOutcome<E::Model>, CommandError<E::Error>>,
+    ) -> std::result::Result<(), ()> {
+        match self {
+            Reply::Id => {
+                let id: std::result::Result<<E::Model as Model>::Id, ManagerError> = handled
+                    .map(|outcome| outcome.id)
+                    .map_err(ManagerError::from);
+                sender.try_tell(id, None)
+            }
+            Reply::Outcome => sender.try_tell(handled, None),
+            Reply::Result => {
+                let id: CommandResult<E> = handled.map(|outcome| outcome.id);
+                sender.try_tell(id, None)
+            }
+        }
+    }
+}
+
+/// The result of successfully handling a command, the commit that will be
+/// persisted along with any advisory warnings that don't prevent the change.
+/// The commit can be followed by more `changes` to the same entity, all of
+/// them are persisted as a whole or not at all. The command can also have
+/// `effects` on other entities of the same model, they are persisted in the
+/// same batch as the commit, see `CommitStore::commit_all`.
+#[derive(Clone, Debug)]
+pub struct CommandOutcome<M: Model> {
+    pub id: M::Id,
+    pub commit: Commit<M>,
+    pub changes: Vec<Commit<M>>,
+    pub effects: Vec<Commit<M>>,
+    pub warnings: Vec<String>,
+}
+
+impl<M: Model> CommandOutcome<M> {
+    pub fn new(commit: Commit<M>) -> Self {
+        CommandOutcome {
+            id: commit.entity_id(),
+            commit,
+            changes: vec![],
+            effects: vec![],
+            warnings: vec![],
+        }
+    }
+
+    pub fn with_change<C: Into<Commit<M>>>(mut self, change: C) -> Self {
+        self.changes.push(change.into());
+        self
+    }
+
+    /// Creates or changes another entity along with the one of the command
+    pub fn with_effect<C: Into<Commit<M>>>(mut self, effect: C) -> Self {
+        self.effects.push(effect.into());
+        self
+    }
+
+    /// Updates the models kept in memory once the outcome is persisted
+    fn apply_to(&self, cache: &Option<SharedCache<M>>) {
+        if let Some(cache) = cache {
+            let commits = std::iter::once(&self.commit)
+                .chain(self.changes.iter())
+                .chain(self.effects.iter());
+            cache.lock().unwrap().apply(commits);
+        }
+    }
+
+    pub(crate) fn set_transaction(&mut self, transaction: Option<Uuid>) {
+        self.commit.set_transaction(transaction);
+        for c in self.changes.iter_mut().chain(self.effects.iter_mut()) {
+            c.set_transaction(transaction);
+        }
+    }
+
+    /// Correlates the commits of the outcome that aren't part of a chain yet
+    pub(crate) fn correlate(&mut self, id: EntityId) {
+        let commits = std::iter::once(&mut self.commit)
+            .chain(self.changes.iter_mut())
+            .chain(self.effects.iter_mut());
+        for c in commits.filter(|c| c.correlation_id().is_none()) {
+            c.set_correlation_id(Some(id));
+        }
+    }
+
+    /// Asks the store to persist the commit along with its follow up changes
+    /// and its effects on other entities
+    pub(crate) fn store_msg(&self) -> CommitResult<StoreMsg<M>> {
+        if self.changes.is_empty() && self.effects.is_empty() {
+            return Ok(self.commit.clone().into());
+        }
+        let history: Vec<_> = std::iter::once(self.commit.clone())
+            .chain(self.changes.iter().cloned())
+            .collect();
+        let creates = self.commit.kind() == EventKind::Create;
+        if creates && self.effects.is_empty() {
+            return Ok(Import {
+                history,
+                force: false,
+            }
+            .into());
+        }
+        if creates && !self.changes.is_empty() {
+            check_history(&self.id, &history)?;
+        } else if self.changes.iter().any(|c| c.entity_id() != self.id) {
+            return Err(CommitError::InvalidHistory(self.id.to_string()));
+        }
+        let batch = history.into_iter().chain(self.effects.iter().cloned());
+        Ok(Batch(batch.collect()).into())
+    }
+
+    /// Takes the effects out of the outcome when they go to a sink instead
+    /// of being persisted.
+    fn divert_effects(&mut self, sink: &Option<EffectSink<M>>) -> Vec<Commit<M>> {
+        match sink {
+            Some(_) => std::mem::take(&mut self.effects),
+            None => vec![],
+        }
+    }
+
+    pub fn with_warning<W: Into<String>>(mut self, warning: W) -> Self {
+        self.warnings.push(warning.into());
+        self
+    }
+}
+
+impl<M: Model> From<Commit<M>> for CommandOutcome<M> {
+    fn from(commit: Commit<M>) -> Self {
+        CommandOutcome::new(commit)
+    }
+}
+
+impl<M: Model> From<Event<M>> for CommandOutcome<M> {
+    fn from(event: Event<M>) -> Self {
+        CommandOutcome::new(event.into())
+    }
+}
 
 /// Implement this trait to allow your entity handle external commands
 #[async_trait]
@@ -23,13 +269,86 @@ pub trait ES: EntityName + fmt::Debug + Send + Sync + 'static {
     type Args: ActorArgs;
     type Model: Model;
     type Cmd: Message;
-    type Error: fmt::Debug;
+    type Error: Message + fmt::Display;
 
     /// The entity constructor receives a Riker context to be able to interact
     /// with other actors.
-    fn new(cx: &Context<CQRS<Self::Cmd>>, args: Self::Args) -> Self;
+    fn new(cx: &Context<CQRS<Self::Cmd, <Self::Model as Model>::Id>>, args: Self::Args) -> Self;
 
+    /// Handlers run outside the entity actor, one at a time, so the actor
+    /// keeps answering queries meanwhile and handlers can query their own
+    /// entity. Commanding their own entity would wait forever though.
     async fn handle_command(&mut self, _cmd: Self::Cmd) -> Result<Self>;
+
+    /// Called right after `new` when the entity is registered in a `Manager`
+    /// with the handle to interact with the other registered entities.
+    fn with_peers(&mut self, _peers: Peers) {}
+
+    /// Called right after `new` with the handle of the entity's own store,
+    /// entities that keep it and return it from `store` can `load` their
+    /// models in the handler to decide on the current state.
+    fn with_store(&mut self, _store: StoreHandle<Self::Model>) {}
+
+    /// The handle given to `with_store` if the entity kept it
+    fn store(&self) -> Option<&StoreHandle<Self::Model>> {
+        None
+    }
+
+    /// Optional description of the shape of the entity's commands and model
+    /// for tools that work with any registered entity.
+    fn schema() -> Option<serde_json::Value> {
+        None
+    }
+}
+
+/// Lets command handlers load the current state of the models of their
+/// entity, implemented for every entity that keeps its `StoreHandle`.
+#[async_trait]
+pub trait Load: ES {
+    /// The model as the entity would answer a query about it, `None` when it
+    /// doesn't exist or the entity didn't keep the handle of its store.
+    async fn load(&self, id: <Self::Model as Model>::Id) -> Option<Self::Model>;
+}
+
+#[async_trait]
+impl<E: ES> Load for E {
+    async fn load(&self, id: <Self::Model as Model>::Id) -> Option<Self::Model> {
+        match self.store() {
+            Some(store) => store.load(id).await,
+            None => None,
+        }
+    }
+}
+
+/// The store of an entity as seen by its command handler
+#[derive(Clone)]
+pub struct StoreHandle<M: Model> {
+    sys: ActorSystem,
+    store: StoreRef<M>,
+    clock: Option<SharedClock>,
+    as_of: Option<DateTime<Utc>>,
+}
+
+impl<M: Model> StoreHandle<M> {
+    /// The state of the model as of the moment the entity answers queries
+    pub async fn load(&self, id: M::Id) -> Option<M> {
+        let now = match (self.as_of, &self.clock) {
+            (Some(at), _) => at,
+            (None, Some(clock)) => clock.now(),
+            (None, None) => Utc::now(),
+        };
+        let msg = StoreMsg::<M>::from((id, now));
+        ask(&self.sys, self.store.clone().into(), msg)
+            .await
+            .ok()
+            .flatten()
+    }
+}
+
+impl<M: Model> fmt::Debug for StoreHandle<M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "StoreHandle({})", self.store.path())
+    }
 }
 
 /// Entity is an actor that handles user commands running the buissiness logic defined
@@ -41,6 +360,114 @@ pub struct Entity<E: ES, S: CommitStore<E::Model>> {
     store_backend: Option<S>,
     args: E::Args,
     es: Option<Arc<Mutex<E>>>,
+    store_config: StoreConfig<E::Model>,
+    sys_bus: Option<SystemBus>,
+    store_name: Option<String>,
+    peers: Option<Peers>,
+    hydration: Hydration,
+    cache: Option<SharedCache<E::Model>>,
+    clock: Option<SharedClock>,
+    effect_sink: Option<EffectSink<E::Model>>,
+    queued: Arc<AtomicUsize>,
+}
+
+/// How an entity gets the models it's queried about
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Hydration {
+    /// Replay the history of the model from the store on every query
+    Eager,
+    /// Keep the models in memory, applying the changes of the commands of the
+    /// entity to them instead of replaying their history on every query.
+    /// Models are loaded from the store the first time they are queried and
+    /// after an `Invalidate`, needed when commits reach the store other than
+    /// through the entity.
+    Cached,
+    /// Like `Cached` without keeping the first query about a model waiting,
+    /// it's answered with no model while the model loads in the background.
+    /// Queries made until it's loaded wait for it.
+    Lazy,
+}
+
+impl Default for Hydration {
+    fn default() -> Self {
+        Hydration::Eager
+    }
+}
+
+/// Options of an `Entity`, none of them is set by default
+#[derive(Debug, Clone)]
+pub struct EntityConfig<M: Model> {
+    store: StoreConfig<M>,
+    sys_bus: Option<SystemBus>,
+    store_name: Option<String>,
+    peers: Option<Peers>,
+    hydration: Hydration,
+    clock: Option<SharedClock>,
+    effect_sink: Option<EffectSink<M>>,
+}
+
+impl<M: Model> EntityConfig<M> {
+    /// Options of the store child of the entity, it reports to the system bus
+    /// and tells the time with the clock of the entity unless it has its own.
+    pub fn store(mut self, config: StoreConfig<M>) -> Self {
+        self.store = config;
+        self
+    }
+
+    /// Reports the lifecycle of the entity and its store
+    pub fn sys_bus(mut self, bus: SystemBus) -> Self {
+        self.sys_bus = Some(bus);
+        self
+    }
+
+    /// Name of the store child of the entity, it's also the prefix of the
+    /// topic its events are published to. Defaults to the name of the entity
+    /// actor followed by `_store`, set it to tell apart several stores of the
+    /// same model.
+    pub fn store_name(mut self, name: &str) -> Self {
+        self.store_name = Some(name.into());
+        self
+    }
+
+    /// Handle the handler of the entity gets to reach other entities
+    pub fn peers(mut self, peers: Peers) -> Self {
+        self.peers = Some(peers);
+        self
+    }
+
+    /// How the entity gets the models it's queried about, `Eager` by default
+    pub fn hydration(mut self, hydration: Hydration) -> Self {
+        self.hydration = hydration;
+        self
+    }
+
+    /// Makes the entity and its store tell the time with the given clock,
+    /// commits are timestamped and queries answered as of the moment it tells.
+    pub fn clock(mut self, clock: SharedClock) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Collects the effects of commands on other entities in the sink instead
+    /// of persisting them.
+    pub fn effect_sink(mut self, sink: EffectSink<M>) -> Self {
+        self.effect_sink = Some(sink);
+        self
+    }
+}
+
+impl<M: Model> Default for EntityConfig<M> {
+    fn default() -> Self {
+        EntityConfig {
+            store: StoreConfig::default(),
+            sys_bus: None,
+            store_name: None,
+            peers: None,
+            hydration: Hydration::default(),
+            clock: None,
+            effect_sink: None,
+        }
+    }
 }
 
 impl<E, S, Args> ActorFactoryArgs<(S, Args)> for Entity<E, S>
@@ -50,98 +477,622 @@ where
     S: CommitStore<E::Model>,
 {
     fn create_args((store_backend, args): (S, Args)) -> Self {
+        Self::create_args((store_backend, args, EntityConfig::default()))
+    }
+}
+
+impl<E, S, Args> ActorFactoryArgs<(S, Args, EntityConfig<E::Model>)> for Entity<E, S>
+where
+    Args: ActorArgs,
+    E: ES<Args = Args>,
+    S: CommitStore<E::Model>,
+{
+    fn create_args((store_backend, args, config): (S, Args, EntityConfig<E::Model>)) -> Self {
         Entity {
             store: None,
             store_backend: Some(store_backend),
             es: None,
             args,
+            store_config: config.store,
+            sys_bus: config.sys_bus,
+            store_name: config.store_name,
+            peers: config.peers,
+            hydration: config.hydration,
+            cache: match config.hydration {
+                Hydration::Eager => None,
+                Hydration::Cached | Hydration::Lazy => Some(Default::default()),
+            },
+            clock: config.clock,
+            effect_sink: config.effect_sink,
+            queued: Arc::default(),
         }
     }
 }
 
+/// A command the entity received and hasn't replied yet, counted in the
+/// queue depth of the entity until it's dropped.
+struct QueuedCommand(Arc<AtomicUsize>);
+
+impl QueuedCommand {
+    fn new(queued: &Arc<AtomicUsize>) -> Self {
+        queued.fetch_add(1, Ordering::SeqCst);
+        QueuedCommand(queued.clone())
+    }
+}
+
+impl Drop for QueuedCommand {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+type SharedCache<M> = Arc<std::sync::Mutex<ModelCache<M>>>;
+
+/// Models kept in memory by an entity along with how many times they were
+/// changed, a model loaded while it changed might be stale already. Lazily
+/// loaded models keep the queries waiting for them until they are loaded.
+#[derive(Debug)]
+struct ModelCache<M: Model> {
+    models: HashMap<M::Id, M>,
+    changes: u64,
+    loading: HashMap<M::Id, Vec<Sender>>,
+}
+
+impl<M: Model> Default for ModelCache<M> {
+    fn default() -> Self {
+        ModelCache {
+            models: HashMap::new(),
+            changes: 0,
+            loading: HashMap::new(),
+        }
+    }
+}
+
+impl<M: Model> ModelCache<M> {
+    fn apply<'a>(&mut self, commits: impl Iterator<Item = &'a Commit<M>>) {
+        self.changes += 1;
+        for c in commits {
+            match &**c {
+                Event::Create(model) => {
+                    self.models.insert(model.id(), model.clone());
+                }
+                Event::Delete(id) => {
+                    self.models.remove(id);
+                }
+                // changes in domain order might go before others already applied
+                Event::Change(id, change) if M::change_order(change).is_some() => {
+                    self.models.remove(id);
+                }
+                Event::Change(id, change) => {
+                    if let Some(model) = self.models.get_mut(id) {
+                        model.apply_change(change);
+                    }
+                }
+            }
+        }
+    }
+
+    fn invalidate(&mut self, id: &M::Id) {
+        self.changes += 1;
+        self.models.remove(id);
+    }
+}
+
 impl<E, S> Actor for Entity<E, S>
 where
     E: ES,
     S: CommitStore<E::Model>,
 {
-    type Msg = CQRS<E::Cmd>;
+    type Msg = EntityMsg<E>;
 
     fn pre_start(&mut self, ctx: &Context<Self::Msg>) {
-        let entity_handler = Arc::new(Mutex::new(E::new(ctx, self.args.clone())));
-        let store_backend = self.store_backend.take().unwrap();
-        self.es = Some(entity_handler);
-        self.store = Some(
-            ctx.actor_of_args::<Store<E::Model, S>, _>(
-                &format!("{}_store", E::NAME),
-                store_backend,
-            )
-            .unwrap(),
-        );
+        let store_backend = self.store_backend.clone().unwrap();
+        let store_name = match &self.store_name {
+            Some(name) => name.clone(),
+            None => format!("{}_store", ctx.myself().name()),
+        };
+        let store_config = self
+            .store_config
+            .clone()
+            .inherit(self.sys_bus.clone(), self.clock.clone());
+        let store = ctx
+            .actor_of_args::<Store<E::Model, S>, _>(&store_name, (store_backend, store_config))
+            .unwrap();
+        let entity_handler = self.new_handler(ctx, store.clone(), None);
+        self.es = Some(Arc::new(Mutex::new(entity_handler)));
+        self.store = Some(store);
+        notify(&self.sys_bus, E::NAME, Lifecycle::Started);
+    }
+
+    fn post_stop(&mut self) {
+        notify(&self.sys_bus, E::NAME, Lifecycle::Stopped);
     }
 
     fn recv(&mut self, ctx: &Context<Self::Msg>, msg: Self::Msg, sender: Sender) {
         match msg {
             CQRS::Query(q) => self.receive(ctx, q, sender),
-            CQRS::Cmd(cmd) => {
-                let store = self.store.as_ref().unwrap().clone();
-                let es = self.es.clone();
-                ctx.system.exec.spawn_ok(async move {
-                    let cmd_dbg = format!("{:?}", cmd);
-                    debug!("processing command {}", cmd_dbg);
-                    let commit = es
-                        .unwrap()
-                        .lock()
-                        .await
-                        .handle_command(cmd)
-                        .await
-                        .expect("Failed handling command");
-                    let entity_id = commit.entity_id();
-                    store.tell(commit, None);
+            CQRS::Cmd(cmd) => self.run_command(ctx, cmd, sender, Reply::Id, None, None),
+            CQRS::CmdOutcome(cmd) => self.run_command(ctx, cmd, sender, Reply::Outcome, None, None),
+            CQRS::TryCmd(cmd) => self.run_command(ctx, cmd, sender, Reply::Result, None, None),
+            CQRS::Transaction(tx, cmd) => {
+                self.run_command(ctx, cmd, sender, Reply::Id, Some(tx), None)
+            }
+            CQRS::Correlated(id, cmd) => {
+                self.run_command(ctx, cmd, sender, Reply::Id, None, Some(id))
+            }
+            CQRS::Retry(cmd, attempts) => self.run_retrying(ctx, cmd, attempts, sender),
+            CQRS::DryRun(cmd) => self.dry_run(ctx, cmd, sender),
+            CQRS::DryRunAt(at, cmd) => self.dry_run_at(ctx, at, cmd, sender),
+            CQRS::QueueDepth => {
+                if let Some(sender) = sender {
+                    let _ = sender.try_tell(self.queued.load(Ordering::SeqCst), None);
+                }
+            }
+            CQRS::Checkpoint(at) => self.store.as_ref().unwrap().tell(Checkpoint(at), sender),
+            CQRS::Revert(id, at) => self.revert(ctx, id, at, sender),
+            CQRS::Invalidate(id) => {
+                if let Some(cache) = &self.cache {
+                    cache.lock().unwrap().invalidate(&id);
+                }
+            }
+        };
+    }
+}
 
+impl<E, S> Entity<E, S>
+where
+    E: ES,
+    S: CommitStore<E::Model>,
+{
+    /// Runs the command handler and stores its commit replying to the sender
+    /// as requested, the commit is tagged with the transaction and correlated
+    /// to the chain the command was issued in if any. Commands the handler
+    /// rejects or whose commit fails are replied with the error.
+    fn run_command(
+        &self,
+        ctx: &Context<EntityMsg<E>>,
+        cmd: E::Cmd,
+        sender: Sender,
+        reply: Reply,
+        transaction: Option<Uuid>,
+        correlation: Option<EntityId>,
+    ) {
+        let sys = ctx.system.clone();
+        let store = self.store.as_ref().unwrap().clone();
+        let es = self.es.clone();
+        let cache = self.cache.clone();
+        let effect_sink = self.effect_sink.clone();
+        let queued = QueuedCommand::new(&self.queued);
+        ctx.system.exec.spawn_ok(async move {
+            let cmd_dbg = format!("{:?}", cmd);
+            debug!("processing command {}", cmd_dbg);
+            let handled = es.unwrap().lock().await.handle_command(cmd).await;
+            let mut outcome = match handled {
+                Ok(outcome) => outcome,
+                Err(err) => {
+                    warn!("{} was rejected: {:?}", cmd_dbg, err);
+                    drop(queued);
                     if let Some(sender) = sender {
-                        let _ = sender
-                            .try_tell(entity_id, None)
-                            .map_err(|_| warn!("Couldn't signal completion of {}", cmd_dbg));
+                        let _ = reply
+                            .send::<E>(&sender, Err(CommandError::Rejected(err)))
+                            .map_err(|_| warn!("Couldn't signal rejection of {}", cmd_dbg));
                     }
-                });
+                    return;
+                }
+            };
+            if transaction.is_some() {
+                outcome.set_transaction(transaction);
+            }
+            if let Some(id) = correlation {
+                outcome.correlate(id);
+            }
+            let effects = outcome.divert_effects(&effect_sink);
+            for warning in outcome.warnings.iter() {
+                debug!("{} warned: {}", cmd_dbg, warning);
+            }
+            let committed: CommitResult<()> = match outcome.store_msg() {
+                Ok(msg) => ask(&sys, store.into(), msg)
+                    .await
+                    .unwrap_or(Err(CommitError::Unavailable)),
+                Err(err) => Err(err),
+            };
+            let handled = match committed {
+                Ok(_) => {
+                    if let Some(sink) = &effect_sink {
+                        sink.capture(effects);
+                    }
+                    outcome.apply_to(&cache);
+                    Ok(outcome)
+                }
+                Err(err) => {
+                    warn!("Couldn't store outcome of {}: {}", cmd_dbg, err);
+                    Err(CommandError::Commit(err))
+                }
+            };
+
+            drop(queued);
+            if let Some(sender) = sender {
+                let _ = reply
+                    .send::<E>(&sender, handled)
+                    .map_err(|_| warn!("Couldn't signal completion of {}", cmd_dbg));
+            }
+        });
+    }
+
+    /// Appends the compensating commit of a revert, models kept in memory
+    /// are reloaded once it's persisted.
+    fn revert(
+        &self,
+        ctx: &Context<EntityMsg<E>>,
+        id: <E::Model as Model>::Id,
+        at: DateTime<Utc>,
+        sender: Sender,
+    ) {
+        let store = self.store.as_ref().unwrap().clone();
+        let cache = match self.cache.clone() {
+            Some(cache) => cache,
+            None => return store.tell(Revert { id, at }, sender),
+        };
+        let sys = ctx.system.clone();
+        ctx.system.exec.spawn_ok(async move {
+            let msg = StoreMsg::<E::Model>::from(Revert { id: id.clone(), at });
+            let reverted: CommitResult<Commit<E::Model>> = ask(&sys, store.into(), msg)
+                .await
+                .unwrap_or(Err(CommitError::Unavailable));
+            cache.lock().unwrap().invalidate(&id);
+            if let Some(sender) = sender {
+                let _ = sender.try_tell(reverted, None);
+            }
+        });
+    }
+
+    /// Creates a command handler reaching the peers of the entity and its
+    /// store, models are loaded as of the given moment or the present.
+    fn new_handler(
+        &self,
+        ctx: &Context<EntityMsg<E>>,
+        store: StoreRef<E::Model>,
+        as_of: Option<DateTime<Utc>>,
+    ) -> E {
+        let mut es = E::new(ctx, self.args.clone());
+        if let Some(peers) = self.peers.clone() {
+            es.with_peers(peers);
+        }
+        es.with_store(StoreHandle {
+            sys: ctx.system.clone(),
+            store,
+            clock: self.clock.clone(),
+            as_of,
+        });
+        es
+    }
+
+    /// The moment queries are answered as of
+    fn now(&self) -> DateTime<Utc> {
+        match &self.clock {
+            Some(clock) => clock.now(),
+            None => Utc::now(),
+        }
+    }
+
+    /// Replies with the model kept in memory, loading it from the store when
+    /// it's not there yet. Lazy hydration replies no model instead of waiting
+    /// for the first load.
+    fn query_cached(
+        &self,
+        ctx: &Context<EntityMsg<E>>,
+        cache: SharedCache<E::Model>,
+        id: <E::Model as Model>::Id,
+        sender: Sender,
+    ) {
+        let (changes, sender) = {
+            let mut cache = cache.lock().unwrap();
+            if let Some(model) = cache.models.get(&id).cloned() {
+                if let Some(sender) = sender {
+                    let _ = sender.try_tell(Some(model), None);
+                }
+                return;
+            }
+            let changes = cache.changes;
+            match (self.hydration, cache.loading.get_mut(&id)) {
+                (Hydration::Lazy, Some(waiting)) => {
+                    waiting.push(sender);
+                    return;
+                }
+                (Hydration::Lazy, None) => {
+                    cache.loading.insert(id.clone(), vec![]);
+                    if let Some(sender) = sender {
+                        let _ = sender.try_tell(None::<E::Model>, None);
+                    }
+                    (changes, None)
+                }
+                _ => (changes, sender),
             }
         };
+        let sys = ctx.system.clone();
+        let store = self.store.as_ref().unwrap().clone();
+        let now = self.now();
+        ctx.system.exec.spawn_ok(async move {
+            let model: Option<E::Model> = ask(
+                &sys,
+                store.into(),
+                StoreMsg::<E::Model>::from((id.clone(), now)),
+            )
+            .await
+            .ok()
+            .flatten();
+            let waiting = {
+                let mut cache = cache.lock().unwrap();
+                let waiting = cache.loading.remove(&id).unwrap_or_default();
+                // a change made while loading might be missing from the model
+                if let (Some(model), true) = (&model, cache.changes == changes) {
+                    cache.models.insert(id, model.clone());
+                }
+                waiting
+            };
+            for sender in waiting.into_iter().chain(Some(sender)).flatten() {
+                let _ = sender.try_tell(model.clone(), None);
+            }
+        });
+    }
+
+    fn dry_run(&self, ctx: &Context<EntityMsg<E>>, cmd: E::Cmd, sender: Sender) {
+        let es = self.es.clone().unwrap();
+        ctx.system.exec.spawn_ok(async move {
+            let cmd_dbg = format!("{:?}", cmd);
+            debug!("dry running command {}", cmd_dbg);
+            let result = es
+                .lock()
+                .await
+                .handle_command(cmd)
+                .await
+                .map_err(CommandError::Rejected);
+            if let Some(sender) = sender {
+                let _ = sender
+                    .try_tell(result, None)
+                    .map_err(|_| warn!("Couldn't reply dry run of {}", cmd_dbg));
+            }
+        });
+    }
+
+    /// Dry runs the command with a handler of its own whose store handle
+    /// loads the models as they were at `at`, the peers it reaches are
+    /// queried in their present state.
+    fn dry_run_at(
+        &self,
+        ctx: &Context<EntityMsg<E>>,
+        at: DateTime<Utc>,
+        cmd: E::Cmd,
+        sender: Sender,
+    ) {
+        let store = self.store.as_ref().unwrap().clone();
+        let mut es = self.new_handler(ctx, store, Some(at));
+        ctx.system.exec.spawn_ok(async move {
+            debug!("dry running command {:?} as of {}", cmd, at);
+            let result = es.handle_command(cmd).await.map_err(CommandError::Rejected);
+            if let Some(sender) = sender {
+                let _ = sender
+                    .try_tell(result, None)
+                    .map_err(|_| warn!("Couldn't reply dry run as of {}", at));
+            }
+        });
+    }
+
+    /// Runs the command handler waiting for the store to confirm the commit,
+    /// when it conflicts with another change the handler is run again so it
+    /// can decide based on the latest state.
+    fn run_retrying(
+        &self,
+        ctx: &Context<EntityMsg<E>>,
+        cmd: E::Cmd,
+        attempts: u32,
+        sender: Sender,
+    ) {
+        let sys = ctx.system.clone();
+        let store = self.store.as_ref().unwrap().clone();
+        let es = self.es.clone().unwrap();
+        let cache = self.cache.clone();
+        let effect_sink = self.effect_sink.clone();
+        let queued = QueuedCommand::new(&self.queued);
+        ctx.system.exec.spawn_ok(async move {
+            let cmd_dbg = format!("{:?}", cmd);
+            let mut result = Err(CommandError::Commit(CommitError::ConflictExhausted(
+                attempts,
+            )));
+            for attempt in 1..=attempts {
+                debug!("processing command {} (attempt {})", cmd_dbg, attempt);
+                let mut outcome = match es.lock().await.handle_command(cmd.clone()).await {
+                    Ok(outcome) => outcome,
+                    Err(err) => {
+                        result = Err(CommandError::Rejected(err));
+                        break;
+                    }
+                };
+                let effects = outcome.divert_effects(&effect_sink);
+                let committed: CommitResult<()> = match outcome.store_msg() {
+                    Ok(msg) => ask(&sys, store.clone().into(), msg)
+                        .await
+                        .unwrap_or(Err(CommitError::Unavailable)),
+                    Err(err) => Err(err),
+                };
+                match committed {
+                    Ok(_) => {
+                        if let Some(sink) = &effect_sink {
+                            sink.capture(effects);
+                        }
+                        outcome.apply_to(&cache);
+                        result = Ok(outcome);
+                        break;
+                    }
+                    Err(CommitError::Conflict { .. }) => continue,
+                    Err(err) => {
+                        result = Err(CommandError::Commit(err));
+                        break;
+                    }
+                }
+            }
+
+            drop(queued);
+            if let Some(sender) = sender {
+                let _ = sender
+                    .try_tell(result, None)
+                    .map_err(|_| warn!("Couldn't signal completion of {}", cmd_dbg));
+            }
+        });
     }
 }
 
-impl<E, S> Receive<Query> for Entity<E, S>
+impl<E, S> Receive<Query<<E::Model as Model>::Id>> for Entity<E, S>
 where
     E: ES,
     S: CommitStore<E::Model>,
 {
-    type Msg = CQRS<E::Cmd>;
-    fn receive(&mut self, _ctx: &Context<Self::Msg>, q: Query, sender: Sender) {
+    type Msg = EntityMsg<E>;
+    fn receive(
+        &mut self,
+        ctx: &Context<Self::Msg>,
+        q: Query<<E::Model as Model>::Id>,
+        sender: Sender,
+    ) {
+        let now = self.now();
         match q {
-            Query::One(id) => self.store.as_ref().unwrap().tell((id, Utc::now()), sender),
-            Query::All => self.store.as_ref().unwrap().tell(Utc::now(), sender),
+            Query::One(id) => match self.cache.clone() {
+                Some(cache) => self.query_cached(ctx, cache, id, sender),
+                None => self.store.as_ref().unwrap().tell((id, now), sender),
+            },
+            Query::All => self.store.as_ref().unwrap().tell(now, sender),
+            Query::AllCancellable(cancel) => {
+                self.store.as_ref().unwrap().tell((now, cancel), sender)
+            }
+            Query::AllLimited(limit) => self
+                .store
+                .as_ref()
+                .unwrap()
+                .tell((now, Limit(limit)), sender),
+            Query::AllPaged { offset, limit } => {
+                let page = SnapshotPage {
+                    until: now,
+                    offset,
+                    limit,
+                };
+                self.store.as_ref().unwrap().tell(page, sender)
+            }
+            Query::AllSortedBy(cmp) => match cmp.of::<E::Model>() {
+                Some(cmp) => self.store.as_ref().unwrap().tell((now, cmp), sender),
+                None => {
+                    warn!("Can't sort {} with a comparison of other model", E::NAME);
+                    if let Some(sender) = sender {
+                        let _ = sender.try_tell(Vec::<E::Model>::new(), None);
+                    }
+                }
+            },
+            Query::History(id) => self.store.as_ref().unwrap().tell(History(id), sender),
+            Query::CommitsSince { since, kinds } => self
+                .store
+                .as_ref()
+                .unwrap()
+                .tell(CommitsSince { since, kinds }, sender),
+            Query::OneAtTag(id, tag) => {
+                self.store.as_ref().unwrap().tell(AtTag { id, tag }, sender)
+            }
+            Query::OneWithHistory { id, last_n } => self
+                .store
+                .as_ref()
+                .unwrap()
+                .tell(RecentHistory { id, last_n }, sender),
         }
     }
 }
 
 #[derive(Clone, Debug)]
-pub enum CQRS<C> {
+pub enum CQRS<C, Id = EntityId> {
+    /// A command whose sender expects the id of the entity it was handled by
+    /// as reply, or the `ManagerError` telling why it didn't go through.
     Cmd(C),
-    Query(Query),
+    /// A command whose sender expects the full `CommandOutcome` as reply, or
+    /// the `CommandError` telling why it didn't go through.
+    CmdOutcome(C),
+    /// A command whose sender expects a `CommandResult` as reply, to learn
+    /// about the handler rejecting it with its own error.
+    TryCmd(C),
+    /// A command retried up to the given attempts when its commit conflicts,
+    /// replies with the `CommandOutcome` of the last attempt or the
+    /// `CommandError` of why it didn't go through, `ConflictExhausted` when
+    /// every attempt conflicted.
+    Retry(C, u32),
+    /// Runs the command handler without persisting the resulting commits,
+    /// replies with the `CommandOutcome` or the `CommandError` of why there's
+    /// none.
+    DryRun(C),
+    /// Like `DryRun` but the handler loads the models of its entity as they
+    /// were at the given moment, to reproduce what a past command did.
+    DryRunAt(DateTime<Utc>, C),
+    /// A command issued as part of a transaction, its commit is tagged with it
+    Transaction(Uuid, C),
+    /// A command issued as part of a chain of commands, its commits carry the
+    /// correlation id unless the handler correlated them already.
+    Correlated(EntityId, C),
+    Query(Query<Id>),
+    /// Replies with the number of commands the entity received and hasn't
+    /// replied yet, both the ones waiting for the handler and the ones whose
+    /// commits are being persisted.
+    QueueDepth,
+    /// Waits for pending commits and checkpoints the store of the entity,
+    /// replies with the `CommitResult` of the checkpoint.
+    Checkpoint(DateTime<Utc>),
+    /// Brings the entity back to the state it had at the given moment keeping
+    /// its history, replies with the `CommitResult` of the compensating commit.
+    Revert(Id, DateTime<Utc>),
+    /// Drops the model of an entity kept in memory, see `Hydration::Cached`
+    Invalidate(Id),
 }
-impl<C> From<Query> for CQRS<C> {
-    fn from(q: Query) -> Self {
+impl<C, Id> From<Query<Id>> for CQRS<C, Id> {
+    fn from(q: Query<Id>) -> Self {
         CQRS::Query(q)
     }
 }
 
 #[derive(Clone, Debug)]
-pub enum Query {
+pub enum Query<Id = EntityId> {
+    /// Every entity as of the moment the query is received, see `ReadConsistency`
     All,
-    One(EntityId),
+    /// Like `All` but stops loading entities once the token is cancelled
+    AllCancellable(CancelToken),
+    /// Like `All` but sorted with a comparison of the entity's model
+    AllSortedBy(Comparator),
+    /// Like `All` but replies `Limited` to at most the given number of entities
+    AllLimited(usize),
+    /// A page of `All` of at most `limit` entities after skipping `offset`,
+    /// replies `Limited` truncated when there are more pages.
+    AllPaged {
+        offset: usize,
+        limit: usize,
+    },
+    One(Id),
+    /// The commits of an entity in the order they were made, none when the
+    /// entity doesn't exist
+    History(Id),
+    /// The current state of an entity along with its `last_n` commits
+    OneWithHistory {
+        id: Id,
+        last_n: usize,
+    },
+    /// The state of an entity at the moment named by a tag of the store
+    OneAtTag(Id, String),
+    /// The commits of every entity made since a moment whose event is of one
+    /// of the given kinds, entity after entity. Replies with a `CommitResult`
+    /// as reading them can fail half way.
+    CommitsSince {
+        since: DateTime<Utc>,
+        kinds: Vec<EventKind>,
+    },
 }
 
-// NOTE: work around to get entity name for commands
-// TODO derive from implementor struct name
+/// Messages handled by the entity actor of `E`
+pub type EntityMsg<E> = CQRS<<E as ES>::Cmd, <<E as ES>::Model as Model>::Id>;
+
+/// Names an entity, commands implement it too with the name of the entity
+/// that handles them. `#[derive(EntityName)]` names types after themselves.
 pub trait EntityName {
     const NAME: &'static str;
 }
@@ -149,16 +1100,18 @@ pub trait EntityName {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::store::tests::{Op, TestCount};
-    use crate::store::MemStore;
+    use crate::store::tests::{HookedStore, Op, TestCount};
+    use crate::store::{Limited, MemLimit, MemPolicy, MemStore};
     use crate::{macros::*, Event};
     use futures::executor::block_on;
+    use futures::future::{ready, FutureExt};
+    use futures::stream::StreamExt;
     use riker_patterns::ask::ask;
+    use std::sync::atomic::{AtomicBool, Ordering::SeqCst};
 
     #[derive(EntityName, Debug)]
     struct Test {
-        sys: ActorSystem,
-        entity: ActorRef<CQRS<<Self as ES>::Cmd>>,
+        store: Option<StoreHandle<TestCount>>,
         _foo: String,
     }
     #[async_trait]
@@ -168,34 +1121,553 @@ mod tests {
         type Cmd = TestCmd;
         type Error = String;
 
-        fn new(cx: &Context<CQRS<Self::Cmd>>, (num, txt): Self::Args) -> Self {
+        fn new(_cx: &Context<CQRS<Self::Cmd>>, (num, txt): Self::Args) -> Self {
             Test {
                 _foo: format!("{}{}", num, txt),
-                sys: cx.system.clone(),
-                entity: cx.myself(),
+                store: None,
             }
         }
 
+        fn with_store(&mut self, store: StoreHandle<TestCount>) {
+            self.store = Some(store);
+        }
+
+        fn store(&self) -> Option<&StoreHandle<TestCount>> {
+            self.store.as_ref()
+        }
+
         async fn handle_command(&mut self, cmd: Self::Cmd) -> Result<Self> {
             let event = match cmd {
                 TestCmd::Create42 => Event::Create(TestCount::new(42)),
                 TestCmd::Create99 => Event::Create(TestCount::new(99)),
+                TestCmd::Create(count) => {
+                    let outcome = CommandOutcome::from(Event::Create(TestCount::new(count)));
+                    if count < 10 {
+                        return Ok(outcome.with_warning("count is low"));
+                    }
+                    return Ok(outcome);
+                }
+                TestCmd::CreateWith(count, adds) => {
+                    let count = TestCount::new(count);
+                    let id = count.id();
+                    return Ok(adds
+                        .into_iter()
+                        .fold(CommandOutcome::from(Event::Create(count)), |outcome, n| {
+                            outcome.with_change(Event::Change(id, Op::Add(n)))
+                        }));
+                }
+                TestCmd::CreateWithForeignChange => {
+                    let outcome = CommandOutcome::from(Event::Create(TestCount::new(1)));
+                    return Ok(outcome.with_change(Event::Change(EntityId::new(), Op::Add(1))));
+                }
+                TestCmd::CreateBorrowing(from, count) => {
+                    let outcome = CommandOutcome::from(Event::Create(TestCount::new(count)));
+                    return Ok(outcome.with_effect(Event::Change(from, Op::Sub(count))));
+                }
+                TestCmd::Transfer(from, to, n) => {
+                    let outcome = CommandOutcome::from(Event::Change(from, Op::Sub(n)));
+                    return Ok(outcome.with_effect(Event::Change(to, Op::Add(n))));
+                }
+                TestCmd::AddEach(id, adds) => {
+                    let mut adds = adds.into_iter().map(|n| Event::Change(id, Op::Add(n)));
+                    let first = adds.next().ok_or("Nothing to add")?;
+                    return Ok(adds.fold(first.into(), CommandOutcome::with_change));
+                }
                 TestCmd::Double(id) => {
-                    let res: Option<TestCount> = ask(&self.sys, &self.entity, Query::One(id)).await;
-                    let res = res.ok_or("Not found")?;
+                    let res = self.load(id).await.ok_or("Not found")?;
                     Event::Change(res.id(), Op::Add(res.count))
                 }
             };
             Ok(event.into())
         }
     }
+
+    type Outcome = std::result::Result<CommandOutcome<TestCount>, CommandError<String>>;
+
+    /// Sends a command waiting for the id of the entity that handled it
+    fn command(sys: &ActorSystem, entity: &ActorRef<EntityMsg<Test>>, cmd: TestCmd) -> EntityId {
+        let res: std::result::Result<EntityId, ManagerError> =
+            block_on(ask(sys, entity, CQRS::Cmd(cmd)));
+        res.unwrap()
+    }
+
     #[derive(Clone, Debug)]
     enum TestCmd {
         Create42,
         Create99,
+        Create(i16),
+        CreateWith(i16, Vec<i16>),
+        CreateWithForeignChange,
+        CreateBorrowing(EntityId, i16),
+        Transfer(EntityId, EntityId, i16),
+        AddEach(EntityId, Vec<i16>),
         Double(EntityId),
     }
 
+    #[test]
+    fn name_stores_of_same_model() {
+        let sys = ActorSystem::new().unwrap();
+        let spawn = |name: &str, store: &str, backend| {
+            sys.actor_of_args::<Entity<Test, MemStore<_>>, _>(
+                name,
+                (
+                    backend,
+                    (1, "1".into()),
+                    EntityConfig::default().store_name(store),
+                ),
+            )
+            .unwrap()
+        };
+        let (eu_backend, us_backend) = (MemStore::new(), MemStore::new());
+        let eu = spawn("counts-eu", "eu_counts", eu_backend.clone());
+        let us = spawn("counts-us", "us_counts", us_backend.clone());
+
+        command(&sys, &eu, TestCmd::Create42);
+        command(&sys, &us, TestCmd::Create99);
+        let eu_counts: Vec<TestCount> = block_on(ask(&sys, &eu, Query::All));
+        let us_counts: Vec<TestCount> = block_on(ask(&sys, &us, Query::All));
+        assert_eq!(eu_counts.len(), 1);
+        assert_eq!(eu_counts[0].count, 42);
+        assert_eq!(us_counts.len(), 1);
+        assert_eq!(us_counts[0].count, 99);
+
+        let stores: Vec<_> = eu.children().chain(us.children()).collect();
+        let names: Vec<_> = stores.iter().map(|s| s.name()).collect();
+        assert_eq!(names, vec!["eu_counts", "us_counts"]);
+    }
+
+    #[test]
+    fn create_with_changes_atomically() {
+        let sys = ActorSystem::new().unwrap();
+        let backend = MemStore::new();
+        let entity = sys
+            .actor_of_args::<Entity<Test, MemStore<_>>, _>(
+                "counts",
+                (backend.clone(), (1, "1".into())),
+            )
+            .unwrap();
+
+        let cmd = CQRS::Retry(TestCmd::CreateWith(1, vec![2, 3, 4]), 1);
+        let res: Outcome = block_on(ask(&sys, &entity, cmd));
+        let id = res.unwrap().id;
+        let count: Option<TestCount> = block_on(ask(&sys, &entity, Query::One(id)));
+        assert_eq!(count.unwrap().count, 10);
+        let commits: Vec<Commit<TestCount>> = block_on(ask(&sys, &entity, Query::History(id)));
+        assert_eq!(commits.len(), 4);
+
+        let cmd = CQRS::Retry(TestCmd::CreateWithForeignChange, 1);
+        let res: Outcome = block_on(ask(&sys, &entity, cmd));
+        assert!(matches!(
+            res,
+            Err(CommandError::Commit(CommitError::InvalidHistory(_)))
+        ));
+        let counts: Vec<TestCount> = block_on(ask(&sys, &entity, Query::All));
+        assert_eq!(counts.len(), 1);
+    }
+
+    #[test]
+    fn command_with_effects_on_other_entity() {
+        let sys = ActorSystem::new().unwrap();
+        let entity = sys
+            .actor_of_args::<Entity<Test, MemStore<_>>, _>(
+                "counts",
+                (MemStore::new(), (1, "1".into())),
+            )
+            .unwrap();
+        let run = |cmd| {
+            let res: Outcome = block_on(ask(&sys, &entity, CQRS::Retry(cmd, 1)));
+            res
+        };
+        let count = |id| {
+            let count: Option<TestCount> = block_on(ask(&sys, &entity, Query::One(id)));
+            count.unwrap().count
+        };
+
+        let lender = run(TestCmd::Create(10)).unwrap().id;
+        let borrower = run(TestCmd::CreateBorrowing(lender, 3)).unwrap().id;
+        assert_eq!(count(lender), 7);
+        assert_eq!(count(borrower), 3);
+
+        let res = run(TestCmd::CreateBorrowing(EntityId::new(), 3));
+        assert!(matches!(
+            res,
+            Err(CommandError::Commit(CommitError::CantChange))
+        ));
+        let cmd = CQRS::Cmd(TestCmd::CreateBorrowing(EntityId::new(), 3));
+        let res: std::result::Result<EntityId, ManagerError> = block_on(ask(&sys, &entity, cmd));
+        assert_eq!(res, Err(ManagerError::Commit(CommitError::CantChange)));
+        let counts: Vec<TestCount> = block_on(ask(&sys, &entity, Query::All));
+        assert_eq!(counts.len(), 2);
+    }
+
+    #[test]
+    fn capture_effects_in_sink() {
+        let sys = ActorSystem::new().unwrap();
+        let sink = EffectSink::new();
+        let entity = sys
+            .actor_of_args::<Entity<Test, MemStore<_>>, _>(
+                "counts",
+                (
+                    MemStore::new(),
+                    (1, "1".into()),
+                    EntityConfig::default().effect_sink(sink.clone()),
+                ),
+            )
+            .unwrap();
+        let count = |id| {
+            let count: Option<TestCount> = block_on(ask(&sys, &entity, Query::One(id)));
+            count.unwrap().count
+        };
+
+        let from = command(&sys, &entity, TestCmd::Create(10));
+        let to = command(&sys, &entity, TestCmd::Create(10));
+        command(&sys, &entity, TestCmd::Transfer(from, to, 3));
+        let res: Outcome = block_on(ask(
+            &sys,
+            &entity,
+            CQRS::Retry(TestCmd::Transfer(from, to, 2), 1),
+        ));
+        assert!(res.unwrap().effects.is_empty());
+
+        let effects = sink.effects();
+        assert_eq!(effects.len(), 2);
+        assert!(effects.iter().all(|c| c.entity_id() == to));
+        assert!(matches!(effects[0].change(), Some(Op::Add(3))));
+        assert!(matches!(effects[1].change(), Some(Op::Add(2))));
+        assert_eq!(count(from), 5);
+        assert_eq!(count(to), 10);
+    }
+
+    #[test]
+    fn command_with_several_changes() {
+        let sys = ActorSystem::new().unwrap();
+        let backend = MemStore::new();
+        let entity = sys
+            .actor_of_args::<Entity<Test, MemStore<_>>, _>(
+                "counts",
+                (backend.clone(), (1, "1".into())),
+            )
+            .unwrap();
+        let run = |cmd| {
+            let res: Outcome = block_on(ask(&sys, &entity, CQRS::Retry(cmd, 1)));
+            res
+        };
+        let count = |id| {
+            let count: Option<TestCount> = block_on(ask(&sys, &entity, Query::One(id)));
+            count.unwrap().count
+        };
+
+        let from = run(TestCmd::Create(10)).unwrap().id;
+        let to = run(TestCmd::Create(10)).unwrap().id;
+        run(TestCmd::AddEach(from, vec![1, 2, 3])).unwrap();
+        assert_eq!(count(from), 16);
+        assert_eq!(
+            block_on(backend.change_list(from).collect::<Vec<_>>()).len(),
+            4
+        );
+
+        run(TestCmd::Transfer(from, to, 6)).unwrap();
+        assert_eq!((count(from), count(to)), (10, 16));
+        // nothing is persisted when part of the outcome can't be
+        let res = run(TestCmd::Transfer(from, EntityId::new(), 6));
+        assert!(matches!(
+            res,
+            Err(CommandError::Commit(CommitError::CantChange))
+        ));
+        assert_eq!(count(from), 10);
+    }
+
+    #[test]
+    fn reject_commands_on_loaded_state() {
+        let sys = ActorSystem::new().unwrap();
+        let entity = sys
+            .actor_of_args::<Entity<Test, _>, _>("counts", (MemStore::new(), (1, "1".into())))
+            .unwrap();
+
+        let unknown = EntityId::new();
+        let res: CommandResult<Test> =
+            block_on(ask(&sys, &entity, CQRS::TryCmd(TestCmd::Double(unknown))));
+        assert_eq!(res, Err(CommandError::Rejected("Not found".into())));
+        let res: std::result::Result<EntityId, ManagerError> =
+            block_on(ask(&sys, &entity, CQRS::Cmd(TestCmd::Double(unknown))));
+        assert_eq!(res, Err(ManagerError::Rejected("Not found".into())));
+
+        let id = command(&sys, &entity, TestCmd::Create(21));
+        let res: CommandResult<Test> =
+            block_on(ask(&sys, &entity, CQRS::TryCmd(TestCmd::Double(id))));
+        assert_eq!(res, Ok(id));
+        let count: Option<TestCount> = block_on(ask(&sys, &entity, Query::One(id)));
+        assert_eq!(count.unwrap().count, 42);
+    }
+
+    #[test]
+    fn reply_failed_commits() {
+        let sys = ActorSystem::new().unwrap();
+        let full = MemStore::with_limit(MemLimit {
+            max_bytes: 0,
+            policy: MemPolicy::Reject,
+        });
+        let entity = sys
+            .actor_of_args::<Entity<Test, MemStore<_>>, _>("counts", (full, (1, "1".into())))
+            .unwrap();
+        let full = CommitError::MemoryLimit(0);
+
+        let res: std::result::Result<EntityId, ManagerError> =
+            block_on(ask(&sys, &entity, CQRS::Cmd(TestCmd::Create42)));
+        assert_eq!(res, Err(ManagerError::Commit(full.clone())));
+        let res: CommandResult<Test> =
+            block_on(ask(&sys, &entity, CQRS::TryCmd(TestCmd::Create42)));
+        assert_eq!(res, Err(CommandError::Commit(full.clone())));
+        let res: std::result::Result<CommandOutcome<TestCount>, CommandError<String>> =
+            block_on(ask(&sys, &entity, CQRS::CmdOutcome(TestCmd::Create42)));
+        assert!(matches!(res, Err(CommandError::Commit(err)) if err == full));
+        let counts: Vec<TestCount> = block_on(ask(&sys, &entity, Query::All));
+        assert!(counts.is_empty());
+    }
+
+    #[test]
+    fn tell_time_with_clock() {
+        let start = Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap();
+        let clock = crate::MockClock::new(start);
+        let sys = ActorSystem::new().unwrap();
+        let backend = MemStore::new();
+        let shared: SharedClock = Arc::new(clock.clone());
+        let entity = sys
+            .actor_of_args::<Entity<Test, MemStore<_>>, _>(
+                "counts",
+                (
+                    backend.clone(),
+                    (1, "1".into()),
+                    EntityConfig::default().clock(shared),
+                ),
+            )
+            .unwrap();
+        let count = |id| {
+            let count: Option<TestCount> = block_on(ask(&sys, &entity, Query::One(id)));
+            count.unwrap().count
+        };
+
+        let id = command(&sys, &entity, TestCmd::Create(10));
+        clock.advance(chrono::Duration::hours(1));
+        command(&sys, &entity, TestCmd::Double(id));
+        clock.advance(chrono::Duration::hours(1));
+        command(&sys, &entity, TestCmd::Double(id));
+        assert_eq!(count(id), 40);
+
+        let traveler = block_on(backend.get(id)).unwrap();
+        assert_eq!(traveler.created(), Some(start));
+        let at = |minutes| {
+            let when = start + chrono::Duration::minutes(minutes);
+            block_on(async { backend.get(id).await?.travel_to(when).await })
+                .unwrap()
+                .count
+        };
+        assert_eq!(at(59), 10);
+        assert_eq!(at(60), 20);
+        assert_eq!(at(120), 40);
+        // queries are answered as of the moment the clock tells
+        clock.set(start + chrono::Duration::minutes(90));
+        assert_eq!(count(id), 20);
+    }
+
+    #[test]
+    fn keep_models_in_memory() {
+        let sys = ActorSystem::new().unwrap();
+        let backend = MemStore::new();
+        let entity = sys
+            .actor_of_args::<Entity<Test, MemStore<_>>, _>(
+                "counts",
+                (
+                    backend.clone(),
+                    (1, "1".into()),
+                    EntityConfig::default().hydration(Hydration::Cached),
+                ),
+            )
+            .unwrap();
+        let count = |id| {
+            let count: Option<TestCount> = block_on(ask(&sys, &entity, Query::One(id)));
+            count.unwrap().count
+        };
+
+        let id = command(&sys, &entity, TestCmd::Create(10));
+        command(&sys, &entity, TestCmd::Double(id));
+        assert_eq!(count(id), 20);
+        let cmd = CQRS::Retry(TestCmd::AddEach(id, vec![1, 2]), 1);
+        let res: Outcome = block_on(ask(&sys, &entity, cmd));
+        res.unwrap();
+        assert_eq!(count(id), 23);
+
+        // commits made elsewhere are only seen once invalidated
+        let before = Utc::now();
+        block_on(backend.commit(Event::Change(id, Op::Add(7)).into())).unwrap();
+        assert_eq!(count(id), 23);
+        entity.tell(CQRS::Invalidate(id), None);
+        assert_eq!(count(id), 30);
+
+        let res: CommitResult<Commit<TestCount>> =
+            block_on(ask(&sys, &entity, CQRS::Revert(id, before)));
+        res.unwrap();
+        assert_eq!(count(id), 23);
+        command(&sys, &entity, TestCmd::Double(id));
+        assert_eq!(count(id), 46);
+    }
+
+    #[test]
+    fn hydrate_on_first_access() {
+        let sys = ActorSystem::new().unwrap();
+        let backend = MemStore::new();
+        let count = TestCount::new(5);
+        let id = count.id();
+        block_on(backend.commit(Event::Create(count).into())).unwrap();
+        let spawn = |name, hydration| {
+            let config = EntityConfig::default().hydration(hydration);
+            sys.actor_of_args::<Entity<Test, MemStore<_>>, _>(
+                name,
+                (backend.clone(), (1, "1".into()), config),
+            )
+            .unwrap()
+        };
+        let eager = spawn("eager", Hydration::Eager);
+        let cached = spawn("cached", Hydration::Cached);
+        let lazy = spawn("lazy", Hydration::Lazy);
+        let count = |entity: &ActorRef<EntityMsg<Test>>| {
+            let count: Option<TestCount> = block_on(ask(&sys, entity, Query::One(id)));
+            count.map(|c| c.count)
+        };
+
+        // only lazy entities answer before the model is loaded
+        assert_eq!(count(&eager), Some(5));
+        assert_eq!(count(&cached), Some(5));
+        assert_eq!(count(&lazy), None);
+        assert_eq!(count(&lazy), Some(5));
+
+        // commits made elsewhere are seen right away without models in memory
+        block_on(backend.commit(Event::Change(id, Op::Add(1)).into())).unwrap();
+        assert_eq!(count(&eager), Some(6));
+        assert_eq!(count(&cached), Some(5));
+        assert_eq!(count(&lazy), Some(5));
+    }
+
+    #[test]
+    fn query_as_of_tag() {
+        let sys = ActorSystem::new().unwrap();
+        let backend = MemStore::new();
+        let entity = sys
+            .actor_of_args::<Entity<Test, MemStore<_>>, _>(
+                "counts",
+                (backend.clone(), (1, "1".into())),
+            )
+            .unwrap();
+
+        let cmd = CQRS::Retry(TestCmd::Create(5), 1);
+        let res: Outcome = block_on(ask(&sys, &entity, cmd));
+        let id = res.unwrap().id;
+        block_on(backend.tag("release".into(), Utc::now())).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        block_on(backend.commit(Event::Change(id, Op::Add(10)).into())).unwrap();
+
+        let tagged: Option<TestCount> =
+            block_on(ask(&sys, &entity, Query::OneAtTag(id, "release".into())));
+        assert_eq!(tagged.unwrap().count, 5);
+        let present: Option<TestCount> = block_on(ask(&sys, &entity, Query::One(id)));
+        assert_eq!(present.unwrap().count, 15);
+        let unknown: Option<TestCount> =
+            block_on(ask(&sys, &entity, Query::OneAtTag(id, "nope".into())));
+        assert!(unknown.is_none());
+    }
+
+    #[test]
+    fn query_state_with_recent_changes() {
+        let sys = ActorSystem::new().unwrap();
+        let entity = sys
+            .actor_of_args::<Entity<Test, MemStore<_>>, _>(
+                "counts",
+                (MemStore::new(), (1, "1".into())),
+            )
+            .unwrap();
+        let cmd = CQRS::Retry(TestCmd::CreateWith(1, vec![2, 3, 4]), 1);
+        let res: Outcome = block_on(ask(&sys, &entity, cmd));
+        let id = res.unwrap().id;
+
+        let q = Query::OneWithHistory { id, last_n: 2 };
+        let res: Option<(TestCount, Vec<Commit<TestCount>>)> = block_on(ask(&sys, &entity, q));
+        let (count, recent) = res.unwrap();
+        assert_eq!(count.count, 10);
+        let recent: Vec<_> = recent.iter().filter_map(|c| c.change()).collect();
+        assert!(matches!(recent[..], [Op::Add(3), Op::Add(4)]));
+
+        let q = Query::OneWithHistory { id, last_n: 10 };
+        let res: Option<(TestCount, Vec<Commit<TestCount>>)> = block_on(ask(&sys, &entity, q));
+        assert_eq!(res.unwrap().1.len(), 4);
+        let q = Query::OneWithHistory {
+            id: EntityId::new(),
+            last_n: 1,
+        };
+        let res: Option<(TestCount, Vec<Commit<TestCount>>)> = block_on(ask(&sys, &entity, q));
+        assert!(res.is_none());
+    }
+
+    #[test]
+    fn query_sorted_list() {
+        let sys = ActorSystem::new().unwrap();
+        let entity = sys
+            .actor_of_args::<Entity<Test, MemStore<_>>, _>(
+                "counts",
+                (MemStore::new(), (1, "1".into())),
+            )
+            .unwrap();
+        for n in [30, 10, 50, 20, 40].iter() {
+            let cmd = CQRS::Retry(TestCmd::Create(*n), 1);
+            let res: Outcome = block_on(ask(&sys, &entity, cmd));
+            res.unwrap();
+        }
+
+        let by_count: fn(&TestCount, &TestCount) -> std::cmp::Ordering =
+            |a, b| b.count.cmp(&a.count);
+        let q = Query::AllSortedBy(Comparator::new(by_count));
+        let counts: Vec<TestCount> = block_on(ask(&sys, &entity, q));
+        let counts: Vec<_> = counts.iter().map(|c| c.count).collect();
+        assert_eq!(counts, vec![50, 40, 30, 20, 10]);
+    }
+
+    #[test]
+    fn query_limited_list() {
+        let sys = ActorSystem::new().unwrap();
+        let entity = sys
+            .actor_of_args::<Entity<Test, MemStore<_>>, _>(
+                "counts",
+                (MemStore::new(), (1, "1".into())),
+            )
+            .unwrap();
+        for n in 0..5 {
+            let cmd = CQRS::Retry(TestCmd::Create(n), 1);
+            let res: Outcome = block_on(ask(&sys, &entity, cmd));
+            res.unwrap();
+        }
+
+        let list: Limited<TestCount> = block_on(ask(&sys, &entity, Query::AllLimited(3)));
+        assert_eq!(list.entities.len(), 3);
+        assert!(list.truncated);
+        let list: Limited<TestCount> = block_on(ask(&sys, &entity, Query::AllLimited(5)));
+        assert_eq!(list.entities.len(), 5);
+        assert!(!list.truncated);
+    }
+
+    #[test]
+    fn derive_entity_name() {
+        #[derive(EntityName)]
+        struct HTTPRequestLog2;
+        #[derive(EntityName)]
+        #[entity(name = "test")]
+        #[allow(dead_code)]
+        enum TestCmd2 {
+            Create,
+        }
+        assert_eq!(Test::NAME, "test");
+        assert_eq!(HTTPRequestLog2::NAME, "http_request_log2");
+        assert_eq!(TestCmd2::NAME, Test::NAME);
+    }
+
     #[test]
     fn command_n_query() {
         let sys = ActorSystem::new().unwrap();
@@ -206,8 +1678,8 @@ mod tests {
             )
             .unwrap();
 
-        let _: EntityId = block_on(ask(&sys, &entity, CQRS::Cmd(TestCmd::Create42)));
-        let _: EntityId = block_on(ask(&sys, &entity, CQRS::Cmd(TestCmd::Create99)));
+        command(&sys, &entity, TestCmd::Create42);
+        command(&sys, &entity, TestCmd::Create99);
         let counts: Vec<TestCount> = block_on(ask(&sys, &entity, Query::All));
 
         assert_eq!(counts.len(), 2);
@@ -217,8 +1689,195 @@ mod tests {
         assert!(count99.is_some());
 
         let id = count42.unwrap().id();
-        let _: EntityId = block_on(ask(&sys, &entity, CQRS::Cmd(TestCmd::Double(id))));
+        command(&sys, &entity, TestCmd::Double(id));
         let result: Option<TestCount> = block_on(ask(&sys, &entity, Query::One(id)));
         assert_eq!(result.unwrap().count, 84);
     }
+
+    #[test]
+    fn report_queue_depth() {
+        let sys = ActorSystem::new().unwrap();
+        let (open, gate) = futures::channel::oneshot::channel::<()>();
+        let gate = gate.shared();
+        let backend = HookedStore::new(MemStore::new())
+            .before_commit(move || gate.clone().map(|_| Ok(())).boxed());
+        let entity = sys
+            .actor_of_args::<Entity<Test, HookedStore>, _>("counts", (backend, (1, "1".into())))
+            .unwrap();
+        let depth = || {
+            let depth: usize = block_on(ask(&sys, &entity, CQRS::QueueDepth));
+            depth
+        };
+        assert_eq!(depth(), 0);
+
+        // commands are queued until their commits go through
+        let created: Vec<
+            futures::future::RemoteHandle<std::result::Result<EntityId, ManagerError>>,
+        > = (0..3)
+            .map(|_| ask(&sys, &entity, CQRS::Cmd(TestCmd::Create42)))
+            .collect();
+        assert_eq!(depth(), 3);
+
+        open.send(()).unwrap();
+        let ids = block_on(futures::future::join_all(created));
+        assert!(ids.iter().all(|id| id.is_ok()));
+        assert_eq!(depth(), 0);
+    }
+
+    #[test]
+    fn query_itself_while_commands_queue() {
+        let sys = ActorSystem::new().unwrap();
+        let entity = sys
+            .actor_of_args::<Entity<Test, MemStore<_>>, _>(
+                "counts",
+                (MemStore::new(), (42, "42".into())),
+            )
+            .unwrap();
+        let id = command(&sys, &entity, TestCmd::Create(1));
+
+        let (done, finished) = std::sync::mpsc::channel();
+        let doubles = (0..8).map(|_| {
+            let res: futures::future::RemoteHandle<std::result::Result<EntityId, ManagerError>> =
+                ask(&sys, &entity, CQRS::Cmd(TestCmd::Double(id)));
+            res
+        });
+        let all = futures::future::join_all(doubles.collect::<Vec<_>>());
+        std::thread::spawn(move || done.send(block_on(all)));
+        let ids = finished
+            .recv_timeout(std::time::Duration::from_secs(5))
+            .expect("self queries shouldn't deadlock");
+        assert!(ids.iter().all(|double| *double == Ok(id)));
+    }
+
+    #[test]
+    fn command_with_warnings() {
+        let sys = ActorSystem::new().unwrap();
+        let entity = sys
+            .actor_of_args::<Entity<Test, MemStore<_>>, _>(
+                "counts",
+                (MemStore::new(), (42, "42".into())),
+            )
+            .unwrap();
+
+        let outcome = |cmd| {
+            let res: std::result::Result<CommandOutcome<TestCount>, CommandError<String>> =
+                block_on(ask(&sys, &entity, CQRS::CmdOutcome(cmd)));
+            res
+        };
+        let low = outcome(TestCmd::Create(3)).unwrap();
+        assert_eq!(low.warnings, vec!["count is low".to_string()]);
+        let outcome = outcome(TestCmd::Create(30)).unwrap();
+        assert!(outcome.warnings.is_empty());
+
+        let result: Option<TestCount> = block_on(ask(&sys, &entity, Query::One(outcome.id)));
+        assert_eq!(result.unwrap().count, 30);
+    }
+
+    /// Fails the first commit as if another change got there first
+    fn conflict_once() -> HookedStore {
+        let conflicted = Arc::new(AtomicBool::new(false));
+        HookedStore::new(MemStore::new()).before_commit(move || {
+            let res = match conflicted.swap(true, SeqCst) {
+                false => Err(CommitError::Conflict {
+                    expected: 0,
+                    actual: 1,
+                }),
+                true => Ok(()),
+            };
+            ready(res).boxed()
+        })
+    }
+
+    #[test]
+    fn retry_on_conflict() {
+        let sys = ActorSystem::new().unwrap();
+        let store = conflict_once();
+        let entity = sys
+            .actor_of_args::<Entity<Test, HookedStore>, _>("counts", (store, (42, "42".into())))
+            .unwrap();
+
+        let result: Outcome = block_on(ask(&sys, &entity, CQRS::Retry(TestCmd::Create42, 3)));
+        let id = result.unwrap().id;
+        let result: Option<TestCount> = block_on(ask(&sys, &entity, Query::One(id)));
+        assert_eq!(result.unwrap().count, 42);
+    }
+
+    #[test]
+    fn retries_exhausted() {
+        let sys = ActorSystem::new().unwrap();
+        let store = conflict_once();
+        let entity = sys
+            .actor_of_args::<Entity<Test, HookedStore>, _>("counts", (store, (42, "42".into())))
+            .unwrap();
+
+        let result: Outcome = block_on(ask(&sys, &entity, CQRS::Retry(TestCmd::Create42, 1)));
+        assert!(matches!(
+            result,
+            Err(CommandError::Commit(CommitError::ConflictExhausted(1)))
+        ));
+    }
+
+    #[test]
+    fn dry_run_command() {
+        let sys = ActorSystem::new().unwrap();
+        let entity = sys
+            .actor_of_args::<Entity<Test, MemStore<_>>, _>(
+                "counts",
+                (MemStore::new(), (42, "42".into())),
+            )
+            .unwrap();
+
+        let dry_run = |cmd| {
+            let result: std::result::Result<CommandOutcome<TestCount>, CommandError<String>> =
+                block_on(ask(&sys, &entity, CQRS::DryRun(cmd)));
+            result.unwrap()
+        };
+        let count = |id| {
+            let count: Option<TestCount> = block_on(ask(&sys, &entity, Query::One(id)));
+            count.unwrap().count
+        };
+
+        let id = command(&sys, &entity, TestCmd::Create42);
+        let outcome = dry_run(TestCmd::Double(id));
+        assert_eq!(outcome.id, id);
+        assert_eq!(outcome.commit.entity_id(), id);
+        assert!(matches!(outcome.commit.change(), Some(Op::Add(42))));
+        assert_eq!(count(id), 42);
+
+        // effects on other entities are replied but not persisted either
+        let to = command(&sys, &entity, TestCmd::Create99);
+        let outcome = dry_run(TestCmd::Transfer(id, to, 2));
+        assert!(matches!(outcome.commit.change(), Some(Op::Sub(2))));
+        assert_eq!(outcome.effects.len(), 1);
+        assert_eq!(outcome.effects[0].entity_id(), to);
+        assert!(matches!(outcome.effects[0].change(), Some(Op::Add(2))));
+        assert_eq!((count(id), count(to)), (42, 99));
+    }
+
+    #[test]
+    fn dry_run_command_in_the_past() {
+        let sys = ActorSystem::new().unwrap();
+        let backend = MemStore::new();
+        let entity = sys
+            .actor_of_args::<Entity<Test, MemStore<_>>, _>(
+                "counts",
+                (backend.clone(), (42, "42".into())),
+            )
+            .unwrap();
+
+        let id = command(&sys, &entity, TestCmd::Create42);
+        let before = Utc::now();
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        block_on(backend.commit(Event::Change(id, Op::Add(8)).into())).unwrap();
+
+        let result: std::result::Result<CommandOutcome<TestCount>, CommandError<String>> = block_on(
+            ask(&sys, &entity, CQRS::DryRunAt(before, TestCmd::Double(id))),
+        );
+        assert!(matches!(result.unwrap().commit.change(), Some(Op::Add(42))));
+        let result: std::result::Result<CommandOutcome<TestCount>, CommandError<String>> =
+            block_on(ask(&sys, &entity, CQRS::DryRun(TestCmd::Double(id))));
+        assert!(matches!(result.unwrap().commit.change(), Some(Op::Add(50))));
+        let history: Vec<Commit<TestCount>> = block_on(ask(&sys, &entity, Query::History(id)));
+        assert_eq!(history.len(), 2);
+    }
 }
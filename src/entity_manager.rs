@@ -1,19 +1,77 @@
-use crate::{CommitStore, Entity, EntityId, EntityName, Query, CQRS, ES};
+use crate::{
+    CancelToken, Codec, CommandError, CommandOutcome, CommandResult, Commit, CommitError,
+    CommitResult, CommitStore, Entity, EntityConfig, EntityId, EntityMsg, EntityName, Event,
+    EventKind, Limited, Model, Query, SystemBus, CQRS, ES,
+};
+use chrono::prelude::*;
 use futures::channel::oneshot::{channel, Sender as ChannelSender};
+use futures::future::{join_all, BoxFuture, FutureExt};
 use riker::actors::*;
+use serde::Serialize;
 use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex, RwLock};
+use thiserror::Error;
+use uuid::Uuid;
 
 pub struct Manager {
     sys: ActorSystem,
-    entities: HashMap<String, BasicActorRef>,
+    entities: Arc<RwLock<HashMap<String, BasicActorRef>>>,
+    registered: Vec<EntityInfo>,
+    lifecycle: SystemBus,
+    checkpoints: Vec<(String, CheckpointFn)>,
+}
+
+type CheckpointFn =
+    Box<dyn Fn(DateTime<Utc>) -> BoxFuture<'static, CommitResult<()>> + Send + Sync>;
+
+/// How `Manager::export` writes commits
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ExportFormat {
+    /// One JSON encoded commit per line
+    JsonLines,
+    /// A header and a row per commit with its metadata, the model or change
+    /// being JSON encoded in the `data` column
+    Csv,
+}
+
+/// Why the manager couldn't deliver a message to an entity
+#[derive(Error, Clone, Debug, Eq, PartialEq)]
+pub enum ManagerError {
+    #[error("No entity is registered as {0}")]
+    UnknownEntity(String),
+    /// The handler of the entity rejected the command with the error as it's
+    /// displayed, `try_command` replies the error itself.
+    #[error("Command was rejected: {0}")]
+    Rejected(String),
+    /// The store of the entity failed, e.g. persisting the outcome of a
+    /// command
+    #[error(transparent)]
+    Commit(CommitError),
+    /// The entity stopped, or the actor waiting for it couldn't be started,
+    /// before it replied
+    #[error("Entity stopped before replying")]
+    Canceled,
+}
+
+/// Describes an entity type registered in the manager
+#[derive(Clone, Debug)]
+pub struct EntityInfo {
+    pub name: String,
+    pub schema: Option<serde_json::Value>,
 }
 
 impl Manager {
     pub fn new(sys: ActorSystem) -> Self {
+        let lifecycle =
+            riker::actors::channel("entity-lifecycle", &sys).expect("create lifecycle channel");
         Manager {
             sys,
-            entities: HashMap::new(),
+            entities: Arc::new(RwLock::new(HashMap::new())),
+            registered: vec![],
+            lifecycle,
+            checkpoints: vec![],
         }
     }
 
@@ -21,47 +79,518 @@ impl Manager {
         &self.sys
     }
 
-    pub fn register<E, S>(mut self, store: S, args: E::Args) -> Self
+    /// Channel where registered entities and their stores announce when they
+    /// start or stop, subscribe to the `LIFECYCLE_TOPIC` to observe them.
+    pub fn lifecycle(&self) -> &SystemBus {
+        &self.lifecycle
+    }
+
+    pub fn register<E, S>(self, store: S, args: E::Args) -> Self
     where
         E: ES,
         S: CommitStore<E::Model>,
     {
+        self.register_with::<E, S>(store, args, EntityConfig::default())
+    }
+
+    /// Registers the entity with the options of the entity and its store, the
+    /// entity reports to the lifecycle bus of the manager and reaches other
+    /// entities through its peers regardless of the config.
+    pub fn register_with<E, S>(
+        mut self,
+        store: S,
+        args: E::Args,
+        config: EntityConfig<E::Model>,
+    ) -> Self
+    where
+        E: ES,
+        S: CommitStore<E::Model>,
+    {
+        let config = config.sys_bus(self.lifecycle.clone()).peers(self.peers());
         let entity = self
             .sys
-            .actor_of_args::<Entity<E, S>, _>(E::NAME, (store, args))
+            .actor_of_args::<Entity<E, S>, _>(E::NAME, (store, args, config))
             .expect(&format!("create entity {}", E::NAME));
-        self.entities.insert(E::NAME.into(), entity.into());
+        self.entities
+            .write()
+            .unwrap()
+            .insert(E::NAME.into(), entity.clone().into());
+        self.registered.push(EntityInfo {
+            name: E::NAME.into(),
+            schema: E::schema(),
+        });
+        let sys = self.sys.clone();
+        let checkpoint: CheckpointFn = Box::new(move |at| {
+            let (sys, entity) = (sys.clone(), entity.clone());
+            async move {
+                let msg: EntityMsg<E> = CQRS::Checkpoint(at);
+                ask(&sys, entity.into(), msg)
+                    .await
+                    .unwrap_or(Err(CommitError::Unavailable))
+            }
+            .boxed()
+        });
+        self.checkpoints.push((E::NAME.into(), checkpoint));
         self
     }
 
-    pub async fn command<C>(&self, cmd: C) -> EntityId
+    /// Stops the entity registered with the name along with its store and
+    /// forgets about it, returns whether there was such an entity.
+    pub fn deregister(&mut self, name: &str) -> bool {
+        let entity = match self.entities.write().unwrap().remove(name) {
+            Some(entity) => entity,
+            None => return false,
+        };
+        self.sys.stop(&entity);
+        self.registered.retain(|info| info.name != name);
+        self.checkpoints.retain(|(entity, _)| entity != name);
+        true
+    }
+
+    /// Waits for the commits every entity is persisting and checkpoints their
+    /// stores, e.g. saving snapshots when they are `SnapshottingStore`s.
+    /// Returns the moment of the checkpoint.
+    pub async fn checkpoint(&self) -> CommitResult<DateTime<Utc>> {
+        let at = Utc::now();
+        join_all(
+            self.checkpoints
+                .iter()
+                .map(|(_, checkpoint)| checkpoint(at)),
+        )
+        .await
+        .into_iter()
+        .collect::<CommitResult<Vec<()>>>()?;
+        Ok(at)
+    }
+
+    /// A handle registered entities get to interact with each other
+    pub fn peers(&self) -> Peers {
+        Peers {
+            sys: self.sys.clone(),
+            entities: self.entities.clone(),
+        }
+    }
+
+    /// The entity types registered so far in the order they were registered
+    pub fn registered(&self) -> Vec<EntityInfo> {
+        self.registered.clone()
+    }
+
+    /// Sends a command to the entity that handles it, for entities whose model
+    /// is identified by something other than an `EntityId` use `command_to`.
+    /// Fails when no entity is registered with the name of the command, when
+    /// its handler rejects it or when its commit can't be persisted.
+    pub async fn command<C>(&self, cmd: C) -> Result<EntityId, ManagerError>
     where
         C: Message + EntityName,
     {
-        let entity = self.entity(<C as EntityName>::NAME);
-        self.ask(entity, CQRS::Cmd(cmd)).await
+        let entity = self.find(<C as EntityName>::NAME)?;
+        let cmd: CQRS<C> = CQRS::Cmd(cmd);
+        self.ask(entity, cmd).await?
+    }
+
+    pub async fn command_to<E>(&self, cmd: E::Cmd) -> Result<<E::Model as Model>::Id, ManagerError>
+    where
+        E: ES,
+    {
+        let entity = self.find(<E as EntityName>::NAME)?;
+        let cmd: EntityMsg<E> = CQRS::Cmd(cmd);
+        self.ask(entity, cmd).await?
+    }
+
+    /// Like `command_to` but replies with the error of the entity itself when
+    /// its handler rejects the command.
+    pub async fn try_command<E>(&self, cmd: E::Cmd) -> CommandResult<E>
+    where
+        E: ES,
+    {
+        let entity = self.find(<E as EntityName>::NAME)?;
+        let cmd: EntityMsg<E> = CQRS::TryCmd(cmd);
+        self.ask(entity, cmd).await?
+    }
+
+    /// Sends a command as part of a chain of commands, the commits it results
+    /// in carry the correlation id to trace them back to the original request.
+    pub async fn command_correlated<E>(
+        &self,
+        cmd: E::Cmd,
+        correlation: EntityId,
+    ) -> Result<<E::Model as Model>::Id, ManagerError>
+    where
+        E: ES,
+    {
+        let entity = self.find(<E as EntityName>::NAME)?;
+        let cmd: EntityMsg<E> = CQRS::Correlated(correlation, cmd);
+        self.ask(entity, cmd).await?
+    }
+
+    /// Like `command` but replies with the full outcome of handling it,
+    /// including any warnings the entity raised, or why it didn't go through.
+    pub async fn command_outcome<E>(
+        &self,
+        cmd: E::Cmd,
+    ) -> Result<CommandOutcome<E::Model>, CommandError<E::Error>>
+    where
+        E: ES,
+    {
+        let entity = self.find(<E as EntityName>::NAME)?;
+        let cmd: EntityMsg<E> = CQRS::CmdOutcome(cmd);
+        self.ask(entity, cmd).await?
+    }
+
+    /// Handles a command retrying it when its commit conflicts with a concurrent
+    /// change, the entity handler runs again against the latest state each time.
+    /// Fails with `ConflictExhausted` when every attempt conflicted.
+    pub async fn command_with_retry<E>(
+        &self,
+        cmd: E::Cmd,
+        attempts: u32,
+    ) -> Result<CommandOutcome<E::Model>, CommandError<E::Error>>
+    where
+        E: ES,
+    {
+        let entity = self.find(<E as EntityName>::NAME)?;
+        let cmd: EntityMsg<E> = CQRS::Retry(cmd, attempts);
+        self.ask(entity, cmd).await?
+    }
+
+    /// How many commands the entity received and hasn't replied yet, a
+    /// command is only tied to an instance once handled so the depth is the
+    /// one of the entity type as a whole.
+    pub async fn queue_depth<E>(&self) -> Result<usize, ManagerError>
+    where
+        E: ES,
+    {
+        let entity = self.find(<E as EntityName>::NAME)?;
+        let msg: EntityMsg<E> = CQRS::QueueDepth;
+        self.ask(entity, msg).await
+    }
+
+    /// Runs a command through the entity's handler without persisting the
+    /// commits it produces, useful to try out command logic on real data.
+    pub async fn dry_run<E>(
+        &self,
+        cmd: E::Cmd,
+    ) -> Result<CommandOutcome<E::Model>, CommandError<E::Error>>
+    where
+        E: ES,
+    {
+        let entity = self.find(<E as EntityName>::NAME)?;
+        let cmd: EntityMsg<E> = CQRS::DryRun(cmd);
+        self.ask(entity, cmd).await?
+    }
+
+    /// Like `dry_run` but against the state entities had at the given moment,
+    /// to reproduce what a past command did.
+    pub async fn dry_run_at<E>(
+        &self,
+        at: DateTime<Utc>,
+        cmd: E::Cmd,
+    ) -> Result<CommandOutcome<E::Model>, CommandError<E::Error>>
+    where
+        E: ES,
+    {
+        let entity = self.find(<E as EntityName>::NAME)?;
+        let cmd: EntityMsg<E> = CQRS::DryRunAt(at, cmd);
+        self.ask(entity, cmd).await?
     }
 
-    pub async fn query<E>(&self, id: EntityId) -> Option<E::Model>
+    /// Appends the commit that brings an entity back to the state it had at
+    /// the given moment, its history is kept as is.
+    pub async fn revert_to<E>(
+        &self,
+        id: <E::Model as Model>::Id,
+        at: DateTime<Utc>,
+    ) -> Result<Commit<E::Model>, ManagerError>
+    where
+        E: ES,
+    {
+        let entity = self.find(<E as EntityName>::NAME)?;
+        let msg: EntityMsg<E> = CQRS::Revert(id, at);
+        let revert: CommitResult<_> = self.ask(entity, msg).await?;
+        revert.map_err(ManagerError::Commit)
+    }
+
+    /// The current state of an entity, `None` if it doesn't exist. Fails when
+    /// the entity type isn't registered.
+    pub async fn query<E>(
+        &self,
+        id: <E::Model as Model>::Id,
+    ) -> Result<Option<E::Model>, ManagerError>
     where
         E: ES + EntityName,
     {
-        let entity = self.entity(<E as EntityName>::NAME);
-        let q: CQRS<E::Cmd> = CQRS::Query(Query::One(id.into()));
+        let entity = self.find(<E as EntityName>::NAME)?;
+        let q: EntityMsg<E> = CQRS::Query(Query::One(id));
         self.ask(entity, q).await
     }
 
-    pub fn entity(&self, name: &str) -> BasicActorRef {
-        self.entities.get(name).unwrap().clone()
+    /// Lists the current state of all entities of a type, dropping the
+    /// returned future before it completes cancels the query.
+    pub async fn list<E>(&self) -> Result<Vec<E::Model>, ManagerError>
+    where
+        E: ES + EntityName,
+    {
+        let entity = self.find(<E as EntityName>::NAME)?;
+        let (_guard, cancel) = CancelToken::new();
+        let q: EntityMsg<E> = CQRS::Query(Query::AllCancellable(cancel));
+        self.ask(entity, q).await
     }
 
-    async fn ask<Msg: Message, R: Message>(&self, entity: BasicActorRef, msg: Msg) -> R {
-        let (tx, rx) = channel::<R>();
-        let tx = Arc::new(Mutex::new(Some(tx)));
-        let tmp_sender = self.sys.tmp_actor_of_args::<AskActor<R>, _>(tx).unwrap();
+    /// Lists a page of the current state of the entities of a type, the page
+    /// is `truncated` when there are more entities after it.
+    pub async fn query_page<E>(
+        &self,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Limited<E::Model>, ManagerError>
+    where
+        E: ES + EntityName,
+    {
+        let entity = self.find(<E as EntityName>::NAME)?;
+        let q: EntityMsg<E> = CQRS::Query(Query::AllPaged { offset, limit });
+        self.ask(entity, q).await
+    }
+
+    /// Lists the commits made to an entity, oldest first. Unknown entities
+    /// have no commits.
+    pub async fn history<E>(
+        &self,
+        id: <E::Model as Model>::Id,
+    ) -> Result<Vec<Commit<E::Model>>, ManagerError>
+    where
+        E: ES + EntityName,
+    {
+        let entity = self.find(<E as EntityName>::NAME)?;
+        let q: EntityMsg<E> = CQRS::Query(Query::History(id));
+        self.ask(entity, q).await
+    }
+
+    /// Writes the commits of an entity, or of all the entities of a type when
+    /// no id is given, to the writer for analysis with other tools.
+    /// Returns how many commits were written.
+    pub async fn export<E, W>(
+        &self,
+        mut writer: W,
+        format: ExportFormat,
+        id: Option<<E::Model as Model>::Id>,
+    ) -> io::Result<usize>
+    where
+        E: ES + EntityName,
+        E::Model: Serialize,
+        <E::Model as Model>::Id: Serialize,
+        <E::Model as Model>::Change: Serialize,
+        W: Write,
+    {
+        let commits = match id {
+            Some(id) => self.history::<E>(id).await.map_err(io::Error::other)?,
+            None => {
+                let entity = self
+                    .find(<E as EntityName>::NAME)
+                    .map_err(io::Error::other)?;
+                let q: EntityMsg<E> = CQRS::Query(Query::CommitsSince {
+                    since: DateTime::<Utc>::MIN_UTC,
+                    kinds: vec![EventKind::Create, EventKind::Change, EventKind::Delete],
+                });
+                let commits: CommitResult<Vec<Commit<E::Model>>> =
+                    self.ask(entity, q).await.map_err(io::Error::other)?;
+                commits.map_err(io::Error::other)?
+            }
+        };
+        if format == ExportFormat::Csv {
+            writeln!(writer, "entity_id,kind,when,who,why,transaction,data")?;
+        }
+        for c in &commits {
+            match format {
+                ExportFormat::JsonLines => {
+                    serde_json::to_writer(&mut writer, c)?;
+                    writeln!(writer)?;
+                }
+                ExportFormat::Csv => {
+                    let data = match &**c {
+                        Event::Create(model) => serde_json::to_string(model)?,
+                        Event::Change(_, change) => serde_json::to_string(change)?,
+                        Event::Delete(_) => String::new(),
+                    };
+                    let row = [
+                        c.entity_id().to_string(),
+                        format!("{:?}", c.kind()),
+                        c.when().to_rfc3339(),
+                        c.who().unwrap_or_default().into(),
+                        c.why().unwrap_or_default().into(),
+                        c.transaction().map(|t| t.to_string()).unwrap_or_default(),
+                        data,
+                    ];
+                    let row: Vec<_> = row.iter().map(|field| csv_field(field)).collect();
+                    writeln!(writer, "{}", row.join(","))?;
+                }
+            }
+        }
+        writer.flush()?;
+        Ok(commits.len())
+    }
+
+    /// Opens a scope whose commands produce commits tagged with the same transaction id
+    pub fn transaction(&self) -> TransactionScope<'_> {
+        TransactionScope {
+            mgr: self,
+            id: Uuid::new_v4(),
+        }
+    }
+
+    /// Asks the entity registered with the name like the typed methods do but
+    /// encodes its reply with the codec, so a transport in front of the
+    /// manager can hand replies over without knowing their types.
+    pub async fn ask_serialized<Msg, R, C>(
+        &self,
+        entity: &str,
+        msg: Msg,
+        codec: &C,
+    ) -> Result<Vec<u8>, ManagerError>
+    where
+        Msg: Message,
+        R: Message + Serialize,
+        C: Codec,
+    {
+        let entity = self.find(entity)?;
+        let reply: R = self.ask(entity, msg).await?;
+        codec.encode(&reply).map_err(ManagerError::Commit)
+    }
+
+    /// The actor of the entity registered with the name if there's one
+    pub fn entity(&self, name: &str) -> Option<BasicActorRef> {
+        self.entities.read().unwrap().get(name).cloned()
+    }
+
+    fn find(&self, name: &str) -> Result<BasicActorRef, ManagerError> {
+        self.entity(name)
+            .ok_or_else(|| ManagerError::UnknownEntity(name.into()))
+    }
+
+    async fn ask<Msg: Message, R: Message>(
+        &self,
+        entity: BasicActorRef,
+        msg: Msg,
+    ) -> Result<R, ManagerError> {
+        ask(&self.sys, entity, msg).await
+    }
+}
 
-        entity.try_tell(msg, tmp_sender).expect("can send message");
-        rx.await.unwrap()
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.into()
+    }
+}
+
+/// Lets the command handler of an entity query or command the other entities
+/// registered in the same `Manager`, including the ones registered after it.
+/// Querying its own entity is fine but commanding it would wait forever as
+/// commands of an entity are handled one at a time. Asking a peer fails when
+/// it isn't registered or stops before replying.
+#[derive(Clone)]
+pub struct Peers {
+    sys: ActorSystem,
+    entities: Arc<RwLock<HashMap<String, BasicActorRef>>>,
+}
+
+impl Peers {
+    pub async fn query<E>(
+        &self,
+        id: <E::Model as Model>::Id,
+    ) -> Result<Option<E::Model>, ManagerError>
+    where
+        E: ES,
+    {
+        let q: EntityMsg<E> = CQRS::Query(Query::One(id));
+        self.ask::<E, _, _>(q).await
+    }
+
+    pub async fn history<E>(
+        &self,
+        id: <E::Model as Model>::Id,
+    ) -> Result<Vec<Commit<E::Model>>, ManagerError>
+    where
+        E: ES,
+    {
+        let q: EntityMsg<E> = CQRS::Query(Query::History(id));
+        self.ask::<E, _, _>(q).await
+    }
+
+    pub async fn command_to<E>(&self, cmd: E::Cmd) -> Result<<E::Model as Model>::Id, ManagerError>
+    where
+        E: ES,
+    {
+        let cmd: EntityMsg<E> = CQRS::Cmd(cmd);
+        self.ask::<E, _, _>(cmd).await?
+    }
+
+    async fn ask<E: ES, Msg: Message, R: Message>(&self, msg: Msg) -> Result<R, ManagerError> {
+        let entity = self
+            .entities
+            .read()
+            .unwrap()
+            .get(E::NAME)
+            .cloned()
+            .ok_or_else(|| ManagerError::UnknownEntity(E::NAME.into()))?;
+        ask(&self.sys, entity, msg).await
+    }
+}
+
+impl fmt::Debug for Peers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Peers({} entities)", self.entities.read().unwrap().len())
+    }
+}
+
+/// Sends a message to an actor and waits for its reply, fails when the actor
+/// is gone or drops the message without replying.
+pub(crate) async fn ask<Msg: Message, R: Message>(
+    sys: &ActorSystem,
+    actor: BasicActorRef,
+    msg: Msg,
+) -> Result<R, ManagerError> {
+    let (tx, rx) = channel::<R>();
+    let tx = Arc::new(Mutex::new(Some(tx)));
+    let tmp_sender = sys
+        .tmp_actor_of_args::<AskActor<R>, _>(tx)
+        .map_err(|_| ManagerError::Canceled)?;
+
+    if actor.try_tell(msg, tmp_sender.clone()).is_err() {
+        sys.stop(&tmp_sender);
+        return Err(ManagerError::Canceled);
+    }
+    rx.await.map_err(|_| ManagerError::Canceled)
+}
+
+/// Issues commands whose commits are grouped under a shared transaction id
+pub struct TransactionScope<'a> {
+    mgr: &'a Manager,
+    id: Uuid,
+}
+
+impl<'a> TransactionScope<'a> {
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    pub async fn command<C>(&self, cmd: C) -> Result<EntityId, ManagerError>
+    where
+        C: Message + EntityName,
+    {
+        let entity = self.mgr.find(<C as EntityName>::NAME)?;
+        let cmd: CQRS<C> = CQRS::Transaction(self.id, cmd);
+        self.mgr.ask(entity, cmd).await?
+    }
+
+    pub async fn command_to<E>(&self, cmd: E::Cmd) -> Result<<E::Model as Model>::Id, ManagerError>
+    where
+        E: ES,
+    {
+        let entity = self.mgr.find(<E as EntityName>::NAME)?;
+        let cmd: EntityMsg<E> = CQRS::Transaction(self.id, cmd);
+        self.mgr.ask(entity, cmd).await?
     }
 }
 
@@ -79,8 +608,9 @@ impl<Msg: Message> Actor for AskActor<Msg> {
     type Msg = Msg;
 
     fn recv(&mut self, ctx: &Context<Self::Msg>, msg: Self::Msg, _: Sender) {
-        if let Ok(mut tx) = self.tx.lock() {
-            tx.take().unwrap().send(msg).unwrap();
+        // the asker might be gone already
+        if let Some(tx) = self.tx.lock().ok().and_then(|mut tx| tx.take()) {
+            let _ = tx.send(msg);
         }
         ctx.stop(&ctx.myself);
     }
@@ -89,15 +619,23 @@ impl<Msg: Message> Actor for AskActor<Msg> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{macros::*, Event, MemStore, Model};
+    use crate::store::tests::{Op, TestCount};
+    use crate::testing::collect_events;
+    use crate::{
+        es_system, macros::*, CommitError, Event, Id, InvariantPolicy, Json, Lifecycle,
+        MemSnapshotStore, MemStore, Model, SharedClock, SnapshotStore, SnapshottingStore,
+        StoreConfig, LIFECYCLE_TOPIC,
+    };
     use async_trait::async_trait;
     use futures::executor::block_on;
+    use std::time::Duration;
 
     #[derive(EntityName, Debug)]
     struct Entity1;
     #[derive(Debug, Clone)]
     struct Model1;
     impl Model for Model1 {
+        type Id = EntityId;
         type Change = ();
         fn id(&self) -> EntityId {
             "dummy".into()
@@ -105,30 +643,592 @@ mod tests {
         fn apply_change(&mut self, _change: &Self::Change) {}
     }
     impl EntityName for () {
-        const NAME: &'static str = "Entity1";
+        const NAME: &'static str = "entity1";
     }
     #[async_trait]
     impl ES for Entity1 {
         type Args = ();
         type Model = Model1;
         type Cmd = ();
-        type Error = ();
+        type Error = String;
         fn new(_cx: &Context<CQRS<Self::Cmd>>, _args: Self::Args) -> Self {
             Entity1
         }
-        async fn handle_command(
-            &mut self,
-            _cmd: Self::Cmd,
-        ) -> Result<crate::Commit<Self::Model>, Self::Error> {
+        async fn handle_command(&mut self, _cmd: Self::Cmd) -> crate::Result<Self> {
             Ok(Event::Create(Model1).into())
         }
     }
 
+    #[derive(EntityName, Debug)]
+    struct Counter;
+    #[derive(EntityName, Debug, Clone)]
+    #[entity(name = "counter")]
+    enum CounterCmd {
+        Create,
+        Add(EntityId, i16),
+    }
+    #[async_trait]
+    impl ES for Counter {
+        type Args = ();
+        type Model = TestCount;
+        type Cmd = CounterCmd;
+        type Error = String;
+        fn new(_cx: &Context<CQRS<Self::Cmd>>, _args: Self::Args) -> Self {
+            Counter
+        }
+        async fn handle_command(&mut self, cmd: Self::Cmd) -> crate::Result<Self> {
+            Ok(match cmd {
+                CounterCmd::Create => Event::Create(TestCount::new(0)).into(),
+                CounterCmd::Add(_, 0) => return Err("Nothing to add".into()),
+                CounterCmd::Add(id, n) => Commit::new(
+                    Event::Change(id, Op::Add(n)),
+                    Some("tester".into()),
+                    Some(format!("add {}", n)),
+                )
+                .into(),
+            })
+        }
+    }
+
+    #[derive(EntityName, Debug)]
+    struct Tickets;
+    #[derive(Debug, Clone)]
+    struct Ticket {
+        number: u64,
+        open: bool,
+    }
+    impl Model for Ticket {
+        type Id = u64;
+        type Change = bool;
+        fn id(&self) -> u64 {
+            self.number
+        }
+        fn apply_change(&mut self, open: &bool) {
+            self.open = *open;
+        }
+    }
+    #[derive(Debug, Clone)]
+    enum TicketCmd {
+        Open(u64),
+        Close(u64),
+    }
+    #[async_trait]
+    impl ES for Tickets {
+        type Args = ();
+        type Model = Ticket;
+        type Cmd = TicketCmd;
+        type Error = String;
+        fn new(_cx: &Context<EntityMsg<Self>>, _args: Self::Args) -> Self {
+            Tickets
+        }
+        fn schema() -> Option<serde_json::Value> {
+            Some(serde_json::json!({
+                "commands": ["Open", "Close"],
+                "model": { "number": "u64", "open": "bool" },
+            }))
+        }
+        async fn handle_command(&mut self, cmd: Self::Cmd) -> crate::Result<Self> {
+            Ok(match cmd {
+                TicketCmd::Open(number) => Event::Create(Ticket { number, open: true }),
+                TicketCmd::Close(number) => Event::Change(number, false),
+            }
+            .into())
+        }
+    }
+
+    #[derive(EntityName, Debug)]
+    struct Report {
+        peers: Option<Peers>,
+    }
+    #[derive(Debug, Clone)]
+    struct Summary {
+        id: EntityId,
+        total: i16,
+    }
+    impl Model for Summary {
+        type Id = EntityId;
+        type Change = ();
+        fn id(&self) -> EntityId {
+            self.id
+        }
+        fn apply_change(&mut self, _change: &()) {}
+    }
+    #[async_trait]
+    impl ES for Report {
+        type Args = ();
+        type Model = Summary;
+        type Cmd = EntityId;
+        type Error = String;
+        fn new(_cx: &Context<EntityMsg<Self>>, _args: Self::Args) -> Self {
+            Report { peers: None }
+        }
+        fn with_peers(&mut self, peers: Peers) {
+            self.peers = Some(peers);
+        }
+        async fn handle_command(&mut self, counter: EntityId) -> crate::Result<Self> {
+            let peers = self.peers.as_ref().ok_or("not registered")?;
+            let count = peers
+                .query::<Counter>(counter)
+                .await
+                .map_err(|err| err.to_string())?
+                .ok_or("no counter")?;
+            Ok(Event::Create(Summary {
+                id: EntityId::new(),
+                total: count.count,
+            })
+            .into())
+        }
+    }
+
+    #[derive(EntityName, Debug)]
+    struct Orders;
+    #[derive(Debug, Clone)]
+    struct Order {
+        id: Id<Order>,
+        paid: bool,
+    }
+    impl Model for Order {
+        type Id = Id<Order>;
+        type Change = ();
+        fn id(&self) -> Id<Order> {
+            self.id
+        }
+        fn apply_change(&mut self, _change: &()) {
+            self.paid = true;
+        }
+    }
+    #[derive(Debug, Clone)]
+    enum OrderCmd {
+        Place,
+        Pay(Id<Order>),
+    }
+    #[async_trait]
+    impl ES for Orders {
+        type Args = ();
+        type Model = Order;
+        type Cmd = OrderCmd;
+        type Error = String;
+        fn new(_cx: &Context<EntityMsg<Self>>, _args: Self::Args) -> Self {
+            Orders
+        }
+        async fn handle_command(&mut self, cmd: Self::Cmd) -> crate::Result<Self> {
+            Ok(match cmd {
+                OrderCmd::Place => Event::Create(Order {
+                    id: Id::new(),
+                    paid: false,
+                }),
+                OrderCmd::Pay(id) => Event::Change(id, ()),
+            }
+            .into())
+        }
+    }
+
+    #[test]
+    fn identify_entities_with_typed_ids() {
+        let sys = ActorSystem::new().unwrap();
+        let mgr = Manager::new(sys).register::<Orders, _>(MemStore::new(), ());
+        let id: Id<Order> = block_on(mgr.command_to::<Orders>(OrderCmd::Place)).unwrap();
+        block_on(mgr.command_to::<Orders>(OrderCmd::Pay(id))).unwrap();
+        assert!(block_on(mgr.query::<Orders>(id)).unwrap().unwrap().paid);
+        let history = block_on(mgr.history::<Orders>(id)).unwrap();
+        assert!(history.iter().all(|c| c.entity_id() == id));
+
+        // serialized like the untyped id it wraps
+        let untyped: EntityId = id.into();
+        assert_eq!(*id, untyped);
+        let json = serde_json::to_string(&id).unwrap();
+        assert_eq!(json, serde_json::to_string(&untyped).unwrap());
+        let decoded: Id<Order> = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, id);
+        assert_eq!(id.to_string().parse::<Id<Order>>().unwrap(), id);
+    }
+
+    #[test]
+    fn query_peer_entities() {
+        let sys = ActorSystem::new().unwrap();
+        let mgr = Manager::new(sys)
+            .register::<Report, _>(MemStore::new(), ())
+            .register::<Counter, _>(MemStore::new(), ());
+        let counter = block_on(mgr.command(CounterCmd::Create)).unwrap();
+        block_on(mgr.command(CounterCmd::Add(counter, 7))).unwrap();
+
+        // commands are replied once their commits are persisted
+        let report = block_on(mgr.command_to::<Report>(counter)).unwrap();
+        let summary = block_on(mgr.query::<Report>(report)).unwrap();
+        assert_eq!(summary.unwrap().total, 7);
+
+        // dry runs in the past reach the peers too
+        let outcome = block_on(mgr.dry_run_at::<Report>(Utc::now(), counter)).unwrap();
+        assert_eq!(outcome.commit.entity().unwrap().total, 7);
+    }
+
+    #[test]
+    fn query_missing_peers() {
+        let sys = ActorSystem::new().unwrap();
+        let mgr = Manager::new(sys).register::<Report, _>(MemStore::new(), ());
+        let res = block_on(mgr.command_to::<Report>(EntityId::new()));
+        let missing = ManagerError::UnknownEntity("counter".into()).to_string();
+        assert_eq!(res, Err(ManagerError::Rejected(missing)));
+    }
+
+    #[test]
+    fn checkpoint_snapshots() {
+        let snapshots = MemSnapshotStore::new(Json);
+        let store = SnapshottingStore::new(MemStore::new(), snapshots.clone());
+        let mgr = Manager::new(ActorSystem::new().unwrap()).register::<Counter, _>(store, ());
+        let id = block_on(mgr.command(CounterCmd::Create)).unwrap();
+        for n in 1..=3 {
+            block_on(mgr.command(CounterCmd::Add(id, n))).unwrap();
+        }
+
+        let at = block_on(mgr.checkpoint()).unwrap();
+        let snapshot = block_on(snapshots.load(id)).unwrap();
+        assert_eq!(snapshot.model.count, 6);
+        assert_eq!(snapshot.version, 4);
+        assert_eq!(snapshot.when, at);
+    }
+
     #[test]
     fn register_entities() {
         let sys = ActorSystem::new().unwrap();
         let mgr = Manager::new(sys).register::<Entity1, _>(MemStore::new(), ());
-        let id = block_on(mgr.command(()));
+        let id = block_on(mgr.command(())).unwrap();
         assert_eq!(id, "dummy".into());
     }
+
+    #[test]
+    fn register_with_config() {
+        let start = Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap();
+        let clock = crate::MockClock::new(start);
+        let shared: SharedClock = Arc::new(clock.clone());
+        let config = EntityConfig::default()
+            .clock(shared)
+            .store(StoreConfig::default().invariants(InvariantPolicy::Reject));
+        let mgr = Manager::new(ActorSystem::new().unwrap()).register_with::<Counter, _>(
+            MemStore::new(),
+            (),
+            config,
+        );
+
+        let id = block_on(mgr.command(CounterCmd::Create)).unwrap();
+        clock.advance(chrono::Duration::hours(1));
+        block_on(mgr.command(CounterCmd::Add(id, 2))).unwrap();
+        let res = block_on(mgr.command(CounterCmd::Add(id, -5)));
+        assert!(matches!(
+            res,
+            Err(ManagerError::Commit(CommitError::Invariant(_)))
+        ));
+        let history = block_on(mgr.history::<Counter>(id)).unwrap();
+        let when: Vec<_> = history.iter().map(|c| c.when()).collect();
+        assert_eq!(when, vec![start, start + chrono::Duration::hours(1)]);
+    }
+
+    #[test]
+    fn register_with_es_system() {
+        let mgr = Manager::new(es_system()).register::<Entity1, _>(MemStore::new(), ());
+        let id = block_on(mgr.command(())).unwrap();
+        assert_eq!(id, "dummy".into());
+    }
+
+    #[test]
+    fn observe_lifecycle() {
+        let sys = ActorSystem::new().unwrap();
+        let mgr = Manager::new(sys);
+        let events = collect_events(mgr.sys(), mgr.lifecycle(), LIFECYCLE_TOPIC, 2);
+        let _mgr = mgr.register::<Entity1, _>(MemStore::new(), ());
+
+        let events = block_on(events);
+        let started = |name: &str| {
+            events
+                .iter()
+                .any(|e| e.name == name && e.kind == Lifecycle::Started)
+        };
+        assert!(started("entity1"));
+        assert!(started("entity1_store"));
+    }
+
+    #[test]
+    fn commit_history() {
+        let sys = ActorSystem::new().unwrap();
+        let mgr = Manager::new(sys).register::<Counter, _>(MemStore::new(), ());
+        let id = block_on(mgr.command(CounterCmd::Create)).unwrap();
+        block_on(mgr.command(CounterCmd::Add(id, 1))).unwrap();
+        block_on(mgr.command(CounterCmd::Add(id, 2))).unwrap();
+
+        let history = block_on(mgr.history::<Counter>(id)).unwrap();
+        assert_eq!(history.len(), 3);
+        assert!(history[0].entity().is_some());
+        assert_eq!(history[0].who(), None);
+        assert_eq!(history[1].who(), Some("tester"));
+        assert_eq!(history[1].why(), Some("add 1"));
+        assert_eq!(history[2].why(), Some("add 2"));
+        assert!(history[1].when() <= history[2].when());
+        assert!(block_on(mgr.history::<Counter>(EntityId::new()))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn export_commits() {
+        let sys = ActorSystem::new().unwrap();
+        let mgr = Manager::new(sys).register::<Counter, _>(MemStore::new(), ());
+        let id = block_on(mgr.command(CounterCmd::Create)).unwrap();
+        block_on(mgr.command(CounterCmd::Add(id, 1))).unwrap();
+        let other = block_on(mgr.command(CounterCmd::Create)).unwrap();
+
+        let mut out = vec![];
+        let written = block_on(mgr.export::<Counter, _>(&mut out, ExportFormat::JsonLines, None));
+        assert_eq!(written.unwrap(), 3);
+        let lines: Vec<Commit<TestCount>> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines.iter().filter(|c| c.entity_id() == other).count(), 1);
+
+        let mut out = vec![];
+        let written = block_on(mgr.export::<Counter, _>(&mut out, ExportFormat::Csv, Some(id)));
+        assert_eq!(written.unwrap(), 2);
+        let csv = String::from_utf8(out).unwrap();
+        let rows: Vec<_> = csv.lines().collect();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0], "entity_id,kind,when,who,why,transaction,data");
+        assert!(rows[1].starts_with(&format!("{},Create,", id)));
+        assert!(rows[2].starts_with(&format!("{},Change,", id)));
+        assert!(rows[2].ends_with(r#",tester,add 1,,"{""Add"":1}""#));
+    }
+
+    #[test]
+    fn revert_keeping_history() {
+        let sys = ActorSystem::new().unwrap();
+        let mgr = Manager::new(sys).register::<Counter, _>(MemStore::new(), ());
+        let id = block_on(mgr.command(CounterCmd::Create)).unwrap();
+        block_on(mgr.command(CounterCmd::Add(id, 2))).unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+        let at = Utc::now();
+        std::thread::sleep(Duration::from_millis(5));
+        block_on(mgr.command(CounterCmd::Add(id, 5))).unwrap();
+        block_on(mgr.command(CounterCmd::Add(id, 3))).unwrap();
+        assert_eq!(
+            block_on(mgr.query::<Counter>(id)).unwrap().unwrap().count,
+            10
+        );
+
+        let revert = block_on(mgr.revert_to::<Counter>(id, at)).unwrap();
+        assert!(revert.why().unwrap().starts_with("revert to"));
+        assert_eq!(revert.expected_version(), Some(4));
+        assert_eq!(
+            block_on(mgr.query::<Counter>(id)).unwrap().unwrap().count,
+            2
+        );
+        assert_eq!(block_on(mgr.history::<Counter>(id)).unwrap().len(), 5);
+
+        let res = block_on(mgr.revert_to::<Counter>(EntityId::new(), at));
+        assert!(matches!(
+            res,
+            Err(ManagerError::Commit(CommitError::NotFound))
+        ));
+    }
+
+    #[test]
+    fn serialize_query_reply() {
+        let sys = ActorSystem::new().unwrap();
+        let mgr = Manager::new(sys).register::<Counter, _>(MemStore::new(), ());
+        let id = block_on(mgr.command(CounterCmd::Create)).unwrap();
+        block_on(mgr.command(CounterCmd::Add(id, 4))).unwrap();
+
+        let query: EntityMsg<Counter> = CQRS::Query(Query::One(id));
+        let bytes =
+            block_on(mgr.ask_serialized::<_, Option<TestCount>, _>("counter", query, &Json));
+        let expected = format!(r#"{{"id":"{}","count":4}}"#, id);
+        assert_eq!(String::from_utf8(bytes.unwrap()).unwrap(), expected);
+        let missing: EntityMsg<Counter> = CQRS::Query(Query::One(EntityId::new()));
+        let bytes =
+            block_on(mgr.ask_serialized::<_, Option<TestCount>, _>("counter", missing, &Json));
+        assert_eq!(bytes.unwrap(), b"null");
+    }
+
+    #[test]
+    fn natural_entity_id() {
+        let sys = ActorSystem::new().unwrap();
+        let mgr = Manager::new(sys).register::<Tickets, _>(MemStore::new(), ());
+        let id = block_on(mgr.command_to::<Tickets>(TicketCmd::Open(7))).unwrap();
+        assert_eq!(id, 7);
+        block_on(mgr.command_to::<Tickets>(TicketCmd::Close(7))).unwrap();
+
+        let history = block_on(mgr.history::<Tickets>(7)).unwrap();
+        assert_eq!(history.len(), 2);
+        let ticket = block_on(mgr.query::<Tickets>(7)).unwrap().unwrap();
+        assert_eq!(ticket.number, 7);
+        assert!(!ticket.open);
+    }
+
+    #[test]
+    fn commands_in_transaction() {
+        let sys = ActorSystem::new().unwrap();
+        let mgr = Manager::new(sys).register::<Counter, _>(MemStore::new(), ());
+        let id = block_on(mgr.command(CounterCmd::Create)).unwrap();
+        let tx = mgr.transaction();
+        block_on(tx.command(CounterCmd::Add(id, 1))).unwrap();
+        block_on(tx.command(CounterCmd::Add(id, 2))).unwrap();
+
+        let history = block_on(mgr.history::<Counter>(id)).unwrap();
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[0].transaction(), None);
+        assert_eq!(history[1].transaction(), Some(tx.id()));
+        assert_eq!(history[2].transaction(), Some(tx.id()));
+    }
+
+    #[test]
+    fn query_entities_by_page() {
+        let sys = ActorSystem::new().unwrap();
+        let mgr = Manager::new(sys).register::<Counter, _>(MemStore::new(), ());
+        let mut created = vec![];
+        for _ in 0..5 {
+            created.push(block_on(mgr.command(CounterCmd::Create)).unwrap());
+        }
+
+        let mut listed = vec![];
+        for (offset, truncated) in [(0, true), (2, true), (4, false)] {
+            let page = block_on(mgr.query_page::<Counter>(offset, 2)).unwrap();
+            assert_eq!(page.truncated, truncated);
+            listed.extend(page.entities.iter().map(|c| c.id()));
+        }
+        listed.sort();
+        created.sort();
+        assert_eq!(listed, created);
+        assert!(block_on(mgr.query_page::<Counter>(5, 2))
+            .unwrap()
+            .entities
+            .is_empty());
+    }
+
+    #[test]
+    fn reply_rejected_commands() {
+        let sys = ActorSystem::new().unwrap();
+        let mgr = Manager::new(sys).register::<Counter, _>(MemStore::new(), ());
+        let id = block_on(mgr.try_command::<Counter>(CounterCmd::Create)).unwrap();
+        let res = block_on(mgr.try_command::<Counter>(CounterCmd::Add(id, 0)));
+        assert_eq!(res, Err(CommandError::Rejected("Nothing to add".into())));
+        let res = block_on(mgr.command(CounterCmd::Add(id, 0)));
+        assert_eq!(res, Err(ManagerError::Rejected("Nothing to add".into())));
+        let res = block_on(mgr.command_outcome::<Counter>(CounterCmd::Add(id, 0)));
+        assert!(matches!(res, Err(CommandError::Rejected(_))));
+        let tx = mgr.transaction();
+        let res = block_on(tx.command_to::<Counter>(CounterCmd::Add(id, 0)));
+        assert!(matches!(res, Err(ManagerError::Rejected(_))));
+        let res = block_on(mgr.command_with_retry::<Counter>(CounterCmd::Add(id, 0), 3));
+        assert!(matches!(res, Err(CommandError::Rejected(_))));
+
+        // the entity keeps handling commands
+        let res = block_on(mgr.try_command::<Counter>(CounterCmd::Add(id, 2)));
+        assert_eq!(res, Ok(id));
+        let res = block_on(mgr.command(CounterCmd::Add(EntityId::new(), 2)));
+        assert_eq!(res, Err(ManagerError::Commit(CommitError::CantChange)));
+        assert_eq!(
+            block_on(mgr.query::<Counter>(id)).unwrap().unwrap().count,
+            2
+        );
+        assert_eq!(block_on(mgr.history::<Counter>(id)).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn correlate_chained_commands() {
+        let sys = ActorSystem::new().unwrap();
+        let mgr = Manager::new(sys).register::<Counter, _>(MemStore::new(), ());
+        let id = block_on(mgr.command(CounterCmd::Create)).unwrap();
+        let request = EntityId::new();
+        block_on(mgr.command_correlated::<Counter>(CounterCmd::Add(id, 1), request)).unwrap();
+        block_on(mgr.command_correlated::<Counter>(CounterCmd::Add(id, 2), request)).unwrap();
+
+        let history = block_on(mgr.history::<Counter>(id)).unwrap();
+        assert_eq!(history[0].correlation_id(), None);
+        assert_eq!(history[1].correlation_id(), Some(request));
+        assert_eq!(history[2].correlation_id(), Some(request));
+
+        let cause = EntityId::new();
+        let c = Commit::<TestCount>::from(Event::Change(id, Op::Add(1))).caused_by(cause);
+        assert_eq!(c.causation_id(), Some(cause));
+        assert_eq!(c.correlation_id(), Some(cause));
+    }
+
+    #[test]
+    fn list_registered_entities() {
+        let sys = ActorSystem::new().unwrap();
+        let mgr = Manager::new(sys)
+            .register::<Entity1, _>(MemStore::new(), ())
+            .register::<Tickets, _>(MemStore::new(), ());
+
+        let registered = mgr.registered();
+        let names: Vec<_> = registered.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, vec!["entity1", "tickets"]);
+        assert!(registered[0].schema.is_none());
+        assert_eq!(
+            registered[1].schema.as_ref().unwrap()["model"]["open"],
+            "bool"
+        );
+    }
+
+    #[test]
+    fn deregister_entities() {
+        let sys = ActorSystem::new().unwrap();
+        let mut mgr = Manager::new(sys)
+            .register::<Entity1, _>(MemStore::new(), ())
+            .register::<Tickets, _>(MemStore::new(), ());
+        // entities and their stores have started once they checkpoint
+        block_on(mgr.checkpoint()).unwrap();
+        let stopped = collect_events(mgr.sys(), mgr.lifecycle(), LIFECYCLE_TOPIC, 2);
+
+        assert!(mgr.deregister("tickets"));
+        assert!(!mgr.deregister("tickets"));
+        let names: Vec<_> = mgr.registered().into_iter().map(|e| e.name).collect();
+        assert_eq!(names, vec!["entity1"]);
+
+        let stopped = block_on(stopped);
+        assert!(stopped.iter().all(|e| e.kind == Lifecycle::Stopped));
+        let mut names: Vec<_> = stopped.iter().map(|e| e.name.as_str()).collect();
+        names.sort_unstable();
+        assert_eq!(names, vec!["tickets", "tickets_store"]);
+        let running = |name: &str| mgr.sys().user_root().children().any(|a| a.name() == name);
+        assert!(running("entity1"));
+    }
+
+    #[test]
+    fn reject_unknown_entities() {
+        let sys = ActorSystem::new().unwrap();
+        let mut mgr = Manager::new(sys).register::<Counter, _>(MemStore::new(), ());
+        let unknown = |name: &str| Some(ManagerError::UnknownEntity(name.into()));
+        assert!(mgr.entity("tickets").is_none());
+        let res = block_on(mgr.command_to::<Tickets>(TicketCmd::Open(1)));
+        assert_eq!(res.err(), unknown("tickets"));
+        assert_eq!(block_on(mgr.query::<Tickets>(1)).err(), unknown("tickets"));
+
+        let id = block_on(mgr.command(CounterCmd::Create)).unwrap();
+        mgr.deregister("counter");
+        let res = block_on(mgr.command(CounterCmd::Add(id, 1)));
+        assert_eq!(res.err(), unknown("counter"));
+        assert_eq!(block_on(mgr.query::<Counter>(id)).err(), unknown("counter"));
+        let res = block_on(mgr.try_command::<Counter>(CounterCmd::Add(id, 1)));
+        assert_eq!(res.err().map(ManagerError::from), unknown("counter"));
+        let res = block_on(mgr.dry_run::<Counter>(CounterCmd::Add(id, 1)));
+        assert_eq!(res.err().map(ManagerError::from), unknown("counter"));
+        let res = block_on(mgr.command_with_retry::<Counter>(CounterCmd::Add(id, 1), 1));
+        assert_eq!(res.err().map(ManagerError::from), unknown("counter"));
+        assert_eq!(block_on(mgr.list::<Counter>()).err(), unknown("counter"));
+        assert_eq!(
+            block_on(mgr.history::<Counter>(id)).err(),
+            unknown("counter")
+        );
+        let res = block_on(mgr.revert_to::<Counter>(id, Utc::now()));
+        assert_eq!(res.err(), unknown("counter"));
+        let q: EntityMsg<Counter> = CQRS::Query(Query::One(id));
+        let res = block_on(mgr.ask_serialized::<_, Option<TestCount>, _>("counter", q, &Json));
+        assert_eq!(res.err(), unknown("counter"));
+        let res = block_on(mgr.transaction().command(CounterCmd::Add(id, 1)));
+        assert_eq!(res.err(), unknown("counter"));
+        let mut out = vec![];
+        let res = block_on(mgr.export::<Counter, _>(&mut out, ExportFormat::JsonLines, None));
+        assert!(res.is_err());
+    }
 }
@@ -2,51 +2,91 @@
 extern crate log;
 
 use riker::actors::ChannelRef;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::str::FromStr;
 use uuid::Uuid;
 
-pub use entity::{Entity, EntityName, Model, Query, Result, CQRS, ES};
-pub use entity_manager::Manager;
+pub use clock::{Clock, MockClock, SharedClock, SystemClock};
+pub use composite::Part;
+pub use entity::{
+    CommandError, CommandOutcome, CommandResult, Entity, EntityConfig, EntityMsg, EntityName,
+    Hydration, Load, Model, Query, Result, SerializableModel, StoreHandle, CQRS, ES,
+};
+pub use entity_manager::{
+    EntityInfo, ExportFormat, Manager, ManagerError, Peers, TransactionScope,
+};
+pub use lifecycle::{Lifecycle, SystemBus, SystemEvent, LIFECYCLE_TOPIC};
 pub use riker_es_macros as macros;
 pub use store::*;
+pub use system::{es_system, EsSystem};
 
 pub type EventBus<T> = ChannelRef<Event<T>>;
+/// Bus where stores publish commits along with their metadata
+pub type CommitBus<T> = ChannelRef<PublishedCommit<T>>;
 
+mod clock;
+mod composite;
 mod entity;
 mod entity_manager;
+mod lifecycle;
 mod store;
+mod system;
+pub mod testing;
 
 /// Events are changes to the system generated by entities after processing
 /// other events or external commands
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum Event<T: Model> {
     Create(T),
-    Change(EntityId, T::Change),
+    Change(T::Id, T::Change),
+    /// Tombstone of a deleted entity, its history is kept so it can still be
+    /// seen as it was before the deletion.
+    Delete(T::Id),
 }
 impl<T: Model> Event<T> {
-    pub fn entity_id(&self) -> EntityId {
+    pub fn entity_id(&self) -> T::Id {
         match self {
             Event::Create(e) => e.id(),
-            Event::Change(id, _) => *id,
+            Event::Change(id, _) | Event::Delete(id) => id.clone(),
         }
     }
 
     pub fn entity(&self) -> Option<T> {
         match self {
             Event::Create(e) => Some(e.clone()),
-            Event::Change(_, _) => None,
+            Event::Change(_, _) | Event::Delete(_) => None,
         }
     }
 
     pub fn change(&self) -> Option<T::Change> {
         match self {
-            Event::Create(_) => None,
+            Event::Create(_) | Event::Delete(_) => None,
             Event::Change(_, c) => Some(c.clone()),
         }
     }
+
+    pub fn kind(&self) -> EventKind {
+        match self {
+            Event::Create(_) => EventKind::Create,
+            Event::Change(_, _) => EventKind::Change,
+            Event::Delete(_) => EventKind::Delete,
+        }
+    }
+}
+
+/// The variant of an event, to select events without looking at their data
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum EventKind {
+    Create,
+    Change,
+    Delete,
 }
-impl<T: Model> From<(EntityId, T::Change)> for Event<T> {
-    fn from((id, data): (EntityId, T::Change)) -> Self {
+impl<T: Model> From<(T::Id, T::Change)> for Event<T> {
+    fn from((id, data): (T::Id, T::Change)) -> Self {
         Event::Change(id, data)
     }
 }
@@ -56,13 +96,18 @@ impl<T: Model> From<T> for Event<T> {
     }
 }
 
-/// Uniquely idenfies an entity
-#[derive(Clone, Debug, Copy, Eq, Hash, Ord, PartialEq, PartialOrd)]
+/// Uniquely idenfies an entity, it's the id models use unless they have
+/// a natural key better suited to identify them.
+#[derive(Clone, Debug, Copy, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
 pub struct EntityId(Uuid);
 impl EntityId {
     pub fn new() -> Self {
         Default::default()
     }
+
+    pub fn uuid(&self) -> Uuid {
+        self.0
+    }
 }
 impl From<String> for EntityId {
     fn from(id: String) -> Self {
@@ -79,6 +124,15 @@ impl From<Uuid> for EntityId {
         EntityId(uuid)
     }
 }
+/// Parses the UUID an id displays as, unlike `From<&str>` that derives
+/// an id from any name.
+impl FromStr for EntityId {
+    type Err = uuid::Error;
+
+    fn from_str(id: &str) -> std::result::Result<Self, Self::Err> {
+        Uuid::parse_str(id).map(EntityId)
+    }
+}
 impl fmt::Display for EntityId {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", self.0)
@@ -89,3 +143,85 @@ impl Default for EntityId {
         Uuid::new_v4().into()
     }
 }
+
+/// An `EntityId` that only identifies entities of the model `M`, models that
+/// use it as their `Model::Id` can't be queried or changed with the id of an
+/// entity of another model by mistake. It dereferences to the untyped id and
+/// is serialized just like it.
+pub struct Id<M>(EntityId, PhantomData<fn() -> M>);
+impl<M> Id<M> {
+    pub fn new() -> Self {
+        EntityId::new().into()
+    }
+
+    /// The untyped id, e.g. to hand it over where any entity is accepted
+    pub fn untyped(&self) -> EntityId {
+        self.0
+    }
+}
+impl<M> Deref for Id<M> {
+    type Target = EntityId;
+
+    fn deref(&self) -> &EntityId {
+        &self.0
+    }
+}
+impl<M> From<EntityId> for Id<M> {
+    fn from(id: EntityId) -> Self {
+        Id(id, PhantomData)
+    }
+}
+impl<M> From<Id<M>> for EntityId {
+    fn from(id: Id<M>) -> Self {
+        id.0
+    }
+}
+impl<M> FromStr for Id<M> {
+    type Err = uuid::Error;
+
+    fn from_str(id: &str) -> std::result::Result<Self, Self::Err> {
+        id.parse::<EntityId>().map(Into::into)
+    }
+}
+impl<M> Default for Id<M> {
+    fn default() -> Self {
+        Id::new()
+    }
+}
+impl<M> Clone for Id<M> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+impl<M> Copy for Id<M> {}
+impl<M> PartialEq for Id<M> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+impl<M> Eq for Id<M> {}
+impl<M> Hash for Id<M> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state)
+    }
+}
+impl<M> fmt::Debug for Id<M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Id({})", self.0)
+    }
+}
+impl<M> fmt::Display for Id<M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+impl<M> Serialize for Id<M> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+impl<'de, M> Deserialize<'de> for Id<M> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        EntityId::deserialize(deserializer).map(Into::into)
+    }
+}
@@ -0,0 +1,61 @@
+use chrono::prelude::*;
+use riker::actors::*;
+
+/// Channel where entity and store actors report their lifecycle
+pub type SystemBus = ChannelRef<SystemEvent>;
+
+/// Reserved topic lifecycle events are published to
+pub const LIFECYCLE_TOPIC: &str = "lifecycle";
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Lifecycle {
+    Started,
+    Stopped,
+    /// The entity with the given id is about to be purged for being idle or
+    /// deleted longer than the store retains it
+    Expired(String),
+    /// Committing a change of the entity with the given id took longer than
+    /// the threshold the store was configured with
+    SlowCommit {
+        id: String,
+        took: std::time::Duration,
+    },
+    /// A commit of the entity with the given id leads to a state that breaks
+    /// the invariants of its model
+    InvariantViolated {
+        id: String,
+        reason: String,
+    },
+    /// A commit of the entity with the given id that was spilled while the
+    /// backend was unavailable failed once it was flushed to the backend
+    SpillFailed {
+        id: String,
+        reason: String,
+    },
+}
+
+/// Notifies that an actor of the system changed its state, a restarted actor
+/// will report that it started again without having reported it stopped.
+#[derive(Clone, Debug)]
+pub struct SystemEvent {
+    pub name: String,
+    pub kind: Lifecycle,
+    pub when: DateTime<Utc>,
+}
+
+pub(crate) fn notify(bus: &Option<SystemBus>, name: &str, kind: Lifecycle) {
+    if let Some(bus) = bus {
+        trace!("{} {:?}", name, kind);
+        bus.tell(
+            Publish {
+                topic: LIFECYCLE_TOPIC.into(),
+                msg: SystemEvent {
+                    name: name.into(),
+                    kind,
+                    when: Utc::now(),
+                },
+            },
+            None,
+        );
+    }
+}
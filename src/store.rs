@@ -1,80 +1,628 @@
-use crate::{EntityId, Event, EventBus, Model};
+use crate::entity_manager::ask;
+use crate::lifecycle::{notify, Lifecycle, SystemBus};
+use crate::{CommitBus, EntityId, Event, EventBus, EventKind, Model, SharedClock};
 use async_trait::async_trait;
 use chrono::prelude::*;
-use futures::future::ok;
-use futures::stream::{BoxStream, StreamExt, TryStreamExt};
+use futures::channel::{mpsc, oneshot};
+use futures::future::{ok, ready, BoxFuture, FutureExt, Ready};
+use futures::stream::{BoxStream, Stream, StreamExt, TryStreamExt};
 use riker::actors::*;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::any::Any;
+use std::cmp::Ordering;
+use std::collections::{HashMap, VecDeque};
 use std::fmt;
+use std::io::{BufRead, Write};
 use std::ops::Deref;
+use std::pin::Pin;
+use std::sync::{Arc, Weak};
+use std::task::{self, Poll};
 use thiserror::Error;
+use uuid::Uuid;
 
-pub use in_memory::MemStore;
+#[cfg(feature = "redis")]
+pub use self::redis::RedisStore;
+pub use aspect::{Aspect, AspectVersionedStore};
+pub use blob::{BlobRef, BlobStore, FsBlobStore, MemBlobStore};
+pub use chain::CommitChain;
+pub use file::FileStore;
+pub use id_codec::{IdCodec, StringId, UuidBytes};
+pub use in_memory::{MemLimit, MemPolicy, MemStats, MemStore};
+pub use intercepted::{CommitInterceptor, InterceptedStore};
+#[cfg(feature = "kafka")]
+pub use kafka::KafkaStore;
+pub use limited::SizeLimitedStore;
+#[cfg(feature = "otel")]
+pub use otel::OtelStore;
+#[cfg(feature = "postgres")]
+pub use postgres::PgStore;
+pub use recording::{replay_recording, RecordingStore};
+pub use replicated::ReplicatedStore;
+pub use snapshot::{
+    Bson, Codec, Json, MemSnapshotStore, Snapshot, SnapshotStore, SnapshottingStore,
+};
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStore;
+pub use timed::{LatencyObserver, Operation, TimedStore};
+pub use verify::VerifyReport;
+pub use working_copy::WorkingCopy;
 
+mod aspect;
+mod blob;
+mod chain;
+mod file;
+mod id_codec;
 mod in_memory;
+mod intercepted;
+#[cfg(feature = "kafka")]
+mod kafka;
+mod limited;
+#[cfg(feature = "otel")]
+mod otel;
+#[cfg(feature = "postgres")]
+mod postgres;
+mod recording;
+#[cfg(feature = "redis")]
+mod redis;
+mod replicated;
+mod snapshot;
+#[cfg(any(feature = "postgres", feature = "sqlite"))]
+mod sql;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod timed;
+mod verify;
+mod working_copy;
 
 #[async_trait]
 pub trait CommitStore<M: Model>: fmt::Debug + Clone + Send + Sync + 'static {
-    fn keys(&self) -> BoxStream<CommitResult<EntityId>>;
+    fn keys(&self) -> BoxStream<CommitResult<M::Id>>;
 
-    fn change_list(&self, id: EntityId) -> BoxStream<CommitResult<Commit<M>>>;
+    fn change_list(&self, id: M::Id) -> BoxStream<CommitResult<Commit<M>>>;
 
     async fn commit(&self, c: Commit<M>) -> CommitResult<()>;
 
+    /// Installs the whole ordered history of an entity keeping its original
+    /// timestamps and metadata, it fails if the entity exists unless `force`d.
+    /// The default commits one by one so readers might see intermediate
+    /// states, backends should override it to make the import atomic.
+    async fn import_entity(
+        &self,
+        id: M::Id,
+        history: Vec<Commit<M>>,
+        force: bool,
+    ) -> CommitResult<()> {
+        check_history(&id, &history)?;
+        if !force && self.change_list(id).try_next().await?.is_some() {
+            return Err(CommitError::AlreadyExists);
+        }
+        for c in history {
+            self.commit(c).await?;
+        }
+        Ok(())
+    }
+
+    /// Persists commits of possibly different entities as a whole, changes
+    /// can target entities created earlier in the same batch.
+    /// The default commits one by one so readers might see intermediate
+    /// states and a failure keeps the commits before it, backends should
+    /// override it to make the batch atomic, e.g. with a single transaction
+    /// or multi-row insert instead of a round-trip per commit.
+    async fn commit_all(&self, commits: Vec<Commit<M>>) -> CommitResult<()> {
+        for c in commits {
+            self.commit(c).await?;
+        }
+        Ok(())
+    }
+
+    /// Checks the backend is reachable, stores configured with an `Outage`
+    /// policy ping it before committing. Backends that can't be unavailable
+    /// keep the default.
+    async fn ping(&self) -> CommitResult<()> {
+        Ok(())
+    }
+
+    /// Called once every commit received before it has been persisted so
+    /// backends can persist what they need for a consistent backup at `at`.
+    async fn checkpoint(&self, _at: DateTime<Utc>) -> CommitResult<()> {
+        Ok(())
+    }
+
+    /// Removes an entity along with its whole history
+    async fn purge(&self, _id: M::Id) -> CommitResult<()> {
+        Err(CommitError::Unsupported("purge"))
+    }
+
+    /// How many entities existed at the given moment, created by then and not
+    /// deleted yet. Purged entities are gone along with their history so they
+    /// never count. By default it reads the history of every entity.
+    async fn count_at(&self, time: DateTime<Utc>) -> CommitResult<usize> {
+        self.keys()
+            .and_then(|id| self.change_list(id).try_collect::<Vec<_>>())
+            .try_fold(0, |count, history| {
+                ok(if existed_at(&history, time) {
+                    count + 1
+                } else {
+                    count
+                })
+            })
+            .await
+    }
+
+    /// Checks the history of an entity for problems that would make folding
+    /// it give wrong results, like commits out of order or repeated.
+    async fn verify(&self, id: M::Id) -> CommitResult<VerifyReport> {
+        let history: Vec<_> = self.change_list(id.clone()).try_collect().await?;
+        Ok(verify::verify_history(&id, &history))
+    }
+
+    /// Fixes the timestamps of commits out of order without changing the
+    /// order of the history, returning what was found before repairing it.
+    /// Other problems are reported but left for an operator to decide.
+    async fn repair(&self, id: M::Id) -> CommitResult<VerifyReport> {
+        let mut history: Vec<_> = self.change_list(id.clone()).try_collect().await?;
+        let report = verify::verify_history(&id, &history);
+        if !report.out_of_order.is_empty() {
+            verify::reorder_timestamps(&mut history);
+            self.import_entity(id, history, true).await?;
+        }
+        Ok(report)
+    }
+
+    /// A copy of the current state of an entity to try changes on before
+    /// committing them all together or discarding them.
+    async fn working_copy(&self, id: M::Id) -> CommitResult<WorkingCopy<M, Self>> {
+        WorkingCopy::new(self.clone(), id).await
+    }
+
+    /// Names a moment of the log, e.g. a release or the end of a day, so
+    /// entities can be queried as they were then. Tagging again moves the tag.
+    async fn tag(&self, _name: String, _at: DateTime<Utc>) -> CommitResult<()> {
+        Err(CommitError::Unsupported("tag"))
+    }
+
+    /// The moment a tag names, `NotFound` if there's no such tag
+    async fn resolve_tag(&self, _name: &str) -> CommitResult<DateTime<Utc>> {
+        Err(CommitError::Unsupported("tag"))
+    }
+
     fn entities(&self) -> BoxStream<CommitResult<TimeTraveler<'_, M>>> {
         self.keys().and_then(move |id| self.get(id)).boxed()
     }
 
-    async fn get(&self, id: EntityId) -> CommitResult<TimeTraveler<'_, M>> {
-        let mut changes = self.change_list(id);
-        let model = changes
-            .try_next()
-            .await?
-            .ok_or(CommitError::NotFound)?
+    async fn get(&self, id: M::Id) -> CommitResult<TimeTraveler<'_, M>> {
+        let mut changes = self.change_list(id.clone());
+        let first = changes.try_next().await?.ok_or(CommitError::NotFound)?;
+        // first change has to be the entity
+        let model = first
             .entity()
-            // first change has to be the entity
-            .unwrap();
-        Ok(TimeTraveler { changes, model })
+            .ok_or_else(|| CommitError::InvalidHistory(id.to_string()))?;
+        let snapshot = match self.load_snapshot(id.clone()).await? {
+            Some(snapshot) => {
+                let version = snapshot.version as usize;
+                Some((snapshot, self.change_list(id).skip(version).boxed()))
+            }
+            None => None,
+        };
+        Ok(TimeTraveler {
+            created: Some(first.when()),
+            snapshot,
+            ..TimeTraveler::new(model, changes)
+        })
     }
 
-    async fn snapshot(&self, id: EntityId, time: DateTime<Utc>) -> CommitResult<M> {
+    /// Keeps the state of an entity after its first `version` commits so
+    /// reading it later only applies newer ones, stores don't keep snapshots
+    /// unless they override it.
+    async fn save_snapshot(&self, _snapshot: Snapshot<M>) -> CommitResult<()> {
+        Ok(())
+    }
+
+    /// The latest snapshot kept of an entity, `get` starts from it when
+    /// travelling to a moment at or after it was taken.
+    async fn load_snapshot(&self, _id: M::Id) -> CommitResult<Option<Snapshot<M>>> {
+        Ok(None)
+    }
+
+    async fn snapshot(&self, id: M::Id, time: DateTime<Utc>) -> CommitResult<M> {
         self.get(id).await?.travel_to(time).await
     }
+
+    /// State of the entity after its first `version` commits, see
+    /// `TimeTraveler::travel_to_version`.
+    async fn snapshot_at_version(&self, id: M::Id, version: u64) -> CommitResult<M> {
+        self.get(id).await?.travel_to_version(version).await
+    }
+
+    /// Applies the changes of an entity to the given state instead of the
+    /// one it was created with, handy to answer "what if" questions.
+    async fn replay_from(&self, id: M::Id, initial: M) -> CommitResult<M> {
+        let traveler = self.get(id).await?;
+        TimeTraveler {
+            model: initial,
+            snapshot: None,
+            ..traveler
+        }
+        .to_present()
+        .await
+    }
+
+    /// State of the entity right after the first commit that matches the
+    /// predicate, e.g. to review the entity as it was after someone's change.
+    async fn snapshot_after<P>(&self, id: M::Id, pred: P) -> CommitResult<M>
+    where
+        P: Fn(&Commit<M>) -> bool + Send + Sync + 'static,
+    {
+        let mut commits = self.change_list(id.clone());
+        let first = commits.try_next().await?.ok_or(CommitError::NotFound)?;
+        let mut model = first
+            .entity()
+            .ok_or_else(|| CommitError::InvalidHistory(id.to_string()))?;
+        if pred(&first) {
+            return Ok(model);
+        }
+        while let Some(c) = commits.try_next().await? {
+            if let Some(change) = c.change() {
+                model.apply_change(&change);
+            }
+            if pred(&c) {
+                return Ok(model);
+            }
+        }
+        Err(CommitError::NotFound)
+    }
+
+    /// Commits made since the given moment whose event is of one of the
+    /// given kinds, entity after entity. By default it filters the history of
+    /// every entity, backends that index the kind should do better.
+    fn commits_since(
+        &self,
+        since: DateTime<Utc>,
+        kinds: Vec<EventKind>,
+    ) -> BoxStream<CommitResult<Commit<M>>> {
+        let kinds = Arc::new(kinds);
+        self.keys()
+            .map_ok(move |id| {
+                let kinds = kinds.clone();
+                self.change_list(id)
+                    .try_filter(move |c| ready(c.when() >= since && kinds.contains(&c.kind())))
+            })
+            .try_flatten()
+            .boxed()
+    }
+
+    /// Folds the state every entity had at the given moment into a single
+    /// value, e.g. to sum a field for a dashboard without listing them all.
+    /// Entities created after that moment or deleted by then are left out.
+    async fn aggregate<A, F>(&self, time: DateTime<Utc>, init: A, f: F) -> CommitResult<A>
+    where
+        A: Send + 'static,
+        F: Fn(A, &M) -> A + Send + Sync + 'static,
+    {
+        self.keys()
+            .try_filter_map(|id| async move {
+                let mut changes = self.change_list(id);
+                let created = match changes.try_next().await? {
+                    Some(c) if c.when() <= time => c.entity(),
+                    // created later
+                    _ => None,
+                };
+                match created {
+                    Some(model) => match TimeTraveler::new(model, changes).travel_to(time).await {
+                        Err(CommitError::Deleted) => Ok(None),
+                        res => res.map(Some),
+                    },
+                    None => Ok(None),
+                }
+            })
+            .try_fold(init, |acc, model| ok(f(acc, &model)))
+            .await
+    }
+
+    /// Writes every entity along with its whole history as of the moment the
+    /// export starts, one JSON encoded list of commits per line. The archive
+    /// can be imported to a store of any type, returns the exported entities.
+    async fn export<W>(&self, mut writer: W) -> CommitResult<usize>
+    where
+        W: Write + Send,
+        M: Serialize,
+        M::Id: Serialize,
+        M::Change: Serialize,
+    {
+        let at = Utc::now();
+        let archive = |e: std::io::Error| CommitError::Archive(e.to_string());
+        let mut keys = self.keys();
+        let mut exported = 0;
+        while let Some(id) = keys.try_next().await? {
+            let history = self
+                .change_list(id)
+                .try_filter(|c| ready(c.when() <= at))
+                .try_collect::<Vec<_>>()
+                .await?;
+            if !history
+                .first()
+                .is_some_and(|c| c.kind() == EventKind::Create)
+            {
+                // created after the export started
+                continue;
+            }
+            serde_json::to_writer(&mut writer, &history)
+                .map_err(|e| CommitError::Codec(e.to_string()))?;
+            writer.write_all(b"\n").map_err(archive)?;
+            exported += 1;
+        }
+        writer.flush().map_err(archive)?;
+        Ok(exported)
+    }
+
+    /// Installs every entity of an archive written by `export` keeping the
+    /// timestamps, order and metadata of their commits. Entities are imported
+    /// one by one and it fails on the first one that already exists.
+    /// Returns the imported entities.
+    async fn import<R>(&self, reader: R) -> CommitResult<usize>
+    where
+        R: BufRead + Send,
+        M: DeserializeOwned,
+        M::Id: DeserializeOwned,
+        M::Change: DeserializeOwned,
+    {
+        let mut imported = 0;
+        for line in reader.lines() {
+            let line = line.map_err(|e| CommitError::Archive(e.to_string()))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let history: Vec<Commit<M>> =
+                serde_json::from_str(&line).map_err(|e| CommitError::Codec(e.to_string()))?;
+            let id = match history.first() {
+                Some(c) => c.entity_id(),
+                None => return Err(CommitError::Archive("entity without commits".into())),
+            };
+            self.import_entity(id, history, false).await?;
+            imported += 1;
+        }
+        Ok(imported)
+    }
+
+    /// The commit that brings an entity back to the state it had at the given
+    /// moment without rewriting its history, it's up to the caller to commit
+    /// it. Models have to provide the change, see `Model::reset_change`.
+    /// The commit expects the version of the present state it was computed
+    /// from, so it conflicts if the entity changed in the meantime.
+    async fn compensate(&self, id: M::Id, at: DateTime<Utc>) -> CommitResult<Commit<M>> {
+        let past = self.snapshot(id.clone(), at).await?;
+        let history: Vec<_> = self.change_list(id.clone()).try_collect().await?;
+        let version = history.len() as u64;
+        let mut history = history.into_iter();
+        let model = history
+            .next()
+            .ok_or(CommitError::NotFound)?
+            .entity()
+            .ok_or_else(|| CommitError::InvalidHistory(id.to_string()))?;
+        let changes = futures::stream::iter(history.map(Ok)).boxed();
+        let present = TimeTraveler::new(model, changes).to_present().await?;
+        let change = present
+            .reset_change(&past)
+            .ok_or(CommitError::Unsupported("reset"))?;
+        let why = format!("revert to {}", at.to_rfc3339());
+        let mut c = Commit::new(Event::Change(id, change), None, Some(why));
+        c.set_expected_version(Some(version));
+        Ok(c)
+    }
+
+    /// Ids of the entities whose list of commits matches the predicate.
+    /// By default it scans the whole history of every entity, backends might
+    /// not be able to do any better so use it with care.
+    fn find_by_history<P>(&self, pred: P) -> BoxStream<CommitResult<M::Id>>
+    where
+        P: Fn(&[Commit<M>]) -> bool + Send + Sync + 'static,
+    {
+        let pred = Arc::new(pred);
+        self.keys()
+            .try_filter_map(move |id| {
+                let pred = pred.clone();
+                async move {
+                    let commits = self.change_list(id.clone()).try_collect::<Vec<_>>().await?;
+                    Ok(if pred(&commits) { Some(id) } else { None })
+                }
+            })
+            .boxed()
+    }
 }
 
 pub type CommitResult<T> = Result<T, CommitError>;
 
-#[derive(Error, Clone, Debug)]
+#[derive(Error, Clone, Debug, Eq, PartialEq)]
 pub enum CommitError {
     #[error("Cant change non existing entity")]
     CantChange,
     #[error("Didn't find commit for entity")]
     NotFound,
+    #[error("Expected entity at version {expected} but found {actual}")]
+    Conflict { expected: u64, actual: u64 },
+    #[error("Commit still conflicting after {0} attempts")]
+    ConflictExhausted(u32),
+    #[error("Couldn't encode or decode: {0}")]
+    Codec(String),
+    #[error("Event takes {size} bytes, more than the limit of {limit}")]
+    PayloadTooLarge { size: usize, limit: usize },
+    #[error("Backend doesn't support {0}")]
+    Unsupported(&'static str),
+    #[error("Entity already exists")]
+    AlreadyExists,
+    #[error("History of {0} doesn't start with its creation or has foreign changes")]
+    InvalidHistory(String),
+    #[error("Couldn't access recording: {0}")]
+    Recording(String),
+    #[error("Couldn't access attachment: {0}")]
+    Blob(String),
+    #[error("Commit breaks an invariant: {0}")]
+    Invariant(String),
+    #[error("Backend is unavailable")]
+    Unavailable,
+    #[error("Couldn't read or write archive: {0}")]
+    Archive(String),
+    #[error("Couldn't read or write event log: {0}")]
+    Log(String),
+    #[error("Couldn't query database: {0}")]
+    Database(String),
+    #[error("Store is over its memory limit of {0} bytes")]
+    MemoryLimit(usize),
+    #[error("Entity was deleted")]
+    Deleted,
+}
+
+/// Whether the commit can follow the `version` commits of its entity, the
+/// last of them being a deletion or not.
+pub(crate) fn check_append<M: Model>(
+    c: &Commit<M>,
+    version: u64,
+    deleted: bool,
+) -> CommitResult<()> {
+    match c.event {
+        Event::Create(_) if version > 0 => return Err(CommitError::AlreadyExists),
+        Event::Change(_, _) | Event::Delete(_) if version == 0 || deleted => {
+            return Err(CommitError::CantChange)
+        }
+        _ => {}
+    }
+    match c.expected_version() {
+        Some(expected) if expected != version => Err(CommitError::Conflict {
+            expected,
+            actual: version,
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// An imported history has to start creating the entity followed by changes to it
+pub(crate) fn check_history<M: Model>(id: &M::Id, history: &[Commit<M>]) -> CommitResult<()> {
+    match history.split_first() {
+        Some((first, rest))
+            if matches!(first.event, Event::Create(_))
+                && first.entity_id() == *id
+                && rest.iter().enumerate().all(|(i, c)| match &c.event {
+                    Event::Change(cid, _) => cid == id,
+                    // nothing happens to an entity after it's deleted
+                    Event::Delete(cid) => cid == id && i + 1 == rest.len(),
+                    Event::Create(_) => false,
+                }) =>
+        {
+            Ok(())
+        }
+        _ => Err(CommitError::InvalidHistory(id.to_string())),
+    }
+}
+
+/// Whether the entity with the history was created by the moment and not
+/// deleted yet.
+pub(crate) fn existed_at<M: Model>(history: &[Commit<M>], time: DateTime<Utc>) -> bool {
+    let deleted = |c: &Commit<M>| c.kind() == EventKind::Delete && c.when() <= time;
+    history.first().is_some_and(|c| c.when() <= time) && !history.iter().any(deleted)
 }
 
+/// How far in the future commits can be and still be part of the present,
+/// clocks of the machines making commits are never perfectly in sync.
+pub const CLOCK_SKEW_TOLERANCE_MS: i64 = 500;
+
 /// A wrapper for a stored entity that applies changes until the specified moment in time.
 pub struct TimeTraveler<'a, M: Model> {
     model: M,
-    changes: BoxStream<'a, CommitResult<Commit<M>>>,
+    changes: Changes<'a, M>,
+    skew: chrono::Duration,
+    created: Option<DateTime<Utc>>,
+    // a snapshot of the entity with the changes made after it
+    snapshot: Option<(Snapshot<M>, Changes<'a, M>)>,
 }
 
+type Changes<'a, M> = BoxStream<'a, CommitResult<Commit<M>>>;
+
 impl<'a, M: Model> TimeTraveler<'a, M> {
+    pub(crate) fn new(model: M, changes: BoxStream<'a, CommitResult<Commit<M>>>) -> Self {
+        TimeTraveler {
+            model,
+            changes,
+            skew: chrono::Duration::milliseconds(CLOCK_SKEW_TOLERANCE_MS),
+            created: None,
+            snapshot: None,
+        }
+    }
+
+    /// When the entity was created if the traveler knows it
+    pub fn created(&self) -> Option<DateTime<Utc>> {
+        self.created
+    }
+
+    /// Changes how far in the future commits can be to be seen by `to_present`
+    pub fn with_skew_tolerance(mut self, skew: chrono::Duration) -> Self {
+        self.skew = skew;
+        self
+    }
+
+    /// The entity with every change made until now, including those whose
+    /// timestamp is slightly ahead of the local clock.
     pub async fn to_present(self) -> CommitResult<M> {
-        self.travel_to(Utc::now()).await
+        let until = Utc::now() + self.skew;
+        self.travel_to(until).await
     }
 
-    pub async fn travel_to(self, _until: DateTime<Utc>) -> CommitResult<M> {
-        let model = self
-            .changes
-            .try_fold(self.model, |mut m, c| {
-                let change = c.change().unwrap();
-                m.apply_change(&change);
-                ok(m)
-            })
+    /// The entity with the changes made until the given moment, included.
+    /// Only the changes made after the snapshot of the entity are applied
+    /// when the moment is at or after the snapshot. There's no entity before
+    /// the moment it was created.
+    pub async fn travel_to(self, until: DateTime<Utc>) -> CommitResult<M> {
+        if self.created.is_some_and(|created| created > until) {
+            return Err(CommitError::NotFound);
+        }
+        let (model, changes) = match self.snapshot {
+            Some((snapshot, changes)) if snapshot.when <= until => (snapshot.model, changes),
+            _ => (self.model, self.changes),
+        };
+        let model = changes
+            // in domain order timestamps aren't necessarily increasing
+            .try_filter(|c| ready(c.when() <= until))
+            .try_fold(model, apply_commit)
+            .await?;
+        Ok(model)
+    }
+
+    /// The entity after its first `version` commits, the creation being the
+    /// first one. Unlike moments, the position of a commit in the history of
+    /// its entity is unambiguous even when several commits share the same
+    /// timestamp. There's no entity at version 0 and versions past the end of
+    /// the history give the latest state.
+    pub async fn travel_to_version(self, version: u64) -> CommitResult<M> {
+        if version == 0 {
+            return Err(CommitError::NotFound);
+        }
+        let (model, changes, applied) = match self.snapshot {
+            Some((snapshot, changes)) if snapshot.version <= version => {
+                (snapshot.model, changes, snapshot.version)
+            }
+            _ => (self.model, self.changes, 1),
+        };
+        let model = changes
+            .take((version - applied) as usize)
+            .try_fold(model, apply_commit)
             .await?;
         Ok(model)
     }
 }
 
+/// Applies the change of a commit, a history with more than one creation is
+/// as invalid as one that doesn't start with it. There's no state after the
+/// entity is deleted.
+fn apply_commit<M: Model>(mut model: M, c: Commit<M>) -> Ready<CommitResult<M>> {
+    ready(match &c.event {
+        Event::Change(_, change) => {
+            model.apply_change(change);
+            Ok(model)
+        }
+        Event::Delete(_) => Err(CommitError::Deleted),
+        Event::Create(_) => Err(CommitError::InvalidHistory(c.entity_id().to_string())),
+    })
+}
+
 impl<M: Model> fmt::Debug for TimeTraveler<'_, M> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "TimeTraveler({:?})", self.model)
@@ -86,7 +634,225 @@ impl<M: Model> fmt::Debug for TimeTraveler<'_, M> {
 #[derive(Debug)]
 pub struct Store<M: Model, S: CommitStore<M>> {
     bus: Option<EventBus<M>>,
+    commit_bus: Option<CommitBus<M>>,
+    invalidations: Option<InvalidationBus<M::Id>>,
+    clock: Option<SharedClock>,
+    in_flight: InFlight,
+    sys_bus: Option<SystemBus>,
+    name: String,
     backend: S,
+    prefix_subs: Vec<(String, BoxedTell<Event<M>>)>,
+    entity_subs: EntitySubs,
+    ttl: Option<Ttl>,
+    retention: Option<Retention>,
+    slow_commit: Option<std::time::Duration>,
+    parallelism: usize,
+    invariants: Option<InvariantPolicy>,
+    outage: Option<Outage>,
+    consistency: ReadConsistency,
+    spillover: Arc<std::sync::Mutex<Spillover<M>>>,
+}
+
+/// Purges entities whose latest commit is older than `idle`, looking for
+/// them `every` so often.
+#[derive(Debug, Clone, Copy)]
+pub struct Ttl {
+    pub idle: chrono::Duration,
+    pub every: std::time::Duration,
+}
+
+/// Purges deleted entities once they were deleted longer than `grace` ago,
+/// looking for them `every` so often. Until then the history of the entity
+/// is kept so it can still be rebuilt as of before the deletion.
+#[derive(Debug, Clone, Copy)]
+pub struct Retention {
+    pub grace: chrono::Duration,
+    pub every: std::time::Duration,
+}
+
+/// What a store does with commits that lead to a state breaking the
+/// invariants of the model, stores don't check them unless configured.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum InvariantPolicy {
+    /// Persist the commit anyway, warning about the violation and reporting
+    /// it to the system bus of the store if it has one
+    Log,
+    /// Don't persist the commit, replying `CommitError::Invariant`
+    Reject,
+}
+
+/// How a store behaves while its backend is unavailable, it pings the
+/// backend before every commit and `probe_every` so often once degraded
+/// until the backend is back.
+#[derive(Debug, Clone, Copy)]
+pub struct Outage {
+    pub degradation: Degradation,
+    pub probe_every: std::time::Duration,
+}
+
+/// What a store does with commits while its backend is unavailable
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Degradation {
+    /// Don't persist the commit, replying `CommitError::Unavailable`
+    Reject,
+    /// Keep the commit in memory replying it was accepted, spilled commits
+    /// are persisted in the order they arrived when the backend is back and
+    /// published to subscribers only then. Commits expecting a version are
+    /// rejected as their version can't be checked until then, spilled
+    /// commits failing once flushed are reported to the system bus.
+    Spillover,
+}
+
+/// Commits waiting for the backend to be available again
+#[derive(Debug)]
+struct Spillover<M: Model> {
+    degraded: bool,
+    flushing: bool,
+    commits: VecDeque<Commit<M>>,
+}
+
+impl<M: Model> Default for Spillover<M> {
+    fn default() -> Self {
+        Spillover {
+            degraded: false,
+            flushing: false,
+            commits: VecDeque::new(),
+        }
+    }
+}
+
+/// What lists of entities see of the commits the store is persisting while
+/// they are asked for. Every entity of a list is rebuilt as of the same
+/// moment regardless of the consistency, entities created after it are left
+/// out and so are the changes made after it.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ReadConsistency {
+    /// Read right away, commits received before the list but not persisted
+    /// yet might be missing from it
+    Eventual,
+    /// Wait for the commits received before the list to be persisted, the
+    /// ones received later are still left out if made after the list moment
+    Settled,
+}
+
+/// Options of a `Store`, none of them is set by default. Entities pass the
+/// config they are given to their store, see `EntityConfig::store`.
+#[derive(Debug, Clone)]
+pub struct StoreConfig<M: Model> {
+    bus: Option<EventBus<M>>,
+    commit_bus: Option<CommitBus<M>>,
+    invalidations: Option<InvalidationBus<M::Id>>,
+    clock: Option<SharedClock>,
+    sys_bus: Option<SystemBus>,
+    ttl: Option<Ttl>,
+    retention: Option<Retention>,
+    slow_commit: Option<std::time::Duration>,
+    parallelism: usize,
+    invariants: Option<InvariantPolicy>,
+    outage: Option<Outage>,
+    consistency: ReadConsistency,
+}
+
+impl<M: Model> StoreConfig<M> {
+    /// Publishes the events of the commits to the `{store}-events` topic
+    pub fn event_bus(mut self, bus: EventBus<M>) -> Self {
+        self.bus = Some(bus);
+        self
+    }
+
+    /// Publishes commits along with their metadata
+    pub fn commit_bus(mut self, bus: CommitBus<M>) -> Self {
+        self.commit_bus = Some(bus);
+        self
+    }
+
+    /// Publishes the new version of the entities that are committed to
+    pub fn invalidations(mut self, bus: InvalidationBus<M::Id>) -> Self {
+        self.invalidations = Some(bus);
+        self
+    }
+
+    /// Timestamps commits and answers queries as of the time it tells
+    pub fn clock(mut self, clock: SharedClock) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Reports the lifecycle of the store and what goes wrong with it
+    pub fn sys_bus(mut self, bus: SystemBus) -> Self {
+        self.sys_bus = Some(bus);
+        self
+    }
+
+    pub fn ttl(mut self, ttl: Ttl) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    pub fn retention(mut self, retention: Retention) -> Self {
+        self.retention = Some(retention);
+        self
+    }
+
+    /// Commits taking longer than the threshold are logged as a warning and
+    /// reported to the system bus of the store if it has one.
+    pub fn slow_commit(mut self, threshold: std::time::Duration) -> Self {
+        self.slow_commit = Some(threshold);
+        self
+    }
+
+    /// How many entities lists rebuild at the same time, one by default
+    pub fn parallelism(mut self, parallelism: usize) -> Self {
+        self.parallelism = parallelism.max(1);
+        self
+    }
+
+    pub fn invariants(mut self, policy: InvariantPolicy) -> Self {
+        self.invariants = Some(policy);
+        self
+    }
+
+    pub fn outage(mut self, outage: Outage) -> Self {
+        self.outage = Some(outage);
+        self
+    }
+
+    /// `ReadConsistency::Eventual` by default
+    pub fn consistency(mut self, consistency: ReadConsistency) -> Self {
+        self.consistency = consistency;
+        self
+    }
+
+    /// Takes the system bus and clock of the entity the store belongs to
+    /// unless it's configured with its own
+    pub(crate) fn inherit(
+        mut self,
+        sys_bus: Option<SystemBus>,
+        clock: Option<SharedClock>,
+    ) -> Self {
+        self.sys_bus = self.sys_bus.or(sys_bus);
+        self.clock = self.clock.or(clock);
+        self
+    }
+}
+
+impl<M: Model> Default for StoreConfig<M> {
+    fn default() -> Self {
+        StoreConfig {
+            bus: None,
+            commit_bus: None,
+            invalidations: None,
+            clock: None,
+            sys_bus: None,
+            ttl: None,
+            retention: None,
+            slow_commit: None,
+            parallelism: 1,
+            invariants: None,
+            outage: None,
+            consistency: ReadConsistency::Eventual,
+        }
+    }
 }
 
 pub type StoreRef<A> = ActorRef<StoreMsg<A>>;
@@ -98,12 +864,49 @@ where
 {
     type Msg = StoreMsg<M>;
 
+    fn pre_start(&mut self, cx: &Context<Self::Msg>) {
+        self.name = cx.myself().name().into();
+        notify(&self.sys_bus, &self.name, Lifecycle::Started);
+        if let Some(ttl) = self.ttl {
+            cx.schedule(ttl.every, ttl.every, cx.myself(), None, ExpireIdle);
+        }
+        if let Some(retention) = self.retention {
+            let every = retention.every;
+            cx.schedule(every, every, cx.myself(), None, PurgeDeleted);
+        }
+        if let Some(outage) = self.outage {
+            let every = outage.probe_every;
+            cx.schedule(every, every, cx.myself(), None, ProbeBackend);
+        }
+    }
+
+    fn post_stop(&mut self) {
+        notify(&self.sys_bus, &self.name, Lifecycle::Stopped);
+    }
+
     fn recv(&mut self, cx: &Context<Self::Msg>, msg: Self::Msg, sender: Sender) {
         match msg {
             StoreMsg::Commit(msg) => self.receive(cx, msg, sender),
-            StoreMsg::Subscribe(msg) => self.receive(cx, msg, sender),
+            StoreMsg::Subscribe(msg) => self.subscribe(cx, msg, sender),
             StoreMsg::Snapshot(msg) => self.receive(cx, msg, sender),
+            StoreMsg::SnapshotAtVersion(msg) => self.receive(cx, msg, sender),
             StoreMsg::SnapshotList(msg) => self.receive(cx, msg, sender),
+            StoreMsg::CancellableSnapshotList(msg) => self.receive(cx, msg, sender),
+            StoreMsg::SortedSnapshotList(msg) => self.receive(cx, msg, sender),
+            StoreMsg::LimitedSnapshotList(msg) => self.receive(cx, msg, sender),
+            StoreMsg::SnapshotPage(msg) => self.receive(cx, msg, sender),
+            StoreMsg::History(msg) => self.receive(cx, msg, sender),
+            StoreMsg::Checkpoint(msg) => self.receive(cx, msg, sender),
+            StoreMsg::RecentHistory(msg) => self.receive(cx, msg, sender),
+            StoreMsg::AtTag(msg) => self.receive(cx, msg, sender),
+            StoreMsg::CommitsSince(msg) => self.receive(cx, msg, sender),
+            StoreMsg::Import(msg) => self.receive(cx, msg, sender),
+            StoreMsg::Batch(msg) => self.receive(cx, msg, sender),
+            StoreMsg::Revert(msg) => self.receive(cx, msg, sender),
+            StoreMsg::ExpireIdle(msg) => self.receive(cx, msg, sender),
+            StoreMsg::PurgeDeleted(msg) => self.receive(cx, msg, sender),
+            StoreMsg::ProbeBackend(msg) => self.receive(cx, msg, sender),
+            StoreMsg::SubscribePrefix(prefix, actor) => self.prefix_subs.push((prefix, actor)),
         };
     }
 }
@@ -114,7 +917,7 @@ where
     S: CommitStore<M>,
 {
     fn create_args(backend: S) -> Self {
-        Store { backend, bus: None }
+        Self::create_args((backend, StoreConfig::default()))
     }
 }
 
@@ -124,9 +927,35 @@ where
     S: CommitStore<M>,
 {
     fn create_args((backend, bus): (S, EventBus<M>)) -> Self {
+        Self::create_args((backend, StoreConfig::default().event_bus(bus)))
+    }
+}
+
+impl<M, S> ActorFactoryArgs<(S, StoreConfig<M>)> for Store<M, S>
+where
+    M: Model,
+    S: CommitStore<M>,
+{
+    fn create_args((backend, config): (S, StoreConfig<M>)) -> Self {
         Store {
             backend,
-            bus: Some(bus),
+            bus: config.bus,
+            commit_bus: config.commit_bus,
+            invalidations: config.invalidations,
+            clock: config.clock,
+            in_flight: InFlight::default(),
+            sys_bus: config.sys_bus,
+            name: String::new(),
+            prefix_subs: vec![],
+            entity_subs: Default::default(),
+            ttl: config.ttl,
+            retention: config.retention,
+            slow_commit: config.slow_commit,
+            parallelism: config.parallelism,
+            invariants: config.invariants,
+            outage: config.outage,
+            consistency: config.consistency,
+            spillover: Default::default(),
         }
     }
 }
@@ -137,253 +966,2956 @@ where
     S: CommitStore<M>,
 {
     type Msg = StoreMsg<M>;
-    fn receive(&mut self, cx: &Context<Self::Msg>, c: Commit<M>, _sender: Sender) {
+    fn receive(&mut self, cx: &Context<Self::Msg>, mut c: Commit<M>, sender: Sender) {
+        if let Some(clock) = &self.clock {
+            c.set_when(clock.now());
+        }
         trace!("storing {:?}", c);
         let store = self.backend.clone();
         let id = c.entity_id();
-        let bus = self.bus.clone();
-        let topic_name = format!("{}-events", cx.myself().name());
-        let event = c.event.clone();
+        let subscribers = self.subscribers(cx, &id);
+        let published = c.clone();
+        let in_flight = self.in_flight.start();
+        let slow_commit = self.slow_commit;
+        let sys_bus = self.sys_bus.clone();
+        let name = self.name.clone();
+        let invariants = self.invariants;
+        let outage = self.outage.map(|o| (o.degradation, self.spillover.clone()));
         cx.system.exec.spawn_ok(async move {
-            store.commit(c).await.expect("commit message");
-            if bus.is_some() {
-                bus.as_ref().unwrap().tell(
-                    Publish {
-                        topic: topic_name.into(),
-                        msg: event,
-                    },
-                    None,
+            let _in_flight = in_flight;
+            let degraded = match &outage {
+                Some((_, spillover)) => {
+                    let degraded = spillover.lock().unwrap().degraded;
+                    degraded || store.ping().await.is_err()
+                }
+                None => false,
+            };
+            // changes of entities the backend has can't be checked while it's
+            // down, they are checked again when the spillover is flushed
+            let checked = check_invariants(&store, &c, invariants, &sys_bus, &name).await;
+            if let (Some((degradation, spillover)), true, Ok(())) = (&outage, degraded, &checked) {
+                let result = degrade(&name, *degradation, spillover, c);
+                if let Some(sender) = sender {
+                    let _ = sender.try_tell(result, None);
+                }
+                return;
+            }
+            if let Err(err) = checked {
+                let result: CommitResult<()> = Err(err);
+                if let Some(sender) = sender {
+                    let _ = sender.try_tell(result, None);
+                }
+                return;
+            }
+            let start = std::time::Instant::now();
+            let mut spilled = false;
+            let result = match (store.commit(c.clone()).await, &outage) {
+                (Err(CommitError::Unavailable), Some((degradation, spillover))) => {
+                    let result = degrade(&name, *degradation, spillover, c);
+                    spilled = result.is_ok();
+                    result
+                }
+                (result, _) => result,
+            };
+            let took = start.elapsed();
+            if let Some(threshold) = slow_commit.filter(|t| took > *t) {
+                warn!(
+                    "slow commit of {} took {:?} (threshold {:?})",
+                    id, took, threshold
                 );
+                let slow = Lifecycle::SlowCommit {
+                    id: id.to_string(),
+                    took,
+                };
+                notify(&sys_bus, &name, slow);
+            }
+            // whoever asks for the result of the commit decides what to do on failure
+            if let Some(sender) = sender {
+                let failed = result.is_err();
+                let _ = sender
+                    .try_tell(result, None)
+                    .map_err(|_| warn!("Couldn't reply commit result for {}", id));
+                if failed {
+                    return;
+                }
+            } else if let Err(e) = result {
+                warn!("Couldn't store commit of {}: {}", id, e);
+                return;
             }
-            debug!("saved commit for {}", id);
+            if spilled {
+                // published once the backend is back and it's persisted
+                return;
+            }
+            publish_commit(&store, subscribers, published).await;
         });
     }
 }
 
-impl<M, S> Receive<(EntityId, DateTime<Utc>)> for Store<M, S>
+/// Publishes a persisted commit to everyone interested in its entity
+async fn publish_commit<M: Model, S: CommitStore<M>>(
+    store: &S,
+    subscribers: Subscribers<M>,
+    published: Commit<M>,
+) {
+    let id = published.entity_id();
+    let sequence = if subscribers.needs_sequence() {
+        match store.change_list(id.clone()).count().await {
+            0 => {
+                warn!("Couldn't find commits of {} to publish", id);
+                return;
+            }
+            n => n as u64 - 1,
+        }
+    } else {
+        0
+    };
+    subscribers.publish(published, sequence);
+    debug!("saved commit for {}", id);
+}
+
+/// Handles a commit that can't reach the backend, marking the store degraded
+/// until a probe finds the backend is back. Commits expecting a version
+/// aren't spilled as there's no telling whether they conflict.
+fn degrade<M: Model>(
+    name: &str,
+    degradation: Degradation,
+    spillover: &std::sync::Mutex<Spillover<M>>,
+    c: Commit<M>,
+) -> CommitResult<()> {
+    let mut spillover = spillover.lock().unwrap();
+    if !spillover.degraded {
+        warn!("backend of {} is unavailable", name);
+        spillover.degraded = true;
+    }
+    match degradation {
+        Degradation::Spillover if c.expected_version().is_none() => {
+            spillover.commits.push_back(c);
+            Ok(())
+        }
+        _ => Err(CommitError::Unavailable),
+    }
+}
+
+impl<M, S> Receive<ProbeBackend> for Store<M, S>
 where
     M: Model,
     S: CommitStore<M>,
 {
     type Msg = StoreMsg<M>;
 
-    fn receive(
-        &mut self,
-        cx: &Context<Self::Msg>,
-        (id, until): (EntityId, DateTime<Utc>),
-        sender: Sender,
-    ) {
-        let store = self.backend.clone();
+    fn receive(&mut self, cx: &Context<Self::Msg>, _msg: ProbeBackend, _sender: Sender) {
+        let spilled = {
+            let mut spillover = self.spillover.lock().unwrap();
+            if !spillover.degraded || spillover.flushing {
+                return;
+            }
+            spillover.flushing = true;
+            spillover
+                .commits
+                .iter()
+                .map(|c| self.subscribers(cx, &c.entity_id()))
+                .collect::<Vec<_>>()
+        };
+        let backend = self.backend.clone();
+        let spillover = self.spillover.clone();
+        let name = self.name.clone();
+        let sys_bus = self.sys_bus.clone();
+        let invariants = self.invariants;
         cx.system.exec.spawn_ok(async move {
-            let snapshot = store.snapshot(id, until).await;
-            if snapshot.is_ok() {
-                debug!("Loaded snapshot for {}", id);
-            } else {
-                debug!("Couldn't load {}", id);
+            let mut subscribers = spilled.into_iter();
+            if backend.ping().await.is_ok() {
+                loop {
+                    // commits spilled while flushing are flushed on the next probe
+                    let next = spillover.lock().unwrap().commits.pop_front();
+                    let (c, subs) = match (next, subscribers.next()) {
+                        (Some(c), Some(subs)) => (c, subs),
+                        (next, _) => {
+                            let mut spillover = spillover.lock().unwrap();
+                            if let Some(c) = next {
+                                spillover.commits.push_front(c);
+                            } else {
+                                info!("backend of {} is available again", name);
+                                spillover.degraded = false;
+                            }
+                            break;
+                        }
+                    };
+                    let committed =
+                        match check_invariants(&backend, &c, invariants, &sys_bus, &name).await {
+                            Ok(()) => backend.commit(c.clone()).await,
+                            Err(err) => Err(err),
+                        };
+                    match committed {
+                        Ok(()) => publish_commit(&backend, subs, c).await,
+                        Err(CommitError::Unavailable) => {
+                            spillover.lock().unwrap().commits.push_front(c);
+                            break;
+                        }
+                        Err(e) => {
+                            let id = c.entity_id();
+                            warn!("spilled commit of {} failed: {} {:?}", id, e, c);
+                            let failed = Lifecycle::SpillFailed {
+                                id: id.to_string(),
+                                reason: e.to_string(),
+                            };
+                            notify(&sys_bus, &name, failed);
+                        }
+                    }
+                }
             }
-            sender
-                .unwrap()
-                .try_tell(snapshot.ok(), None)
-                .expect("can receive snapshot");
+            spillover.lock().unwrap().flushing = false;
         });
     }
 }
 
-// list of entities
-impl<M, S> Receive<DateTime<Utc>> for Store<M, S>
+/// Checks the commit against the invariants of the model when the store is
+/// configured to, violations are reported to the system bus and only fail
+/// the commit when the policy rejects them.
+async fn check_invariants<M: Model, S: CommitStore<M>>(
+    store: &S,
+    c: &Commit<M>,
+    policy: Option<InvariantPolicy>,
+    sys_bus: &Option<SystemBus>,
+    name: &str,
+) -> CommitResult<()> {
+    let policy = match policy {
+        Some(policy) => policy,
+        None => return Ok(()),
+    };
+    let reason = match violated_invariant(store, c.event.clone()).await {
+        Some(reason) => reason,
+        None => return Ok(()),
+    };
+    let id = c.entity_id();
+    warn!("commit of {} breaks an invariant: {}", id, reason);
+    let violated = Lifecycle::InvariantViolated {
+        id: id.to_string(),
+        reason: reason.clone(),
+    };
+    notify(sys_bus, name, violated);
+    match policy {
+        InvariantPolicy::Reject => Err(CommitError::Invariant(reason)),
+        InvariantPolicy::Log => Ok(()),
+    }
+}
+
+/// The reason the state the commit leads to breaks the invariants of the model
+async fn violated_invariant<M: Model, S: CommitStore<M>>(
+    store: &S,
+    event: Event<M>,
+) -> Option<String> {
+    let state = match event {
+        Event::Create(model) => model,
+        Event::Change(id, change) => {
+            // changes to missing entities fail when committed
+            let mut model = store.get(id).await.ok()?.to_present().await.ok()?;
+            model.apply_change(&change);
+            model
+        }
+        Event::Delete(_) => return None,
+    };
+    state.check_invariants().err()
+}
+
+impl<M, S> Receive<Batch<M>> for Store<M, S>
 where
     M: Model,
     S: CommitStore<M>,
 {
     type Msg = StoreMsg<M>;
-
-    fn receive(&mut self, cx: &Context<Self::Msg>, until: DateTime<Utc>, sender: Sender) {
-        let backend = self.backend.clone();
-        let _ = cx.system.exec.spawn_ok(async move {
-            let entities = backend
-                .clone()
-                .entities()
-                .and_then(|entity| entity.travel_to(until))
-                .try_collect::<Vec<M>>()
-                .await
-                .expect("list entities");
-            sender
-                .unwrap()
-                .try_tell(entities, None)
-                .expect("receive snapshot list");
-            debug!("loaded list of snapshots until {}", until);
+    fn receive(&mut self, cx: &Context<Self::Msg>, Batch(mut commits): Batch<M>, sender: Sender) {
+        if let Some(clock) = &self.clock {
+            let now = clock.now();
+            commits.iter_mut().for_each(|c| c.set_when(now));
+        }
+        trace!("storing batch of {} commits", commits.len());
+        let store = self.backend.clone();
+        let subscribers: Vec<_> = commits
+            .iter()
+            .map(|c| self.subscribers(cx, &c.entity_id()))
+            .collect();
+        let in_flight = self.in_flight.start();
+        cx.system.exec.spawn_ok(async move {
+            let _in_flight = in_flight;
+            let result = store.commit_all(commits.clone()).await;
+            let failed = result.is_err();
+            if let Some(sender) = sender {
+                let _ = sender
+                    .try_tell(result, None)
+                    .map_err(|_| warn!("Couldn't reply batch result"));
+            } else if let Err(e) = result {
+                warn!("Couldn't store batch: {}", e);
+            }
+            if failed {
+                return;
+            }
+            // commits of the same entity take consecutive positions ending at its latest
+            let mut next = HashMap::new();
+            let mut sequences = vec![0; commits.len()];
+            let mut ids: Vec<_> = commits.iter().map(|c| c.entity_id()).collect();
+            while let Some(id) = ids.pop() {
+                let i = ids.len();
+                if !subscribers[i].needs_sequence() {
+                    continue;
+                }
+                let sequence = match next.get(&id) {
+                    Some(sequence) => *sequence,
+                    None => store.change_list(id.clone()).count().await as u64,
+                };
+                sequences[i] = sequence.saturating_sub(1);
+                next.insert(id, sequences[i]);
+            }
+            for ((commit, subscribers), sequence) in
+                commits.into_iter().zip(subscribers).zip(sequences)
+            {
+                subscribers.publish(commit, sequence);
+            }
+            debug!("saved batch of commits");
         });
     }
 }
 
-impl<M, S> Receive<EntityId> for Store<M, S>
+impl<M, S> Receive<Import<M>> for Store<M, S>
 where
     M: Model,
     S: CommitStore<M>,
 {
     type Msg = StoreMsg<M>;
-
-    fn receive(&mut self, _cx: &Context<Self::Msg>, _id: EntityId, _sender: Sender) {
-        todo!();
+    fn receive(&mut self, cx: &Context<Self::Msg>, import: Import<M>, sender: Sender) {
+        let store = self.backend.clone();
+        let id = match import.history.first() {
+            Some(c) => c.entity_id(),
+            None => {
+                let _ = sender
+                    .map(|s| s.try_tell(CommitResult::<()>::Err(CommitError::NotFound), None));
+                return;
+            }
+        };
+        trace!("importing {} commits of {}", import.history.len(), id);
+        let subscribers = self.subscribers(cx, &id);
+        let history = import.history.clone();
+        let in_flight = self.in_flight.start();
+        cx.system.exec.spawn_ok(async move {
+            let _in_flight = in_flight;
+            let result = store.import_entity(id.clone(), history, import.force).await;
+            let failed = result.is_err();
+            if let Some(sender) = sender {
+                let _ = sender
+                    .try_tell(result, None)
+                    .map_err(|_| warn!("Couldn't reply import result for {}", id));
+            } else if let Err(e) = result {
+                warn!("Couldn't import {}: {}", id, e);
+            }
+            if failed {
+                return;
+            }
+            for (sequence, commit) in import.history.into_iter().enumerate() {
+                subscribers.publish(commit, sequence as u64);
+            }
+            debug!("imported history of {}", id);
+        });
     }
 }
 
-#[derive(Debug, Clone)]
-pub enum StoreMsg<T: Model> {
-    Commit(Commit<T>),
-    Snapshot((EntityId, DateTime<Utc>)),
-    SnapshotList(DateTime<Utc>),
-    Subscribe(EntityId),
+/// Actors subscribed to the events of a single entity by its id, shared
+/// with the commits being published so the ones that are gone are dropped.
+type EntitySubs = Arc<std::sync::Mutex<HashMap<String, Vec<BasicActorRef>>>>;
+
+/// Everyone interested in the commits of an entity
+struct Subscribers<M: Model> {
+    bus: Option<EventBus<M>>,
+    commit_bus: Option<CommitBus<M>>,
+    invalidations: Option<InvalidationBus<M::Id>>,
+    prefix_subs: Vec<BoxedTell<Event<M>>>,
+    entity_subs: EntitySubs,
+    store: String,
 }
-impl<T: Model> From<Event<T>> for StoreMsg<T> {
+
+impl<M: Model> Subscribers<M> {
+    fn needs_sequence(&self) -> bool {
+        self.commit_bus.is_some() || self.invalidations.is_some()
+    }
+
+    fn publish(&self, commit: Commit<M>, sequence: u64) {
+        let topic = format!("{}-events", self.store);
+        if let Some(invalidations) = &self.invalidations {
+            invalidations.tell(
+                Publish {
+                    topic: format!("{}-invalidations", self.store).into(),
+                    msg: Invalidated {
+                        id: commit.entity_id(),
+                        version: sequence + 1,
+                    },
+                },
+                None,
+            );
+        }
+        for sub in self.prefix_subs.iter() {
+            sub.tell(commit.event.clone(), None);
+        }
+        let id = commit.entity_id().to_string();
+        let mut entity_subs = self.entity_subs.lock().unwrap();
+        if let Some(subs) = entity_subs.get_mut(&id) {
+            // subscribers that stopped can't be told anymore
+            subs.retain(|sub| sub.try_tell(commit.event.clone(), None).is_ok());
+            if subs.is_empty() {
+                entity_subs.remove(&id);
+            }
+        }
+        drop(entity_subs);
+        if let Some(bus) = &self.bus {
+            bus.tell(
+                Publish {
+                    topic: topic.as_str().into(),
+                    msg: commit.event.clone(),
+                },
+                None,
+            );
+            bus.tell(
+                Publish {
+                    topic: format!("{}-{}-events", self.store, commit.entity_id()).into(),
+                    msg: commit.event.clone(),
+                },
+                None,
+            );
+        }
+        if let Some(commit_bus) = &self.commit_bus {
+            commit_bus.tell(
+                Publish {
+                    topic: topic.as_str().into(),
+                    msg: PublishedCommit { commit, sequence },
+                },
+                None,
+            );
+        }
+    }
+}
+
+impl<M, S> Receive<(M::Id, DateTime<Utc>)> for Store<M, S>
+where
+    M: Model,
+    S: CommitStore<M>,
+{
+    type Msg = StoreMsg<M>;
+
+    fn receive(
+        &mut self,
+        cx: &Context<Self::Msg>,
+        (id, until): (M::Id, DateTime<Utc>),
+        sender: Sender,
+    ) {
+        let store = self.backend.clone();
+        cx.system.exec.spawn_ok(async move {
+            let snapshot = store.snapshot(id.clone(), until).await;
+            if snapshot.is_ok() {
+                debug!("Loaded snapshot for {}", id);
+            } else {
+                debug!("Couldn't load {}", id);
+            }
+            sender
+                .unwrap()
+                .try_tell(snapshot.ok(), None)
+                .expect("can receive snapshot");
+        });
+    }
+}
+
+impl<M, S> Receive<(M::Id, u64)> for Store<M, S>
+where
+    M: Model,
+    S: CommitStore<M>,
+{
+    type Msg = StoreMsg<M>;
+
+    fn receive(&mut self, cx: &Context<Self::Msg>, (id, version): (M::Id, u64), sender: Sender) {
+        let store = self.backend.clone();
+        cx.system.exec.spawn_ok(async move {
+            let snapshot = store.snapshot_at_version(id.clone(), version).await;
+            if snapshot.is_ok() {
+                debug!("Loaded {} at version {}", id, version);
+            } else {
+                debug!("Couldn't load {} at version {}", id, version);
+            }
+            sender
+                .unwrap()
+                .try_tell(snapshot.ok(), None)
+                .expect("can receive snapshot");
+        });
+    }
+}
+
+// list of entities
+impl<M, S> Receive<DateTime<Utc>> for Store<M, S>
+where
+    M: Model,
+    S: CommitStore<M>,
+{
+    type Msg = StoreMsg<M>;
+
+    fn receive(&mut self, cx: &Context<Self::Msg>, until: DateTime<Utc>, sender: Sender) {
+        self.list(cx, ListOptions::until(until), sender);
+    }
+}
+
+impl<M, S> Receive<(DateTime<Utc>, CancelToken)> for Store<M, S>
+where
+    M: Model,
+    S: CommitStore<M>,
+{
+    type Msg = StoreMsg<M>;
+
+    fn receive(
+        &mut self,
+        cx: &Context<Self::Msg>,
+        (until, cancel): (DateTime<Utc>, CancelToken),
+        sender: Sender,
+    ) {
+        let opts = ListOptions {
+            cancel: Some(cancel),
+            ..ListOptions::until(until)
+        };
+        self.list(cx, opts, sender);
+    }
+}
+
+impl<M, S> Receive<(DateTime<Utc>, Comparison<M>)> for Store<M, S>
+where
+    M: Model,
+    S: CommitStore<M>,
+{
+    type Msg = StoreMsg<M>;
+
+    fn receive(
+        &mut self,
+        cx: &Context<Self::Msg>,
+        (until, cmp): (DateTime<Utc>, Comparison<M>),
+        sender: Sender,
+    ) {
+        let opts = ListOptions {
+            sort: Some(cmp),
+            ..ListOptions::until(until)
+        };
+        self.list(cx, opts, sender);
+    }
+}
+
+impl<M, S> Receive<(DateTime<Utc>, Limit)> for Store<M, S>
+where
+    M: Model,
+    S: CommitStore<M>,
+{
+    type Msg = StoreMsg<M>;
+
+    fn receive(
+        &mut self,
+        cx: &Context<Self::Msg>,
+        (until, Limit(limit)): (DateTime<Utc>, Limit),
+        sender: Sender,
+    ) {
+        let opts = ListOptions {
+            limit: Some(limit),
+            ..ListOptions::until(until)
+        };
+        self.list(cx, opts, sender);
+    }
+}
+
+impl<M, S> Receive<SnapshotPage> for Store<M, S>
+where
+    M: Model,
+    S: CommitStore<M>,
+{
+    type Msg = StoreMsg<M>;
+
+    fn receive(&mut self, cx: &Context<Self::Msg>, page: SnapshotPage, sender: Sender) {
+        let opts = ListOptions {
+            offset: page.offset,
+            limit: Some(page.limit),
+            ..ListOptions::until(page.until)
+        };
+        self.list(cx, opts, sender);
+    }
+}
+
+/// How to list the entities of a store
+struct ListOptions<M> {
+    until: DateTime<Utc>,
+    cancel: Option<CancelToken>,
+    sort: Option<Comparison<M>>,
+    offset: usize,
+    limit: Option<usize>,
+}
+
+impl<M> ListOptions<M> {
+    fn until(until: DateTime<Utc>) -> Self {
+        ListOptions {
+            until,
+            cancel: None,
+            sort: None,
+            offset: 0,
+            limit: None,
+        }
+    }
+}
+
+impl<M, S> Receive<ExpireIdle> for Store<M, S>
+where
+    M: Model,
+    S: CommitStore<M>,
+{
+    type Msg = StoreMsg<M>;
+
+    fn receive(&mut self, cx: &Context<Self::Msg>, _msg: ExpireIdle, sender: Sender) {
+        let ttl = match self.ttl {
+            Some(ttl) => ttl,
+            None => return,
+        };
+        let deadline = self.now() - ttl.idle;
+        self.purge_where(cx, sender, "idle", move |last| last.when() < deadline);
+    }
+}
+
+impl<M, S> Receive<PurgeDeleted> for Store<M, S>
+where
+    M: Model,
+    S: CommitStore<M>,
+{
+    type Msg = StoreMsg<M>;
+
+    fn receive(&mut self, cx: &Context<Self::Msg>, _msg: PurgeDeleted, sender: Sender) {
+        let retention = match self.retention {
+            Some(retention) => retention,
+            None => return,
+        };
+        let deadline = self.now() - retention.grace;
+        self.purge_where(cx, sender, "deleted", move |last| {
+            last.kind() == EventKind::Delete && last.when() < deadline
+        });
+    }
+}
+
+impl<M, S> Store<M, S>
+where
+    M: Model,
+    S: CommitStore<M>,
+{
+    fn now(&self) -> DateTime<Utc> {
+        self.clock
+            .as_ref()
+            .map_or_else(Utc::now, |clock| clock.now())
+    }
+
+    /// Purges the entities whose last commit matches, reporting each of them
+    /// as expired and replying how many were purged.
+    fn purge_where<F>(
+        &self,
+        cx: &Context<StoreMsg<M>>,
+        sender: Sender,
+        what: &'static str,
+        expired: F,
+    ) where
+        F: Fn(&Commit<M>) -> bool + Send + Sync + 'static,
+    {
+        let backend = self.backend.clone();
+        let sys_bus = self.sys_bus.clone();
+        let name = self.name.clone();
+        cx.system.exec.spawn_ok(async move {
+            let expired = backend
+                .keys()
+                .try_filter_map(|id| {
+                    let backend = backend.clone();
+                    let expired = &expired;
+                    async move {
+                        let last = backend
+                            .change_list(id.clone())
+                            .try_fold(None, |_, c| ok(Some(c)))
+                            .await?;
+                        Ok(last.filter(|c| expired(c)).map(|_| id))
+                    }
+                })
+                .try_collect::<Vec<_>>()
+                .await;
+            let mut purged = 0usize;
+            for id in expired.unwrap_or_default() {
+                notify(&sys_bus, &name, Lifecycle::Expired(id.to_string()));
+                match backend.purge(id.clone()).await {
+                    Ok(()) => {
+                        debug!("purged {} entity {}", what, id);
+                        purged += 1;
+                    }
+                    Err(e) => warn!("couldn't purge {} entity {}: {}", what, id, e),
+                }
+            }
+            if let Some(sender) = sender {
+                let _ = sender.try_tell(purged, None);
+            }
+        });
+    }
+}
+
+impl<M, S> Receive<Checkpoint> for Store<M, S>
+where
+    M: Model,
+    S: CommitStore<M>,
+{
+    type Msg = StoreMsg<M>;
+
+    fn receive(&mut self, cx: &Context<Self::Msg>, Checkpoint(at): Checkpoint, sender: Sender) {
+        let backend = self.backend.clone();
+        let flushed = self.in_flight.idle();
+        cx.system.exec.spawn_ok(async move {
+            flushed.await;
+            let result = backend.checkpoint(at).await;
+            debug!("checkpoint at {}: {:?}", at, result);
+            if let Some(sender) = sender {
+                let _ = sender
+                    .try_tell(result, None)
+                    .map_err(|_| warn!("Couldn't reply checkpoint at {}", at));
+            }
+        });
+    }
+}
+
+/// Keeps count of the commits being persisted to be able to wait for them
+#[derive(Debug, Clone, Default)]
+struct InFlight(Arc<std::sync::Mutex<(usize, Vec<oneshot::Sender<()>>)>>);
+
+impl InFlight {
+    fn start(&self) -> InFlightGuard {
+        self.0.lock().unwrap().0 += 1;
+        InFlightGuard(self.clone())
+    }
+
+    /// Resolves once there are no commits being persisted
+    fn idle(&self) -> BoxFuture<'static, ()> {
+        let mut state = self.0.lock().unwrap();
+        if state.0 == 0 {
+            return ready(()).boxed();
+        }
+        let (tx, rx) = oneshot::channel();
+        state.1.push(tx);
+        rx.map(|_| ()).boxed()
+    }
+}
+
+struct InFlightGuard(InFlight);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        let mut state = (self.0).0.lock().unwrap();
+        state.0 -= 1;
+        if state.0 == 0 {
+            for waiting in state.1.drain(..) {
+                let _ = waiting.send(());
+            }
+        }
+    }
+}
+
+impl<M, S> Receive<RecentHistory<M::Id>> for Store<M, S>
+where
+    M: Model,
+    S: CommitStore<M>,
+{
+    type Msg = StoreMsg<M>;
+
+    fn receive(&mut self, cx: &Context<Self::Msg>, msg: RecentHistory<M::Id>, sender: Sender) {
+        let backend = self.backend.clone();
+        cx.system.exec.spawn_ok(async move {
+            let RecentHistory { id, last_n } = msg;
+            // state and changes come from the same read to be consistent
+            let recent = backend
+                .change_list(id.clone())
+                .try_collect::<Vec<Commit<M>>>()
+                .await
+                .map_err(|err| debug!("Couldn't load history of {}: {}", id, err))
+                .ok()
+                .and_then(|mut commits| {
+                    if commits.last()?.kind() == EventKind::Delete {
+                        return None;
+                    }
+                    let mut model = commits.first()?.entity()?;
+                    for change in commits.iter().filter_map(|c| c.change()) {
+                        model.apply_change(&change);
+                    }
+                    let recent = commits.split_off(commits.len().saturating_sub(last_n));
+                    Some((model, recent))
+                });
+            sender
+                .unwrap()
+                .try_tell(recent, None)
+                .expect("can receive recent history");
+        });
+    }
+}
+
+impl<M, S> Receive<AtTag<M::Id>> for Store<M, S>
+where
+    M: Model,
+    S: CommitStore<M>,
+{
+    type Msg = StoreMsg<M>;
+
+    fn receive(&mut self, cx: &Context<Self::Msg>, msg: AtTag<M::Id>, sender: Sender) {
+        let backend = self.backend.clone();
+        cx.system.exec.spawn_ok(async move {
+            let AtTag { id, tag } = msg;
+            let snapshot = match backend.resolve_tag(&tag).await {
+                Ok(at) => backend.snapshot(id.clone(), at).await,
+                Err(err) => Err(err),
+            };
+            if let Err(err) = &snapshot {
+                debug!("Couldn't load {} as of {}: {}", id, tag, err);
+            }
+            sender
+                .unwrap()
+                .try_tell(snapshot.ok(), None)
+                .expect("can receive snapshot");
+        });
+    }
+}
+
+impl<M, S> Receive<Revert<M::Id>> for Store<M, S>
+where
+    M: Model,
+    S: CommitStore<M>,
+{
+    type Msg = StoreMsg<M>;
+
+    fn receive(&mut self, cx: &Context<Self::Msg>, msg: Revert<M::Id>, sender: Sender) {
+        let backend = self.backend.clone();
+        let sys = cx.system.clone();
+        let myself = cx.myself();
+        cx.system.exec.spawn_ok(async move {
+            let Revert { id, at } = msg;
+            // committed like any other commit so it's checked and published
+            let result = match backend.compensate(id.clone(), at).await {
+                Ok(c) => {
+                    let committed: CommitResult<()> =
+                        ask(&sys, myself.into(), StoreMsg::from(c.clone()))
+                            .await
+                            .unwrap_or(Err(CommitError::Unavailable));
+                    committed.map(|_| c)
+                }
+                Err(err) => Err(err),
+            };
+            if let Err(err) = &result {
+                debug!("Couldn't revert {} to {}: {}", id, at, err);
+            }
+            if let Some(sender) = sender {
+                let _ = sender
+                    .try_tell(result, None)
+                    .map_err(|_| warn!("Couldn't reply revert of {}", id));
+            }
+        });
+    }
+}
+
+impl<M, S> Receive<CommitsSince> for Store<M, S>
+where
+    M: Model,
+    S: CommitStore<M>,
+{
+    type Msg = StoreMsg<M>;
+
+    fn receive(&mut self, cx: &Context<Self::Msg>, msg: CommitsSince, sender: Sender) {
+        let backend = self.backend.clone();
+        cx.system.exec.spawn_ok(async move {
+            let CommitsSince { since, kinds } = msg;
+            let commits: CommitResult<Vec<Commit<M>>> =
+                backend.commits_since(since, kinds).try_collect().await;
+            if let Some(sender) = sender {
+                let _ = sender
+                    .try_tell(commits, None)
+                    .map_err(|_| warn!("Couldn't reply commits since {}", since));
+            }
+        });
+    }
+}
+
+impl<M, S> Receive<History<M::Id>> for Store<M, S>
+where
+    M: Model,
+    S: CommitStore<M>,
+{
+    type Msg = StoreMsg<M>;
+
+    fn receive(&mut self, cx: &Context<Self::Msg>, History(id): History<M::Id>, sender: Sender) {
+        let backend = self.backend.clone();
+        cx.system.exec.spawn_ok(async move {
+            let history = backend
+                .change_list(id.clone())
+                .try_collect::<Vec<Commit<M>>>()
+                .await
+                .unwrap_or_else(|err| {
+                    debug!("Couldn't load history of {}: {}", id, err);
+                    vec![]
+                });
+            sender
+                .unwrap()
+                .try_tell(history, None)
+                .expect("can receive history");
+        });
+    }
+}
+
+impl<M, S> Store<M, S>
+where
+    M: Model,
+    S: CommitStore<M>,
+{
+    fn subscribers(&self, cx: &Context<StoreMsg<M>>, id: &M::Id) -> Subscribers<M> {
+        let id = id.to_string();
+        Subscribers {
+            bus: self.bus.clone(),
+            commit_bus: self.commit_bus.clone(),
+            invalidations: self.invalidations.clone(),
+            prefix_subs: self
+                .prefix_subs
+                .iter()
+                .filter(|(prefix, _)| id.starts_with(prefix))
+                .map(|(_, sub)| sub.box_clone())
+                .collect(),
+            entity_subs: self.entity_subs.clone(),
+            store: cx.myself().name().into(),
+        }
+    }
+
+    /// Replies with the snapshots of every entity, when the query is cancelled
+    /// it stops loading entities and doesn't reply. Limited lists reply with
+    /// `Limited` instead of the bare list. Up to `parallelism` entities are
+    /// rebuilt at once so the order of unsorted lists isn't guaranteed.
+    /// Entities are rebuilt as of `until`, those created later or deleted by
+    /// then are skipped before paging.
+    fn list(&self, cx: &Context<StoreMsg<M>>, opts: ListOptions<M>, sender: Sender) {
+        let backend = self.backend.clone();
+        let parallelism = self.parallelism;
+        let settled = match self.consistency {
+            ReadConsistency::Settled => self.in_flight.idle(),
+            ReadConsistency::Eventual => ready(()).boxed(),
+        };
+        cx.system.exec.spawn_ok(async move {
+            settled.await;
+            let ListOptions {
+                until,
+                cancel,
+                sort,
+                offset,
+                limit,
+            } = opts;
+            let cancelled = || cancel.as_ref().is_some_and(CancelToken::is_cancelled);
+            let snapshots = backend
+                .keys()
+                .try_take_while(|_| ok(!cancelled()))
+                .map_ok(|id| {
+                    let backend = &backend;
+                    async move {
+                        match backend.get(id).await?.travel_to(until).await {
+                            Err(CommitError::Deleted | CommitError::NotFound) => Ok(None),
+                            res => res.map(Some),
+                        }
+                    }
+                });
+            // entities that fail to load are left out instead of failing the
+            // whole list
+            let loaded = |res: CommitResult<Option<M>>| {
+                ready(res.unwrap_or_else(|err| {
+                    warn!("Couldn't load entity to list until {}: {}", until, err);
+                    None
+                }))
+            };
+            // pages skip the entities that don't exist as of `until` before
+            // counting, sorted lists need every entity before knowing which
+            // ones to keep, otherwise no more keys than needed are loaded
+            let snapshots = match limit {
+                Some(limit) if sort.is_none() => snapshots
+                    .try_buffered(parallelism)
+                    .filter_map(loaded)
+                    .skip(offset)
+                    .take(limit + 1)
+                    .boxed(),
+                _ => snapshots
+                    .try_buffer_unordered(parallelism)
+                    .filter_map(loaded)
+                    .boxed(),
+            };
+            let mut entities = snapshots.collect::<Vec<M>>().await;
+            if cancelled() {
+                debug!("cancelled list of snapshots until {}", until);
+                return;
+            }
+            if let Some(cmp) = sort {
+                entities.sort_by(cmp);
+                entities.drain(..offset.min(entities.len()));
+            }
+            let sender = sender.unwrap();
+            match limit {
+                Some(limit) => {
+                    let truncated = entities.len() > limit;
+                    entities.truncate(limit);
+                    sender.try_tell(
+                        Limited {
+                            entities,
+                            truncated,
+                        },
+                        None,
+                    )
+                }
+                None => sender.try_tell(entities, None),
+            }
+            .expect("receive snapshot list");
+            debug!("loaded list of snapshots until {}", until);
+        });
+    }
+
+    /// Tells the sender every event of the entity from now on until it
+    /// stops, stores with an event bus also publish them to the
+    /// `{store}-{id}-events` topic.
+    fn subscribe(&mut self, _cx: &Context<StoreMsg<M>>, id: M::Id, sender: Sender) {
+        match sender {
+            Some(sub) => {
+                debug!("{} subscribed to events of {}", sub.path(), id);
+                self.entity_subs
+                    .lock()
+                    .unwrap()
+                    .entry(id.to_string())
+                    .or_default()
+                    .push(sub);
+            }
+            None => warn!("Can't subscribe to {} without a sender", id),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum StoreMsg<T: Model> {
+    Commit(Commit<T>),
+    Snapshot((T::Id, DateTime<Utc>)),
+    /// The state of an entity after its first n commits
+    SnapshotAtVersion((T::Id, u64)),
+    SnapshotList(DateTime<Utc>),
+    CancellableSnapshotList((DateTime<Utc>, CancelToken)),
+    SortedSnapshotList((DateTime<Utc>, Comparison<T>)),
+    LimitedSnapshotList((DateTime<Utc>, Limit)),
+    SnapshotPage(SnapshotPage),
+    Subscribe(T::Id),
+    History(History<T::Id>),
+    Checkpoint(Checkpoint),
+    RecentHistory(RecentHistory<T::Id>),
+    AtTag(AtTag<T::Id>),
+    CommitsSince(CommitsSince),
+    Import(Import<T>),
+    Batch(Batch<T>),
+    Revert(Revert<T::Id>),
+    ExpireIdle(ExpireIdle),
+    PurgeDeleted(PurgeDeleted),
+    ProbeBackend(ProbeBackend),
+    /// Subscribes an actor to events of entities whose id starts with the prefix
+    SubscribePrefix(String, BoxedTell<Event<T>>),
+}
+
+/// Asks for the list of commits of an entity
+#[derive(Debug, Clone)]
+pub struct History<Id>(pub Id);
+/// Asks the store to persist commits of several entities at once, see
+/// `CommitStore::commit_all`. It replies with the `CommitResult` of the batch.
+#[derive(Debug, Clone)]
+pub struct Batch<T: Model>(pub Vec<Commit<T>>);
+impl<T: Model> From<Batch<T>> for StoreMsg<T> {
+    fn from(msg: Batch<T>) -> Self {
+        StoreMsg::Batch(msg)
+    }
+}
+/// Asks the store to install the whole history of an entity at once,
+/// see `CommitStore::import_entity`.
+#[derive(Debug, Clone)]
+pub struct Import<T: Model> {
+    pub history: Vec<Commit<T>>,
+    pub force: bool,
+}
+impl<T: Model> From<Import<T>> for StoreMsg<T> {
+    fn from(msg: Import<T>) -> Self {
+        StoreMsg::Import(msg)
+    }
+}
+/// Asks the store to purge the entities that outlived their TTL, it replies
+/// with how many were purged.
+#[derive(Debug, Clone)]
+pub struct ExpireIdle;
+impl<T: Model> From<ExpireIdle> for StoreMsg<T> {
+    fn from(msg: ExpireIdle) -> Self {
+        StoreMsg::ExpireIdle(msg)
+    }
+}
+/// Asks the store to purge the entities deleted longer than the grace period
+/// of its `Retention`, it replies with how many were purged.
+#[derive(Debug, Clone)]
+pub struct PurgeDeleted;
+impl<T: Model> From<PurgeDeleted> for StoreMsg<T> {
+    fn from(msg: PurgeDeleted) -> Self {
+        StoreMsg::PurgeDeleted(msg)
+    }
+}
+/// Asks a degraded store to check if its backend is back and persist the
+/// commits it spilled meanwhile
+#[derive(Debug, Clone)]
+pub struct ProbeBackend;
+impl<T: Model> From<ProbeBackend> for StoreMsg<T> {
+    fn from(msg: ProbeBackend) -> Self {
+        StoreMsg::ProbeBackend(msg)
+    }
+}
+/// Asks the store to wait for the commits it's persisting and checkpoint
+/// its backend, it replies with the `CommitResult` of the checkpoint.
+#[derive(Debug, Clone, Copy)]
+pub struct Checkpoint(pub DateTime<Utc>);
+impl<T: Model> From<Checkpoint> for StoreMsg<T> {
+    fn from(msg: Checkpoint) -> Self {
+        StoreMsg::Checkpoint(msg)
+    }
+}
+
+/// Asks for the commits of every entity made since a moment whose event is
+/// of one of the given kinds, see `CommitStore::commits_since`.
+#[derive(Debug, Clone)]
+pub struct CommitsSince {
+    pub since: DateTime<Utc>,
+    pub kinds: Vec<EventKind>,
+}
+impl<T: Model> From<CommitsSince> for StoreMsg<T> {
+    fn from(msg: CommitsSince) -> Self {
+        StoreMsg::CommitsSince(msg)
+    }
+}
+
+/// Asks for the state of an entity at the moment named by a tag
+#[derive(Debug, Clone)]
+pub struct AtTag<Id> {
+    pub id: Id,
+    pub tag: String,
+}
+impl<T: Model> From<AtTag<T::Id>> for StoreMsg<T> {
+    fn from(msg: AtTag<T::Id>) -> Self {
+        StoreMsg::AtTag(msg)
+    }
+}
+
+/// Asks the store to append the commit that brings an entity back to its
+/// state at the given moment, see `CommitStore::compensate`. It replies
+/// with the `CommitResult` of the appended commit.
+#[derive(Debug, Clone)]
+pub struct Revert<Id> {
+    pub id: Id,
+    pub at: DateTime<Utc>,
+}
+impl<T: Model> From<Revert<T::Id>> for StoreMsg<T> {
+    fn from(msg: Revert<T::Id>) -> Self {
+        StoreMsg::Revert(msg)
+    }
+}
+
+/// Asks for the current state of an entity along with its last commits
+#[derive(Debug, Clone)]
+pub struct RecentHistory<Id> {
+    pub id: Id,
+    pub last_n: usize,
+}
+impl<T: Model> From<RecentHistory<T::Id>> for StoreMsg<T> {
+    fn from(msg: RecentHistory<T::Id>) -> Self {
+        StoreMsg::RecentHistory(msg)
+    }
+}
+impl<T: Model> From<History<T::Id>> for StoreMsg<T> {
+    fn from(msg: History<T::Id>) -> Self {
+        StoreMsg::History(msg)
+    }
+}
+impl<T: Model> From<Event<T>> for StoreMsg<T> {
     fn from(msg: Event<T>) -> Self {
         StoreMsg::Commit(msg.into())
     }
 }
-impl<T: Model> From<DateTime<Utc>> for StoreMsg<T> {
-    fn from(range: DateTime<Utc>) -> Self {
-        StoreMsg::SnapshotList(range)
+impl<T: Model> From<DateTime<Utc>> for StoreMsg<T> {
+    fn from(range: DateTime<Utc>) -> Self {
+        StoreMsg::SnapshotList(range)
+    }
+}
+impl<T: Model> From<(DateTime<Utc>, Limit)> for StoreMsg<T> {
+    fn from(list: (DateTime<Utc>, Limit)) -> Self {
+        StoreMsg::LimitedSnapshotList(list)
+    }
+}
+impl<T: Model> From<(DateTime<Utc>, Comparison<T>)> for StoreMsg<T> {
+    fn from(list: (DateTime<Utc>, Comparison<T>)) -> Self {
+        StoreMsg::SortedSnapshotList(list)
+    }
+}
+impl<T: Model> From<(DateTime<Utc>, CancelToken)> for StoreMsg<T> {
+    fn from(list: (DateTime<Utc>, CancelToken)) -> Self {
+        StoreMsg::CancellableSnapshotList(list)
+    }
+}
+impl<T: Model> From<Commit<T>> for StoreMsg<T> {
+    fn from(msg: Commit<T>) -> Self {
+        StoreMsg::Commit(msg)
+    }
+}
+impl<T: Model> From<(T::Id, DateTime<Utc>)> for StoreMsg<T> {
+    fn from(snap: (T::Id, DateTime<Utc>)) -> Self {
+        StoreMsg::Snapshot(snap)
+    }
+}
+impl<T: Model> From<(T::Id, u64)> for StoreMsg<T> {
+    fn from(snap: (T::Id, u64)) -> Self {
+        StoreMsg::SnapshotAtVersion(snap)
+    }
+}
+
+/// Caps the number of entities of a list
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limit(pub usize);
+
+/// Asks for a page of the list of entities as of `until`, skipping the first
+/// `offset` ones. It replies `Limited` to `limit` entities, `truncated` when
+/// there are more pages. Pages are consistent with each other as long as the
+/// backend lists its keys in a stable order like `MemStore` does.
+#[derive(Debug, Clone, Copy)]
+pub struct SnapshotPage {
+    pub until: DateTime<Utc>,
+    pub offset: usize,
+    pub limit: usize,
+}
+impl<T: Model> From<SnapshotPage> for StoreMsg<T> {
+    fn from(page: SnapshotPage) -> Self {
+        StoreMsg::SnapshotPage(page)
+    }
+}
+
+/// A list of at most the requested number of entities, `truncated` when
+/// there were more entities left out.
+#[derive(Debug, Clone)]
+pub struct Limited<M> {
+    pub entities: Vec<M>,
+    pub truncated: bool,
+}
+
+/// Orders the entities of a list
+pub type Comparison<M> = fn(&M, &M) -> Ordering;
+
+/// A `Comparison` of any model, to be able to send it in a `Query`
+#[derive(Clone)]
+pub struct Comparator(Arc<dyn Any + Send + Sync>);
+
+impl Comparator {
+    pub fn new<M: Model>(cmp: Comparison<M>) -> Self {
+        Comparator(Arc::new(cmp))
+    }
+
+    /// The comparison if it orders models of type `M`
+    pub fn of<M: Model>(&self) -> Option<Comparison<M>> {
+        self.0.downcast_ref::<Comparison<M>>().copied()
+    }
+}
+
+impl fmt::Debug for Comparator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Comparator")
+    }
+}
+
+/// Lets a long running query know whether its requester is still waiting,
+/// the query is cancelled once the `CancelGuard` is dropped.
+#[derive(Clone, Debug)]
+pub struct CancelToken(Weak<()>);
+
+/// Keeps the query of its `CancelToken` running while it's alive
+#[derive(Debug)]
+pub struct CancelGuard(#[allow(dead_code)] Arc<()>);
+
+impl CancelToken {
+    pub fn new() -> (CancelGuard, CancelToken) {
+        let guard = Arc::new(());
+        let token = CancelToken(Arc::downgrade(&guard));
+        (CancelGuard(guard), token)
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.strong_count() == 0
+    }
+}
+
+type Author = Option<String>;
+type Reason = Option<String>;
+
+/// Commit represents a unique inmutable change to the system made by someone at a specific time
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(bound(
+    serialize = "T: Serialize, T::Id: Serialize, T::Change: Serialize",
+    deserialize = "T: Deserialize<'de>, T::Id: Deserialize<'de>, T::Change: Deserialize<'de>"
+))]
+pub struct Commit<T: Model> {
+    event: Event<T>,
+    when: DateTime<Utc>,
+    who: Author,
+    why: Reason,
+    transaction: Option<Uuid>,
+    expected_version: Option<u64>,
+    idempotency_key: Option<String>,
+    #[serde(default)]
+    attachments: Vec<BlobRef>,
+    #[serde(default)]
+    correlation_id: Option<EntityId>,
+    #[serde(default)]
+    causation_id: Option<EntityId>,
+}
+impl<T: Model> Commit<T> {
+    pub fn new(event: Event<T>, who: Author, why: Reason) -> Self {
+        Commit {
+            event,
+            when: Utc::now(),
+            who,
+            why,
+            transaction: None,
+            expected_version: None,
+            idempotency_key: None,
+            attachments: vec![],
+            correlation_id: None,
+            causation_id: None,
+        }
+    }
+}
+
+impl<T: Model> Commit<T> {
+    pub fn when(&self) -> DateTime<Utc> {
+        self.when
+    }
+
+    pub fn who(&self) -> Option<&str> {
+        self.who.as_deref()
+    }
+
+    pub fn why(&self) -> Option<&str> {
+        self.why.as_deref()
+    }
+
+    /// Who made the commit, same as `who`
+    pub fn author(&self) -> Option<&str> {
+        self.who()
+    }
+
+    /// Why the commit was made, same as `why`
+    pub fn reason(&self) -> Option<&str> {
+        self.why()
+    }
+
+    /// When the commit was made, same as `when`
+    pub fn timestamp(&self) -> DateTime<Utc> {
+        self.when()
+    }
+
+    /// Groups the commits caused by the same user action
+    pub fn transaction(&self) -> Option<Uuid> {
+        self.transaction
+    }
+
+    pub fn set_transaction(&mut self, transaction: Option<Uuid>) {
+        self.transaction = transaction;
+    }
+
+    /// Identifies the chain of commands the commit resulted from, commits of
+    /// the same originating request share it.
+    pub fn correlation_id(&self) -> Option<EntityId> {
+        self.correlation_id
+    }
+
+    pub fn set_correlation_id(&mut self, id: Option<EntityId>) {
+        self.correlation_id = id;
+    }
+
+    /// What directly caused the commit, callers use the id of the command
+    /// or request that triggered it as commits don't have ids of their own.
+    pub fn causation_id(&self) -> Option<EntityId> {
+        self.causation_id
+    }
+
+    pub fn set_causation_id(&mut self, id: Option<EntityId>) {
+        self.causation_id = id;
+    }
+
+    /// Marks the commit as caused by the given id, when the commit isn't
+    /// correlated to a chain yet the cause also starts one.
+    pub fn caused_by(mut self, id: EntityId) -> Self {
+        self.causation_id = Some(id);
+        self.correlation_id.get_or_insert(id);
+        self
+    }
+
+    /// The version the change was based on, stores that check it reject the
+    /// commit with a `Conflict` when the entity moved on in the meantime.
+    pub fn expected_version(&self) -> Option<u64> {
+        self.expected_version
+    }
+
+    pub fn set_expected_version(&mut self, version: Option<u64>) {
+        self.expected_version = version;
+    }
+
+    /// Identifies a request so retrying it doesn't commit the change twice
+    pub fn idempotency_key(&self) -> Option<&str> {
+        self.idempotency_key.as_deref()
+    }
+
+    pub fn set_idempotency_key(&mut self, key: Option<String>) {
+        self.idempotency_key = key;
+    }
+
+    /// Handles of the attachments of the commit kept in a `BlobStore`
+    pub fn attachments(&self) -> &[BlobRef] {
+        &self.attachments
+    }
+
+    pub fn attach(&mut self, blob: BlobRef) {
+        self.attachments.push(blob);
+    }
+
+    /// Where the commit goes in the history of the entity according to the
+    /// domain order of its change, see `Model::change_order`.
+    pub fn order_key(&self) -> Option<u64> {
+        match &self.event {
+            Event::Create(_) => None,
+            Event::Change(_, change) => T::change_order(change),
+            // after every change whatever the order of the domain
+            Event::Delete(_) => Some(u64::MAX),
+        }
+    }
+
+    pub fn set_when(&mut self, when: DateTime<Utc>) {
+        self.when = when;
+    }
+
+    pub fn set_who(&mut self, who: Author) {
+        self.who = who;
+    }
+
+    pub fn set_why(&mut self, why: Reason) {
+        self.why = why;
+    }
+}
+
+/// Tells caches that what they know about an entity older than `version`,
+/// the number of commits it has now, is stale.
+#[derive(Debug, Clone)]
+pub struct Invalidated<Id> {
+    pub id: Id,
+    pub version: u64,
+}
+
+/// Bus where stores publish invalidations to the `{store}-invalidations` topic
+pub type InvalidationBus<Id> = ChannelRef<Invalidated<Id>>;
+
+/// A commit published to subscribers along with its position in the history
+/// of the entity, the creation of the entity being the commit 0.
+#[derive(Debug, Clone)]
+pub struct PublishedCommit<T: Model> {
+    pub commit: Commit<T>,
+    pub sequence: u64,
+}
+
+/// Subscribes to the commits a store publishes to the commit bus handing
+/// them over as a stream, handy to consume them from async code.
+/// Dropping the stream unsubscribes from the bus.
+pub fn subscribe_channel<M: Model>(
+    sys: &ActorSystem,
+    bus: &CommitBus<M>,
+    store: &str,
+) -> BusStream<PublishedCommit<M>> {
+    BusStream::new(sys, bus, &format!("{}-events", store))
+}
+
+/// Streams the changes of a single entity as they are published to the
+/// commit bus of the store, skipping its creation and every other entity.
+/// Like `subscribe_channel` dropping it unsubscribes from the bus.
+pub fn change_stream<M: Model>(
+    sys: &ActorSystem,
+    bus: &CommitBus<M>,
+    store: &str,
+    id: M::Id,
+) -> BoxStream<'static, M::Change> {
+    subscribe_channel(sys, bus, store)
+        .filter_map(move |published| {
+            let change = match &published.event {
+                Event::Change(cid, change) if *cid == id => Some(change.clone()),
+                _ => None,
+            };
+            ready(change)
+        })
+        .boxed()
+}
+
+/// Streams the events a store publishes to the `topic` of its event bus,
+/// `{store}-events` for the events of a store named `store`. Dropping the
+/// stream unsubscribes from the bus and stops the actor bridging both.
+pub fn subscribe_stream<M: Model>(
+    sys: &ActorSystem,
+    bus: &EventBus<M>,
+    topic: &str,
+) -> EventStream<M> {
+    BusStream::new(sys, bus, topic)
+}
+
+/// Events published to a topic of an event bus, see `subscribe_stream`.
+pub type EventStream<M> = BusStream<Event<M>>;
+
+/// What's published to a topic of a bus, an actor subscribed to the topic
+/// hands it over until the stream is dropped, which unsubscribes the actor
+/// from the bus and stops it.
+pub struct BusStream<T: Message> {
+    rx: mpsc::UnboundedReceiver<T>,
+    subscriber: ActorRef<T>,
+    sys: ActorSystem,
+    bus: ChannelRef<T>,
+    topic: String,
+}
+
+impl<T: Message> BusStream<T> {
+    fn new(sys: &ActorSystem, bus: &ChannelRef<T>, topic: &str) -> Self {
+        let (rx, subscriber) = bridge(sys, bus, topic);
+        BusStream {
+            rx,
+            subscriber,
+            sys: sys.clone(),
+            bus: bus.clone(),
+            topic: topic.into(),
+        }
+    }
+}
+
+impl<T: Message> Stream for BusStream<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_next_unpin(cx)
+    }
+}
+
+impl<T: Message> Drop for BusStream<T> {
+    fn drop(&mut self) {
+        trace!("stream of {} dropped", self.topic);
+        self.bus.tell(
+            Unsubscribe {
+                topic: self.topic.as_str().into(),
+                actor: Box::new(self.subscriber.clone()),
+            },
+            None,
+        );
+        self.sys.stop(&self.subscriber);
+    }
+}
+
+/// Subscribes an actor to the topic of the bus that forwards what's published
+/// to the returned channel.
+fn bridge<T: Message>(
+    sys: &ActorSystem,
+    bus: &ChannelRef<T>,
+    topic: &str,
+) -> (mpsc::UnboundedReceiver<T>, ActorRef<T>) {
+    let (tx, rx) = mpsc::unbounded();
+    let subscriber = sys
+        .tmp_actor_of_args::<ChannelSubscriber<T>, _>((tx, bus.clone(), topic.to_string()))
+        .expect("create channel subscriber");
+    bus.tell(
+        Subscribe {
+            topic: topic.into(),
+            actor: Box::new(subscriber.clone()),
+        },
+        None,
+    );
+    (rx, subscriber)
+}
+
+struct ChannelSubscriber<T: Message> {
+    tx: mpsc::UnboundedSender<T>,
+    bus: ChannelRef<T>,
+    topic: String,
+}
+
+type SubscriberArgs<T> = (mpsc::UnboundedSender<T>, ChannelRef<T>, String);
+
+impl<T: Message> ActorFactoryArgs<SubscriberArgs<T>> for ChannelSubscriber<T> {
+    fn create_args((tx, bus, topic): SubscriberArgs<T>) -> Self {
+        ChannelSubscriber { tx, bus, topic }
+    }
+}
+
+impl<T: Message> Actor for ChannelSubscriber<T> {
+    type Msg = T;
+
+    fn recv(&mut self, cx: &Context<Self::Msg>, msg: Self::Msg, _sender: Sender) {
+        if self.tx.unbounded_send(msg).is_err() {
+            trace!("channel of {} subscriber closed", self.topic);
+            self.bus.tell(
+                Unsubscribe {
+                    topic: self.topic.as_str().into(),
+                    actor: Box::new(cx.myself()),
+                },
+                None,
+            );
+            cx.stop(cx.myself());
+        }
+    }
+}
+
+impl<T: Model> Deref for PublishedCommit<T> {
+    type Target = Commit<T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.commit
+    }
+}
+
+impl<T: Model> Deref for Commit<T> {
+    type Target = Event<T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.event
+    }
+}
+
+impl<T: Model> From<Event<T>> for Commit<T> {
+    fn from(e: Event<T>) -> Self {
+        Commit::new(e, None, None)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::testing::{collect, collect_events};
+    use crate::EntityId;
+    use futures::executor::block_on;
+    use riker_patterns::ask::ask;
+
+    #[derive(Default, Clone, Debug, Serialize, Deserialize)]
+    pub struct TestCount {
+        id: EntityId,
+        pub count: i16,
+    }
+    impl TestCount {
+        pub fn new(c: i16) -> Self {
+            Self {
+                count: c,
+                id: EntityId::new(),
+            }
+        }
+    }
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub enum Op {
+        Add(i16),
+        Sub(i16),
+    }
+    impl Model for TestCount {
+        type Id = EntityId;
+        type Change = Op;
+        fn id(&self) -> EntityId {
+            self.id
+        }
+        fn apply_change(&mut self, change: &Self::Change) {
+            match change {
+                Op::Add(n) => self.count += n,
+                Op::Sub(n) => self.count -= n,
+            };
+        }
+        fn check_invariants(&self) -> std::result::Result<(), String> {
+            if self.count < 0 {
+                return Err(format!("count of {} is negative", self.id));
+            }
+            Ok(())
+        }
+        fn reset_change(&self, target: &Self) -> Option<Op> {
+            Some(Op::Add(target.count - self.count))
+        }
+    }
+
+    type Hook = Arc<dyn Fn() -> BoxFuture<'static, CommitResult<()>> + Send + Sync>;
+
+    /// Delegates to a `MemStore` running hooks before some of its calls, to
+    /// slow them down, count them or make them fail, a failing hook fails the
+    /// call without reaching the backend.
+    #[derive(Clone)]
+    pub struct HookedStore {
+        backend: MemStore<TestCount>,
+        before_commit: Option<Hook>,
+        before_get: Option<Hook>,
+        before_keys: Option<Hook>,
+        before_ping: Option<Hook>,
+    }
+    impl HookedStore {
+        pub fn new(backend: MemStore<TestCount>) -> Self {
+            HookedStore {
+                backend,
+                before_commit: None,
+                before_get: None,
+                before_keys: None,
+                before_ping: None,
+            }
+        }
+
+        pub fn before_commit<F>(mut self, hook: F) -> Self
+        where
+            F: Fn() -> BoxFuture<'static, CommitResult<()>> + Send + Sync + 'static,
+        {
+            self.before_commit = Some(Arc::new(hook));
+            self
+        }
+
+        pub fn before_get<F>(mut self, hook: F) -> Self
+        where
+            F: Fn() -> BoxFuture<'static, CommitResult<()>> + Send + Sync + 'static,
+        {
+            self.before_get = Some(Arc::new(hook));
+            self
+        }
+
+        pub fn before_keys<F>(mut self, hook: F) -> Self
+        where
+            F: Fn() -> BoxFuture<'static, CommitResult<()>> + Send + Sync + 'static,
+        {
+            self.before_keys = Some(Arc::new(hook));
+            self
+        }
+
+        pub fn before_ping<F>(mut self, hook: F) -> Self
+        where
+            F: Fn() -> BoxFuture<'static, CommitResult<()>> + Send + Sync + 'static,
+        {
+            self.before_ping = Some(Arc::new(hook));
+            self
+        }
+
+        async fn run(hook: &Option<Hook>) -> CommitResult<()> {
+            match hook {
+                Some(hook) => hook().await,
+                None => Ok(()),
+            }
+        }
+    }
+    impl fmt::Debug for HookedStore {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "HookedStore({:?})", self.backend)
+        }
+    }
+    #[async_trait]
+    impl CommitStore<TestCount> for HookedStore {
+        fn keys(&self) -> BoxStream<CommitResult<EntityId>> {
+            Self::run(&self.before_keys)
+                .map(move |res| match res {
+                    Ok(()) => self.backend.keys(),
+                    Err(err) => futures::stream::once(ready(Err(err))).boxed(),
+                })
+                .flatten_stream()
+                .boxed()
+        }
+        fn change_list(&self, id: EntityId) -> BoxStream<CommitResult<Commit<TestCount>>> {
+            self.backend.change_list(id)
+        }
+        async fn commit(&self, c: Commit<TestCount>) -> CommitResult<()> {
+            Self::run(&self.before_commit).await?;
+            self.backend.commit(c).await
+        }
+        async fn get(&self, id: EntityId) -> CommitResult<TimeTraveler<'_, TestCount>> {
+            Self::run(&self.before_get).await?;
+            self.backend.get(id).await
+        }
+        async fn ping(&self) -> CommitResult<()> {
+            Self::run(&self.before_ping).await?;
+            self.backend.ping().await
+        }
+    }
+
+    /// Hook blocking the call for a while, like a backend under load
+    pub fn slow(ms: u64) -> impl Fn() -> BoxFuture<'static, CommitResult<()>> + Clone {
+        move || {
+            std::thread::sleep(std::time::Duration::from_millis(ms));
+            ok(()).boxed()
+        }
+    }
+
+    #[test]
+    fn derive_model() {
+        use crate::macros::Model;
+
+        #[derive(Default, Clone, Debug, Model)]
+        #[model(change = "Op", apply = "apply_op")]
+        struct DerivedCount {
+            #[model(id)]
+            id: EntityId,
+            count: i16,
+        }
+        impl DerivedCount {
+            fn apply_op(&mut self, change: &Op) {
+                match change {
+                    Op::Add(n) => self.count += n,
+                    Op::Sub(n) => self.count -= n,
+                };
+            }
+        }
+
+        let count = DerivedCount::default();
+        let id = count.id();
+        let store = MemStore::new();
+        block_on(store.commit(Event::Create(count).into())).unwrap();
+        block_on(store.commit(Event::Change(id, Op::Add(5)).into())).unwrap();
+        block_on(store.commit(Event::Change(id, Op::Sub(2)).into())).unwrap();
+        let count = block_on(store.snapshot(id, Utc::now())).unwrap();
+        assert_eq!(count.id(), id);
+        assert_eq!(count.count, 3);
+    }
+
+    #[test]
+    fn load_snapshot() {
+        let sys = ActorSystem::new().unwrap();
+        let store = sys
+            .actor_of_args::<Store<TestCount, _>, _>("test-counts", MemStore::new())
+            .unwrap();
+
+        let test = TestCount::default();
+        let id = test.id();
+        store.tell(Event::Create(test), None);
+        store.tell(Event::Change(id, Op::Add(15)), None);
+        store.tell(Event::Change(id, Op::Add(5)), None);
+        store.tell(Event::Change(id, Op::Sub(9)), None);
+        store.tell(Event::Change(id, Op::Add(31)), None);
+
+        let result: Option<TestCount> = block_on(ask(&sys, &store, (id, Utc::now())));
+        assert_eq!(result.unwrap().count, 42);
+    }
+
+    #[test]
+    fn load_snapshot_at_version() {
+        let sys = ActorSystem::new().unwrap();
+        let store = sys
+            .actor_of_args::<Store<TestCount, _>, _>("test-counts", MemStore::new())
+            .unwrap();
+
+        let test = TestCount::default();
+        let id = test.id();
+        let when = Utc::now();
+        let mut commits = vec![Commit::from(Event::Create(test))];
+        commits.push(Event::Change(id, Op::Add(15)).into());
+        commits.push(Event::Change(id, Op::Sub(5)).into());
+        for mut c in commits {
+            // the same moment can't tell the commits apart
+            c.set_when(when);
+            let res: CommitResult<()> = block_on(ask(&sys, &store, c));
+            res.unwrap();
+        }
+
+        let at = |version: u64| -> Option<TestCount> {
+            block_on(ask(&sys, &store, StoreMsg::from((id, version))))
+        };
+        assert!(at(0).is_none());
+        assert_eq!(at(1).unwrap().count, 0);
+        assert_eq!(at(2).unwrap().count, 15);
+        assert_eq!(at(3).unwrap().count, 10);
+        assert_eq!(at(9).unwrap().count, 10);
+        let unknown: Option<TestCount> =
+            block_on(ask(&sys, &store, StoreMsg::from(("123".into(), 1))));
+        assert!(unknown.is_none());
+    }
+
+    #[test]
+    fn non_existing_entity() {
+        let sys = ActorSystem::new().unwrap();
+        let store = sys
+            .actor_of_args::<Store<TestCount, _>, _>("test-counts", MemStore::new())
+            .unwrap();
+
+        let result: Option<TestCount> = block_on(ask(&sys, &store, ("123".into(), Utc::now())));
+        assert!(result.is_none());
+    }
+
+    #[test]
+    fn load_list_of_snapshots() {
+        let sys = ActorSystem::new().unwrap();
+        let store = sys
+            .actor_of_args::<Store<TestCount, _>, _>("test-counts", MemStore::new())
+            .unwrap();
+
+        let some_counter = TestCount {
+            id: "123".into(),
+            count: 42,
+        };
+        store.tell(Event::Create(some_counter), None);
+        store.tell(Event::Create(TestCount::default()), None);
+        store.tell(Event::Create(TestCount::default()), None);
+        store.tell(Event::Change("123".into(), Op::Add(8)), None);
+
+        let result: Vec<TestCount> = block_on(ask(&sys, &store, Utc::now()));
+        assert_eq!(result.len(), 3);
+        let some_counter_snapshot = result.iter().find(|s| s.id() == "123".into()).unwrap();
+        assert_eq!(some_counter_snapshot.count, 50);
+    }
+
+    #[test]
+    fn share_mem_store_across_systems() {
+        let backend = MemStore::new();
+        let sys1 = ActorSystem::with_name("node1").unwrap();
+        let sys2 = ActorSystem::with_name("node2").unwrap();
+        let store1 = sys1
+            .actor_of_args::<Store<TestCount, _>, _>("test-counts", backend.clone())
+            .unwrap();
+        let store2 = sys2
+            .actor_of_args::<Store<TestCount, _>, _>("test-counts", backend.clone())
+            .unwrap();
+
+        let test = TestCount::new(42);
+        let id = test.id();
+        let _: CommitResult<()> = block_on(ask(&sys1, &store1, Event::Create(test)));
+
+        let result: Option<TestCount> = block_on(ask(&sys2, &store2, (id, Utc::now())));
+        assert_eq!(result.unwrap().count, 42);
+    }
+
+    #[test]
+    fn cancel_list_of_snapshots() {
+        let backend = MemStore::new();
+        for _ in 0..10 {
+            block_on(backend.commit(Event::Create(TestCount::default()).into())).unwrap();
+        }
+        let (guard, token) = CancelToken::new();
+        let state = Arc::new(std::sync::Mutex::new((0, Some(guard))));
+        // drops the guard of the query the first time an entity is loaded
+        let cancelling = HookedStore::new(backend).before_get({
+            let state = state.clone();
+            move || {
+                let mut state = state.lock().unwrap();
+                state.0 += 1;
+                state.1.take();
+                ok(()).boxed()
+            }
+        });
+        let sys = ActorSystem::new().unwrap();
+        let store = sys
+            .actor_of_args::<Store<TestCount, _>, _>("test-counts", cancelling)
+            .unwrap();
+
+        store.tell((Utc::now(), token), None);
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        assert_eq!(state.lock().unwrap().0, 1);
+    }
+
+    #[test]
+    fn purge_idle_entities() {
+        let backend = MemStore::new();
+        let idle = TestCount::new(1);
+        let idle_id = idle.id();
+        let mut c: Commit<TestCount> = Event::Create(idle).into();
+        c.set_when(Utc::now() - chrono::Duration::hours(1));
+        block_on(backend.commit(c)).unwrap();
+        let active = TestCount::new(2);
+        let active_id = active.id();
+        block_on(backend.commit(Event::Create(active).into())).unwrap();
+
+        let sys = ActorSystem::new().unwrap();
+        let ttl = Ttl {
+            idle: chrono::Duration::minutes(10),
+            every: std::time::Duration::from_secs(3600),
+        };
+        let config = StoreConfig::default().ttl(ttl);
+        let store = sys
+            .actor_of_args::<Store<TestCount, _>, _>("sessions", (backend.clone(), config))
+            .unwrap();
+        let purged: usize = block_on(ask(&sys, &store, ExpireIdle));
+        assert_eq!(purged, 1);
+
+        let keys: Vec<_> = block_on(backend.keys().try_collect()).unwrap();
+        assert_eq!(keys, vec![active_id]);
+        assert!(block_on(backend.get(idle_id)).is_err());
+    }
+
+    #[test]
+    fn purge_deleted_entities_after_grace() {
+        let sys = ActorSystem::new().unwrap();
+        let start = Utc::now() - chrono::Duration::days(1);
+        let clock = crate::MockClock::new(start);
+        let shared: SharedClock = Arc::new(clock.clone());
+        let backend = MemStore::new();
+        let retention = Retention {
+            grace: chrono::Duration::hours(1),
+            every: std::time::Duration::from_secs(3600),
+        };
+        let store = sys
+            .actor_of_args::<Store<TestCount, _>, _>(
+                "retained",
+                (
+                    backend.clone(),
+                    StoreConfig::default().clock(shared).retention(retention),
+                ),
+            )
+            .unwrap();
+        let (deleted, kept) = (TestCount::new(1), TestCount::new(2));
+        let (deleted_id, kept_id) = (deleted.id(), kept.id());
+        let commit = |c: Commit<TestCount>| {
+            let result: CommitResult<()> = block_on(ask(&sys, &store, c));
+            result.unwrap();
+        };
+        commit(Event::Create(deleted).into());
+        commit(Event::Change(deleted_id, Op::Add(1)).into());
+        commit(Event::Create(kept).into());
+        clock.advance(chrono::Duration::minutes(30));
+        let deleted_at = start + chrono::Duration::minutes(30);
+        commit(Event::Delete(deleted_id).into());
+
+        clock.advance(chrono::Duration::minutes(59));
+        let purged: usize = block_on(ask(&sys, &store, PurgeDeleted));
+        assert_eq!(purged, 0);
+        // recoverable as of before the deletion during the grace period
+        let before = deleted_at - chrono::Duration::milliseconds(1);
+        let recovered =
+            block_on(async { backend.get(deleted_id).await?.travel_to(before).await }).unwrap();
+        assert_eq!(recovered.count, 2);
+
+        clock.advance(chrono::Duration::minutes(2));
+        let purged: usize = block_on(ask(&sys, &store, PurgeDeleted));
+        assert_eq!(purged, 1);
+        assert!(matches!(
+            block_on(backend.get(deleted_id)),
+            Err(CommitError::NotFound)
+        ));
+        let keys: Vec<_> = block_on(backend.keys().try_collect()).unwrap();
+        assert_eq!(keys, vec![kept_id]);
+    }
+
+    #[test]
+    fn rebuild_entities_in_parallel() {
+        let backend = MemStore::new();
+        for n in 0..10 {
+            block_on(backend.commit(Event::Create(TestCount::new(n)).into())).unwrap();
+        }
+        let active = Arc::new(std::sync::Mutex::new((0, 0)));
+        // keeps track of how many entities are being loaded at once
+        let store = HookedStore::new(backend).before_get({
+            let active = active.clone();
+            move || {
+                let active = active.clone();
+                async move {
+                    {
+                        let mut active = active.lock().unwrap();
+                        active.0 += 1;
+                        active.1 = active.1.max(active.0);
+                    }
+                    let (done, delay) = oneshot::channel();
+                    std::thread::spawn(move || {
+                        std::thread::sleep(std::time::Duration::from_millis(20));
+                        let _ = done.send(());
+                    });
+                    let _ = delay.await;
+                    active.lock().unwrap().0 -= 1;
+                    Ok(())
+                }
+                .boxed()
+            }
+        });
+        let sys = ActorSystem::new().unwrap();
+        let store = sys
+            .actor_of_args::<Store<TestCount, _>, _>(
+                "counts",
+                (store, StoreConfig::default().parallelism(3)),
+            )
+            .unwrap();
+
+        let mut counts: Vec<TestCount> = block_on(ask(&sys, &store, Utc::now()));
+        counts.sort_by_key(|c| c.count);
+        let counts: Vec<_> = counts.iter().map(|c| c.count).collect();
+        assert_eq!(counts, (0..10).collect::<Vec<_>>());
+        assert_eq!(active.lock().unwrap().1, 3);
+    }
+
+    #[test]
+    fn check_invariants_after_commit() {
+        let sys = ActorSystem::new().unwrap();
+        let spawn = |name, policy| {
+            let backend = MemStore::new();
+            let store = sys
+                .actor_of_args::<Store<TestCount, _>, _>(
+                    name,
+                    (backend.clone(), StoreConfig::default().invariants(policy)),
+                )
+                .unwrap();
+            (backend, store)
+        };
+        let (strict, strict_store) = spawn("strict", InvariantPolicy::Reject);
+        let (lenient, lenient_store) = spawn("lenient", InvariantPolicy::Log);
+        let count = TestCount::new(1);
+        let id = count.id();
+
+        for store in [&strict_store, &lenient_store] {
+            let created: CommitResult<()> =
+                block_on(ask(&sys, store, Commit::from(Event::Create(count.clone()))));
+            assert!(created.is_ok());
+            let ok: CommitResult<()> = block_on(ask(
+                &sys,
+                store,
+                Commit::from(Event::Change(id, Op::Sub(1))),
+            ));
+            assert!(ok.is_ok());
+        }
+        let broken: CommitResult<()> = block_on(ask(
+            &sys,
+            &strict_store,
+            Commit::from(Event::Change(id, Op::Sub(5))),
+        ));
+        assert!(matches!(broken, Err(CommitError::Invariant(_))));
+        let logged: CommitResult<()> = block_on(ask(
+            &sys,
+            &lenient_store,
+            Commit::from(Event::Change(id, Op::Sub(5))),
+        ));
+        assert!(logged.is_ok());
+
+        let count_of = |backend: &MemStore<TestCount>| {
+            block_on(async { backend.get(id).await?.to_present().await })
+                .unwrap()
+                .count
+        };
+        assert_eq!(count_of(&strict), 0);
+        assert_eq!(count_of(&lenient), -5);
+        let created: CommitResult<()> = block_on(ask(
+            &sys,
+            &strict_store,
+            Commit::from(Event::Create(TestCount::new(-1))),
+        ));
+        assert!(matches!(created, Err(CommitError::Invariant(_))));
+    }
+
+    #[test]
+    fn report_slow_commits() {
+        let sys = ActorSystem::new().unwrap();
+        let sys_bus: SystemBus = channel("system", &sys).unwrap();
+        let reported = collect_events(&sys, &sys_bus, crate::LIFECYCLE_TOPIC, 2);
+        let threshold = std::time::Duration::from_millis(20);
+        let store = sys
+            .actor_of_args::<Store<TestCount, _>, _>(
+                "slow",
+                (
+                    HookedStore::new(MemStore::new()).before_commit(slow(50)),
+                    StoreConfig::default()
+                        .sys_bus(sys_bus)
+                        .slow_commit(threshold),
+                ),
+            )
+            .unwrap();
+        let count = TestCount::new(1);
+        let id = count.id();
+        store.tell(Commit::from(Event::Create(count)), None);
+
+        let reported = block_on(reported);
+        assert_eq!(reported[0].kind, Lifecycle::Started);
+        match &reported[1].kind {
+            Lifecycle::SlowCommit { id: slow, took } => {
+                assert_eq!(slow, &id.to_string());
+                assert!(*took >= std::time::Duration::from_millis(50));
+            }
+            other => panic!("expected a slow commit, got {:?}", other),
+        }
+        assert_eq!(reported[1].name, "slow");
+    }
+
+    #[test]
+    fn spill_commits_while_backend_is_down() {
+        use std::sync::atomic::{AtomicBool, Ordering::SeqCst};
+
+        let sys = ActorSystem::new().unwrap();
+        let bus: CommitBus<_> = channel("commits", &sys).unwrap();
+        let down = Arc::new(AtomicBool::new(true));
+        let reachable = {
+            let down = down.clone();
+            move || match down.load(SeqCst) {
+                true => ready(Err(CommitError::Unavailable)).boxed(),
+                false => ok(()).boxed(),
+            }
+        };
+        let spawn = |name, degradation| {
+            let backend = MemStore::new();
+            let outage = Outage {
+                degradation,
+                probe_every: std::time::Duration::from_millis(10),
+            };
+            let flaky = HookedStore::new(backend.clone())
+                .before_commit(reachable.clone())
+                .before_ping(reachable.clone());
+            let config = StoreConfig::default()
+                .outage(outage)
+                .commit_bus(bus.clone());
+            let store = sys
+                .actor_of_args::<Store<TestCount, _>, _>(name, (flaky, config))
+                .unwrap();
+            (backend, store)
+        };
+        let (spilling, spilling_store) = spawn("spilling", Degradation::Spillover);
+        let (_, rejecting_store) = spawn("rejecting", Degradation::Reject);
+        let count = TestCount::new(1);
+        let id = count.id();
+
+        let rejected: CommitResult<()> = block_on(ask(
+            &sys,
+            &rejecting_store,
+            Commit::from(Event::Create(count.clone())),
+        ));
+        assert!(matches!(rejected, Err(CommitError::Unavailable)));
+        let mut commits = vec![Commit::from(Event::Create(count))];
+        commits.extend((1..=3).map(|n| Commit::from(Event::Change(id, Op::Add(n)))));
+        for c in commits {
+            let accepted: CommitResult<()> = block_on(ask(&sys, &spilling_store, c));
+            assert!(accepted.is_ok());
+        }
+        assert!(matches!(
+            block_on(spilling.get(id)),
+            Err(CommitError::NotFound)
+        ));
+
+        // spilled commits are published once they're flushed to the backend
+        let flushed = collect_events(&sys, &bus, "spilling-events", 4);
+        down.store(false, SeqCst);
+        assert_eq!(block_on(flushed).len(), 4);
+        let count = block_on(async { spilling.get(id).await?.to_present().await }).unwrap();
+        assert_eq!(count.count, 7);
+        let accepted: CommitResult<()> = block_on(ask(
+            &sys,
+            &spilling_store,
+            Commit::from(Event::Change(id, Op::Add(1))),
+        ));
+        assert!(accepted.is_ok());
+        let count = block_on(async { spilling.get(id).await?.to_present().await }).unwrap();
+        assert_eq!(count.count, 8);
+    }
+
+    #[test]
+    fn report_spilled_commits_failing_on_flush() {
+        use std::sync::atomic::{AtomicBool, Ordering::SeqCst};
+
+        let sys = ActorSystem::new().unwrap();
+        let sys_bus: SystemBus = channel("system", &sys).unwrap();
+        let reported = collect_events(&sys, &sys_bus, crate::LIFECYCLE_TOPIC, 2);
+        let down = Arc::new(AtomicBool::new(false));
+        let reachable = {
+            let down = down.clone();
+            move || match down.load(SeqCst) {
+                true => ready(Err(CommitError::Unavailable)).boxed(),
+                false => ok(()).boxed(),
+            }
+        };
+        let backend = MemStore::new();
+        let (kept, deleted) = (TestCount::new(1), TestCount::new(1));
+        let (kept_id, deleted_id) = (kept.id(), deleted.id());
+        block_on(backend.commit(Event::Create(kept).into())).unwrap();
+        block_on(backend.commit(Event::Create(deleted).into())).unwrap();
+        let outage = Outage {
+            degradation: Degradation::Spillover,
+            probe_every: std::time::Duration::from_millis(10),
+        };
+        let store = sys
+            .actor_of_args::<Store<TestCount, _>, _>(
+                "spilling",
+                (
+                    HookedStore::new(backend.clone())
+                        .before_commit(reachable.clone())
+                        .before_ping(reachable),
+                    StoreConfig::default().sys_bus(sys_bus).outage(outage),
+                ),
+            )
+            .unwrap();
+
+        down.store(true, SeqCst);
+        let mut versioned = Commit::from(Event::Change(kept_id, Op::Add(5)));
+        versioned.set_expected_version(Some(1));
+        let rejected: CommitResult<()> = block_on(ask(&sys, &store, versioned));
+        assert!(matches!(rejected, Err(CommitError::Unavailable)));
+        for c in vec![
+            Commit::from(Event::Change(kept_id, Op::Add(2))),
+            Commit::from(Event::Change(deleted_id, Op::Add(2))),
+        ] {
+            let accepted: CommitResult<()> = block_on(ask(&sys, &store, c));
+            assert!(accepted.is_ok());
+        }
+        // the spilled change conflicts with a delete that reached the backend
+        block_on(backend.commit(Event::Delete(deleted_id).into())).unwrap();
+        down.store(false, SeqCst);
+
+        let reported = block_on(reported);
+        match &reported[1].kind {
+            Lifecycle::SpillFailed { id, reason } => {
+                assert_eq!(id, &deleted_id.to_string());
+                assert_eq!(reason, &CommitError::CantChange.to_string());
+            }
+            other => panic!("expected a failed spill, got {:?}", other),
+        }
+        let kept = block_on(async { backend.get(kept_id).await?.to_present().await }).unwrap();
+        assert_eq!(kept.count, 3);
+    }
+
+    #[test]
+    fn page_across_deleted_entities() {
+        let sys = ActorSystem::new().unwrap();
+        let backend = MemStore::new();
+        let store = sys
+            .actor_of_args::<Store<TestCount, _>, _>("counts", backend.clone())
+            .unwrap();
+        for n in 0..5 {
+            block_on(backend.commit(Event::Create(TestCount::new(n)).into())).unwrap();
+        }
+        let keys: Vec<EntityId> = block_on(backend.keys().try_collect()).unwrap();
+        block_on(backend.commit(Event::Delete(keys[0]).into())).unwrap();
+
+        let until = Utc::now();
+        let page = |offset| -> Limited<TestCount> {
+            let page = SnapshotPage {
+                until,
+                offset,
+                limit: 2,
+            };
+            block_on(ask(&sys, &store, StoreMsg::from(page)))
+        };
+        let (first, second) = (page(0), page(2));
+        assert_eq!(first.entities.len(), 2);
+        assert!(first.truncated);
+        assert_eq!(second.entities.len(), 2);
+        assert!(!second.truncated);
+        let listed: Vec<EntityId> = first
+            .entities
+            .iter()
+            .chain(&second.entities)
+            .map(|c| c.id())
+            .collect();
+        assert_eq!(listed, keys[1..]);
+        assert!(page(4).entities.is_empty());
     }
-}
-impl<T: Model> From<Commit<T>> for StoreMsg<T> {
-    fn from(msg: Commit<T>) -> Self {
-        StoreMsg::Commit(msg)
+
+    #[test]
+    fn list_as_of_one_moment() {
+        let sys = ActorSystem::new().unwrap();
+        // the list waits for the keys of the backend until it's resumed
+        let (reached, listing) = std::sync::mpsc::channel();
+        let reached = std::sync::Mutex::new(reached);
+        let (resume, resumed) = oneshot::channel::<()>();
+        let resumed = resumed.shared();
+        let backend = HookedStore::new(MemStore::new()).before_keys(move || {
+            let _ = reached.lock().unwrap().send(());
+            resumed.clone().map(|_| Ok(())).boxed()
+        });
+        let store = sys
+            .actor_of_args::<Store<TestCount, _>, _>("counts", backend)
+            .unwrap();
+        let count = TestCount::new(1);
+        let id = count.id();
+        let commit = |c: Commit<TestCount>| {
+            let res: CommitResult<()> = block_on(ask(&sys, &store, c));
+            res.unwrap();
+        };
+        commit(Event::Create(count).into());
+        let listed: futures::future::RemoteHandle<Vec<TestCount>> =
+            ask(&sys, &store, StoreMsg::from(Utc::now()));
+        listing
+            .recv_timeout(std::time::Duration::from_secs(5))
+            .expect("list reaches the backend");
+        commit(Event::Change(id, Op::Add(10)).into());
+        commit(Event::Create(TestCount::new(5)).into());
+        resume.send(()).unwrap();
+        let listed = block_on(listed);
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].count, 1);
+
+        let settled = sys
+            .actor_of_args::<Store<TestCount, _>, _>(
+                "settled",
+                (
+                    HookedStore::new(MemStore::new()).before_commit(slow(50)),
+                    StoreConfig::default().consistency(ReadConsistency::Settled),
+                ),
+            )
+            .unwrap();
+        settled.tell(Commit::from(Event::Create(TestCount::new(3))), None);
+        let listed: Vec<TestCount> = block_on(ask(&sys, &settled, StoreMsg::from(Utc::now())));
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].count, 3);
     }
-}
-impl<T: Model> From<EntityId> for StoreMsg<T> {
-    fn from(id: EntityId) -> Self {
-        StoreMsg::Subscribe(id)
+
+    fn roundtrip<M: crate::SerializableModel>(c: &Commit<M>) -> Commit<M> {
+        serde_json::from_slice(&serde_json::to_vec(c).unwrap()).unwrap()
     }
-}
-impl<T: Model> From<(EntityId, DateTime<Utc>)> for StoreMsg<T> {
-    fn from(snap: (EntityId, DateTime<Utc>)) -> Self {
-        StoreMsg::Snapshot(snap)
+
+    #[test]
+    fn serialize_commits_of_any_model() {
+        let count = TestCount::new(3);
+        let id = count.id();
+        let created = roundtrip(&Commit::new(Event::Create(count), None, None));
+        assert!(matches!(&*created, Event::Create(c) if c.count == 3));
+        let mut change: Commit<TestCount> = Commit::new(
+            Event::Change(id, Op::Add(2)),
+            Some("someone".into()),
+            Some("because".into()),
+        );
+        change.set_expected_version(Some(1));
+        let decoded = roundtrip(&change);
+        assert_eq!(decoded.entity_id(), id);
+        assert_eq!(decoded.when(), change.when());
+        assert_eq!(decoded.who(), Some("someone"));
+        assert_eq!(decoded.why(), Some("because"));
+        assert_eq!(decoded.expected_version(), Some(1));
     }
-}
 
-type Author = Option<String>;
-type Reason = Option<String>;
+    #[test]
+    fn export_and_import_whole_store() {
+        let store = MemStore::new();
+        let mut ids = vec![];
+        for n in [1, 2] {
+            let count = TestCount::new(n);
+            ids.push(count.id());
+            block_on(store.commit(Event::Create(count).into())).unwrap();
+        }
+        let add = Commit::new(
+            Event::Change(ids[0], Op::Add(5)),
+            Some("tester".into()),
+            Some("add 5".into()),
+        );
+        block_on(store.commit(add)).unwrap();
+        block_on(store.commit(Event::Change(ids[1], Op::Sub(1)).into())).unwrap();
 
-/// Commit represents a unique inmutable change to the system made by someone at a specific time
-#[derive(Debug, Clone)]
-pub struct Commit<T: Model> {
-    event: Event<T>,
-    when: DateTime<Utc>,
-    who: Author,
-    why: Reason,
-}
-impl<T: Model> Commit<T> {
-    pub fn new(event: Event<T>, who: Author, why: Reason) -> Self {
-        Commit {
-            event,
-            when: Utc::now(),
-            who,
-            why,
+        let mut archive = vec![];
+        assert_eq!(block_on(store.export(&mut archive)).unwrap(), 2);
+        let copy = MemStore::new();
+        assert_eq!(block_on(copy.import(&archive[..])).unwrap(), 2);
+
+        for id in ids {
+            let history = |s: &MemStore<TestCount>| {
+                let commits: Vec<_> = block_on(s.change_list(id).try_collect()).unwrap();
+                serde_json::to_string(&commits).unwrap()
+            };
+            assert_eq!(history(&copy), history(&store));
+            let now = Utc::now();
+            let count = |s: &MemStore<TestCount>| block_on(s.snapshot(id, now)).unwrap().count;
+            assert_eq!(count(&copy), count(&store));
         }
+        let again = block_on(copy.import(&archive[..]));
+        assert!(matches!(again, Err(CommitError::AlreadyExists)));
     }
-}
 
-impl<T: Model> Deref for Commit<T> {
-    type Target = Event<T>;
+    #[test]
+    fn subscribe_to_one_entity() {
+        let sys = ActorSystem::new().unwrap();
+        let bus: EventBus<_> = channel("bus", &sys).unwrap();
+        let store = sys
+            .actor_of_args::<Store<TestCount, _>, _>("counts", (MemStore::new(), bus.clone()))
+            .unwrap();
+        let (count, other) = (TestCount::new(1), TestCount::new(2));
+        let (id, other_id) = (count.id(), other.id());
+        for created in [count, other] {
+            let res: CommitResult<()> =
+                block_on(ask(&sys, &store, Commit::from(Event::Create(created))));
+            res.unwrap();
+        }
 
-    fn deref(&self) -> &Self::Target {
-        &self.event
+        let on_topic = collect_events(&sys, &bus, &format!("counts-{}-events", id), 1);
+        let first: futures::future::RemoteHandle<Event<TestCount>> =
+            ask(&sys, &store, StoreMsg::Subscribe(id));
+        store.tell(Event::Change(other_id, Op::Add(5)), None);
+        store.tell(Event::Change(id, Op::Add(1)), None);
+        assert!(matches!(block_on(first), Event::Change(cid, Op::Add(1)) if cid == id));
+        let on_topic = block_on(on_topic);
+        assert!(matches!(&on_topic[0], Event::Change(cid, Op::Add(1)) if *cid == id));
     }
-}
 
-impl<T: Model> From<Event<T>> for Commit<T> {
-    fn from(e: Event<T>) -> Self {
-        Commit::new(e, None, None)
+    #[test]
+    fn keep_working_after_failed_commit() {
+        let sys = ActorSystem::new().unwrap();
+        let store = sys
+            .actor_of_args::<Store<TestCount, _>, _>("counts", MemStore::new())
+            .unwrap();
+        let orphan = Commit::from(Event::Change(EntityId::new(), Op::Add(1)));
+        store.tell(orphan.clone(), None);
+        let res: CommitResult<()> = block_on(ask(&sys, &store, orphan));
+        assert!(matches!(res, Err(CommitError::CantChange)));
+
+        let count = TestCount::new(1);
+        let id = count.id();
+        let res: CommitResult<()> = block_on(ask(&sys, &store, Commit::from(Event::Create(count))));
+        assert!(res.is_ok());
+        let count: Option<TestCount> =
+            block_on(ask(&sys, &store, StoreMsg::from((id, Utc::now()))));
+        assert_eq!(count.unwrap().count, 1);
     }
-}
 
-#[cfg(test)]
-pub(crate) mod tests {
-    use super::*;
-    use futures::executor::block_on;
-    use riker_patterns::ask::ask;
+    #[test]
+    fn snapshot_between_changes() {
+        let store = MemStore::new();
+        let start = Utc::now() - chrono::Duration::hours(3);
+        let count = TestCount::new(1);
+        let id = count.id();
+        let commit = |c: Commit<TestCount>, hours| {
+            let mut c = c;
+            c.set_when(start + chrono::Duration::hours(hours));
+            block_on(store.commit(c)).unwrap();
+        };
+        commit(Event::Create(count).into(), 0);
+        commit(Event::Change(id, Op::Add(10)).into(), 1);
+        commit(Event::Change(id, Op::Add(100)).into(), 2);
 
-    #[derive(Default, Clone, Debug)]
-    pub struct TestCount {
-        id: EntityId,
-        pub count: i16,
+        let at = |minutes| {
+            let when = start + chrono::Duration::minutes(minutes);
+            block_on(store.snapshot(id, when)).unwrap().count
+        };
+        assert_eq!(at(30), 1);
+        assert_eq!(at(90), 11);
+        assert_eq!(at(150), 111);
     }
-    impl TestCount {
-        pub fn new(c: i16) -> Self {
-            Self {
-                count: c,
-                id: EntityId::new(),
+
+    #[test]
+    fn travel_to_includes_until() {
+        let store = MemStore::new();
+        let count = TestCount::new(1);
+        let id = count.id();
+        let mut created: Commit<TestCount> = Event::Create(count).into();
+        let created_at = Utc::now() - chrono::Duration::seconds(2);
+        created.set_when(created_at);
+        block_on(store.commit(created)).unwrap();
+        let mut c: Commit<TestCount> = Event::Change(id, Op::Add(1)).into();
+        let when = Utc::now() - chrono::Duration::seconds(1);
+        c.set_when(when);
+        block_on(store.commit(c)).unwrap();
+
+        let at = |until| block_on(async { store.get(id).await?.travel_to(until).await });
+        assert_eq!(at(when).unwrap().count, 2);
+        assert_eq!(
+            at(when - chrono::Duration::milliseconds(1)).unwrap().count,
+            1
+        );
+        assert_eq!(at(created_at).unwrap().count, 1);
+        assert!(matches!(
+            at(created_at - chrono::Duration::milliseconds(1)),
+            Err(CommitError::NotFound)
+        ));
+    }
+
+    #[test]
+    fn travel_to_before_deletion() {
+        let sys = ActorSystem::new().unwrap();
+        let backend = MemStore::new().snapshot_every(1);
+        let store = sys
+            .actor_of_args::<Store<TestCount, _>, _>("counts", backend.clone())
+            .unwrap();
+        let commit = |c: Commit<TestCount>| -> CommitResult<()> {
+            block_on(ask(&sys, &store, StoreMsg::from(c)))
+        };
+        let (count, other) = (TestCount::new(1), TestCount::new(2));
+        let (id, other_id) = (count.id(), other.id());
+        commit(Event::Create(count).into()).unwrap();
+        commit(Event::Create(other).into()).unwrap();
+        commit(Event::Change(id, Op::Add(1)).into()).unwrap();
+        let before = Utc::now();
+        let deletion: Commit<TestCount> = Event::Delete(id).into();
+        let deleted_at = deletion.when();
+        commit(deletion).unwrap();
+
+        let res = commit(Event::Change(id, Op::Add(1)).into());
+        assert!(matches!(res, Err(CommitError::CantChange)));
+        let at = |when| block_on(async { backend.get(id).await?.travel_to(when).await });
+        assert_eq!(at(before).unwrap().count, 2);
+        assert!(matches!(at(deleted_at), Err(CommitError::Deleted)));
+        assert!(matches!(
+            block_on(backend.snapshot_at_version(id, 4)),
+            Err(CommitError::Deleted)
+        ));
+        assert_eq!(block_on(backend.change_list(id).count()), 3);
+
+        let now: Option<TestCount> = block_on(ask(&sys, &store, StoreMsg::from((id, Utc::now()))));
+        assert!(now.is_none());
+        let listed: Vec<TestCount> = block_on(ask(&sys, &store, Utc::now()));
+        assert_eq!(
+            listed.iter().map(|c| c.id()).collect::<Vec<_>>(),
+            vec![other_id]
+        );
+        let listed: Vec<TestCount> = block_on(ask(&sys, &store, before));
+        assert_eq!(listed.len(), 2);
+    }
+
+    #[test]
+    fn reject_corrupt_histories() {
+        #[derive(Debug, Clone)]
+        struct Corrupt(Vec<Commit<TestCount>>);
+        #[async_trait]
+        impl CommitStore<TestCount> for Corrupt {
+            fn keys(&self) -> BoxStream<CommitResult<EntityId>> {
+                let id = self.0.first().map(|c| Ok(c.entity_id()));
+                futures::stream::iter(id).boxed()
+            }
+            fn change_list(&self, _: EntityId) -> BoxStream<CommitResult<Commit<TestCount>>> {
+                futures::stream::iter(self.0.clone().into_iter().map(Ok)).boxed()
+            }
+            async fn commit(&self, _: Commit<TestCount>) -> CommitResult<()> {
+                Ok(())
             }
         }
+
+        let count = TestCount::new(1);
+        let id = count.id();
+        let created: Commit<TestCount> = Event::Create(count).into();
+        let changed: Commit<TestCount> = Event::Change(id, Op::Add(1)).into();
+        let invalid = |res| matches!(res, Err(CommitError::InvalidHistory(_)));
+
+        let store = Corrupt(vec![changed.clone(), created.clone()]);
+        assert!(invalid(block_on(store.get(id)).map(|_| ())));
+        assert!(invalid(
+            block_on(store.snapshot_after(id, |_| true)).map(|_| ())
+        ));
+        assert!(invalid(
+            block_on(store.compensate(id, Utc::now())).map(|_| ())
+        ));
+        let sys = ActorSystem::new().unwrap();
+        let actor = sys
+            .actor_of_args::<Store<TestCount, _>, _>("corrupt", store)
+            .unwrap();
+        let listed: Vec<TestCount> = block_on(ask(&sys, &actor, StoreMsg::from(Utc::now())));
+        assert!(listed.is_empty());
+        let store = Corrupt(vec![created.clone(), created, changed]);
+        assert!(invalid(
+            block_on(store.snapshot(id, Utc::now())).map(|_| ())
+        ));
+        assert!(invalid(
+            block_on(store.snapshot_at_version(id, 3)).map(|_| ())
+        ));
     }
-    #[derive(Clone, Debug)]
-    pub enum Op {
-        Add(i16),
-        Sub(i16),
+
+    #[test]
+    fn present_includes_slightly_future_commits() {
+        let store = MemStore::new();
+        let count = TestCount::new(1);
+        let id = count.id();
+        block_on(store.commit(Event::Create(count).into())).unwrap();
+        let mut c: Commit<TestCount> = Event::Change(id, Op::Add(1)).into();
+        c.set_when(Utc::now() + chrono::Duration::milliseconds(100));
+        block_on(store.commit(c)).unwrap();
+
+        let present = block_on(async { store.get(id).await?.to_present().await });
+        assert_eq!(present.unwrap().count, 2);
+        let strict = block_on(async {
+            let traveler = store.get(id).await?;
+            traveler
+                .with_skew_tolerance(chrono::Duration::zero())
+                .to_present()
+                .await
+        });
+        assert_eq!(strict.unwrap().count, 1);
     }
-    impl Model for TestCount {
-        type Change = Op;
-        fn id(&self) -> EntityId {
-            self.id
-        }
-        fn apply_change(&mut self, change: &Self::Change) {
-            match change {
-                Op::Add(n) => self.count += n,
-                Op::Sub(n) => self.count -= n,
-            };
-        }
+
+    #[test]
+    fn snapshot_after_authors_change() {
+        let store = MemStore::new();
+        let count = TestCount::new(1);
+        let id = count.id();
+        let by = |who: &str, op| Commit::new(Event::Change(id, op), Some(who.into()), None);
+        block_on(store.commit(Event::Create(count).into())).unwrap();
+        block_on(store.commit(by("bob", Op::Add(1)))).unwrap();
+        block_on(store.commit(by("alice", Op::Add(10)))).unwrap();
+        block_on(store.commit(by("bob", Op::Sub(5)))).unwrap();
+        block_on(store.commit(by("alice", Op::Add(100)))).unwrap();
+
+        let after_alice = block_on(store.snapshot_after(id, |c| c.who() == Some("alice")));
+        assert_eq!(after_alice.unwrap().count, 12);
+        let after_bob = block_on(store.snapshot_after(id, |c| c.who() == Some("bob")));
+        assert_eq!(after_bob.unwrap().count, 2);
+        let after_eve = block_on(store.snapshot_after(id, |c| c.who() == Some("eve")));
+        assert!(matches!(after_eve, Err(CommitError::NotFound)));
     }
 
     #[test]
-    fn load_snapshot() {
+    fn invalidate_on_commit() {
         let sys = ActorSystem::new().unwrap();
+        let bus: InvalidationBus<EntityId> = channel("invalidations", &sys).unwrap();
         let store = sys
-            .actor_of_args::<Store<TestCount, _>, _>("test-counts", MemStore::new())
+            .actor_of_args::<Store<TestCount, _>, _>(
+                "counts",
+                (
+                    MemStore::new(),
+                    StoreConfig::default().invalidations(bus.clone()),
+                ),
+            )
             .unwrap();
 
-        let test = TestCount::default();
-        let id = test.id();
-        store.tell(Event::Create(test), None);
-        store.tell(Event::Change(id, Op::Add(15)), None);
-        store.tell(Event::Change(id, Op::Add(5)), None);
-        store.tell(Event::Change(id, Op::Sub(9)), None);
-        store.tell(Event::Change(id, Op::Add(31)), None);
+        let invalidated = collect_events(&sys, &bus, "counts-invalidations", 2);
 
-        let result: Option<TestCount> = block_on(ask(&sys, &store, (id, Utc::now())));
-        assert_eq!(result.unwrap().count, 42);
+        let count = TestCount::default();
+        let id = count.id();
+        let created: CommitResult<()> = block_on(ask(
+            &sys,
+            &store,
+            StoreMsg::from(Commit::from(Event::Create(count))),
+        ));
+        created.unwrap();
+        store.tell(Event::Change(id, Op::Add(1)), None);
+
+        let invalidated: Vec<_> = block_on(invalidated)
+            .into_iter()
+            .map(|i| (i.id, i.version))
+            .collect();
+        assert_eq!(invalidated, vec![(id, 1), (id, 2)]);
     }
 
     #[test]
-    fn non_existing_entity() {
+    fn replay_changes_from_other_state() {
+        let store = MemStore::new();
+        let count = TestCount::new(5);
+        let id = count.id();
+        block_on(store.commit(Event::Create(count.clone()).into())).unwrap();
+        block_on(store.commit(Event::Change(id, Op::Add(10)).into())).unwrap();
+        block_on(store.commit(Event::Change(id, Op::Sub(3)).into())).unwrap();
+
+        let stored = block_on(store.snapshot(id, Utc::now())).unwrap();
+        let what_if = TestCount {
+            count: 100,
+            ..count
+        };
+        let replayed = block_on(store.replay_from(id, what_if)).unwrap();
+        assert_eq!(stored.count, 12);
+        assert_eq!(replayed.count - stored.count, 100 - 5);
+        assert!(block_on(store.replay_from(EntityId::new(), TestCount::new(1))).is_err());
+    }
+
+    #[test]
+    fn travel_with_mock_clock() {
+        let start = Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap();
+        let clock = crate::MockClock::new(start);
         let sys = ActorSystem::new().unwrap();
+        let shared: SharedClock = Arc::new(clock.clone());
         let store = sys
-            .actor_of_args::<Store<TestCount, _>, _>("test-counts", MemStore::new())
+            .actor_of_args::<Store<TestCount, _>, _>(
+                "counts",
+                (MemStore::new(), StoreConfig::default().clock(shared)),
+            )
             .unwrap();
+        let commit = |c: Commit<TestCount>| {
+            let res: CommitResult<()> = block_on(ask(&sys, &store, StoreMsg::from(c)));
+            res.unwrap();
+        };
 
-        let result: Option<TestCount> = block_on(ask(&sys, &store, ("123".into(), Utc::now())));
-        assert!(result.is_none());
+        let count = TestCount::new(1);
+        let id = count.id();
+        commit(Event::Create(count).into());
+        clock.advance(chrono::Duration::hours(1));
+        commit(Event::Change(id, Op::Add(10)).into());
+        clock.advance(chrono::Duration::hours(1));
+        commit(Event::Change(id, Op::Add(100)).into());
+
+        let at = |minutes| {
+            let when = start + chrono::Duration::minutes(minutes);
+            let count: Option<TestCount> = block_on(ask(&sys, &store, StoreMsg::from((id, when))));
+            count.unwrap().count
+        };
+        assert_eq!(at(30), 1);
+        assert_eq!(at(60), 11);
+        assert_eq!(at(90), 11);
+        assert_eq!(at(120), 111);
     }
 
     #[test]
-    fn load_list_of_snapshots() {
+    fn count_entities_at_past_times() {
+        let t = |h| Utc.with_ymd_and_hms(2020, 1, 1, h, 0, 0).unwrap();
+        let store = MemStore::new();
+        let mut ids = vec![];
+        for h in [1, 2, 3] {
+            let count = TestCount::new(h as i16);
+            ids.push(count.id());
+            let mut c: Commit<TestCount> = Event::Create(count).into();
+            c.set_when(t(h));
+            block_on(store.commit(c)).unwrap();
+        }
+        let mut c: Commit<TestCount> = Event::Change(ids[0], Op::Add(1)).into();
+        c.set_when(t(4));
+        block_on(store.commit(c)).unwrap();
+
+        assert_eq!(block_on(store.count_at(t(0))).unwrap(), 0);
+        assert_eq!(block_on(store.count_at(t(1))).unwrap(), 1);
+        assert_eq!(block_on(store.count_at(t(2))).unwrap(), 2);
+        assert_eq!(block_on(store.count_at(t(5))).unwrap(), 3);
+        block_on(store.purge(ids[1])).unwrap();
+        assert_eq!(block_on(store.count_at(t(2))).unwrap(), 1);
+        assert_eq!(block_on(store.count_at(t(5))).unwrap(), 2);
+
+        let mut c: Commit<TestCount> = Event::Delete(ids[2]).into();
+        c.set_when(t(6));
+        block_on(store.commit(c)).unwrap();
+        assert_eq!(block_on(store.count_at(t(5))).unwrap(), 2);
+        assert_eq!(block_on(store.count_at(t(6))).unwrap(), 1);
+        assert_eq!(block_on(store.count_at(t(7))).unwrap(), 1);
+    }
+
+    #[test]
+    fn aggregate_entities() {
+        let store = MemStore::new();
+        for n in 1..=3 {
+            let count = TestCount::new(n);
+            let id = count.id();
+            block_on(store.commit(Event::Create(count).into())).unwrap();
+            block_on(store.commit(Event::Change(id, Op::Add(10)).into())).unwrap();
+        }
+        let gone = TestCount::new(50);
+        let gone_id = gone.id();
+        block_on(store.commit(Event::Create(gone).into())).unwrap();
+        block_on(store.commit(Event::Delete(gone_id).into())).unwrap();
+        let before = Utc::now();
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        let late = TestCount::new(100);
+        block_on(store.commit(Event::Create(late).into())).unwrap();
+
+        let sum = |time| block_on(store.aggregate(time, 0, |sum, c| sum + c.count)).unwrap();
+        assert_eq!(sum(before), 36);
+        assert_eq!(sum(Utc::now()), 136);
+        let max =
+            block_on(store.aggregate(before, None, |max: Option<i16>, c| max.max(Some(c.count))));
+        assert_eq!(max.unwrap(), Some(13));
+    }
+
+    #[test]
+    fn replay_only_creations() {
+        let store = MemStore::new();
+        let start = Utc::now();
+        for n in 0..3 {
+            let count = TestCount::new(n);
+            let id = count.id();
+            block_on(store.commit(Event::Create(count).into())).unwrap();
+            block_on(store.commit(Event::Change(id, Op::Add(1)).into())).unwrap();
+        }
+
+        let creations: Vec<_> = block_on(
+            store
+                .commits_since(start, vec![EventKind::Create])
+                .try_collect(),
+        )
+        .unwrap();
+        assert_eq!(creations.len(), 3);
+        assert!(creations.iter().all(|c| c.kind() == EventKind::Create));
+        let all: Vec<_> = block_on(
+            store
+                .commits_since(start, vec![EventKind::Create, EventKind::Change])
+                .try_collect(),
+        )
+        .unwrap();
+        assert_eq!(all.len(), 6);
+        let later = Utc::now() + chrono::Duration::seconds(1);
+        let none: Vec<_> = block_on(
+            store
+                .commits_since(later, vec![EventKind::Create])
+                .try_collect(),
+        )
+        .unwrap();
+        assert!(none.is_empty());
+    }
+
+    #[test]
+    fn find_by_change_history() {
+        let store = MemStore::new();
+        let big_spender = TestCount::new(0);
+        let big_id = big_spender.id();
+        let saver = TestCount::new(0);
+        let saver_id = saver.id();
+        block_on(async {
+            store.commit(Event::Create(big_spender).into()).await?;
+            store
+                .commit(Event::Change(big_id, Op::Sub(100)).into())
+                .await?;
+            store
+                .commit(Event::Change(big_id, Op::Add(100)).into())
+                .await?;
+            store.commit(Event::Create(saver).into()).await?;
+            store
+                .commit(Event::Change(saver_id, Op::Sub(5)).into())
+                .await
+        })
+        .unwrap();
+
+        let found: Vec<EntityId> = block_on(
+            store
+                .find_by_history(|commits| {
+                    commits
+                        .iter()
+                        .any(|c| matches!(c.change(), Some(Op::Sub(n)) if n > 50))
+                })
+                .try_collect(),
+        )
+        .unwrap();
+        assert_eq!(found, vec![big_id]);
+    }
+
+    #[test]
+    fn subscribe_without_bus() {
         let sys = ActorSystem::new().unwrap();
         let store = sys
             .actor_of_args::<Store<TestCount, _>, _>("test-counts", MemStore::new())
             .unwrap();
 
-        let some_counter = TestCount {
-            id: "123".into(),
-            count: 42,
+        let count = TestCount::new(1);
+        let id = count.id();
+        store.tell(Event::Create(count), None);
+        let first: futures::future::RemoteHandle<Event<TestCount>> =
+            ask(&sys, &store, StoreMsg::Subscribe(id));
+        store.tell(Event::Change(id, Op::Add(1)), None);
+        assert!(matches!(block_on(first), Event::Change(cid, Op::Add(1)) if cid == id));
+    }
+
+    #[test]
+    fn drop_subscribers_that_cant_be_told() {
+        let sys = ActorSystem::new().unwrap();
+        // stores don't take events so telling them fails
+        let deaf = sys
+            .actor_of_args::<Store<TestCount, _>, _>("deaf", MemStore::new())
+            .unwrap();
+        let count = TestCount::new(1);
+        let id = count.id();
+        let entity_subs = EntitySubs::default();
+        entity_subs
+            .lock()
+            .unwrap()
+            .insert(id.to_string(), vec![deaf.into()]);
+        let subscribers = Subscribers {
+            bus: None,
+            commit_bus: None,
+            invalidations: None,
+            prefix_subs: vec![],
+            entity_subs: entity_subs.clone(),
+            store: "counts".into(),
         };
-        store.tell(Event::Create(some_counter), None);
-        store.tell(Event::Create(TestCount::default()), None);
-        store.tell(Event::Create(TestCount::default()), None);
-        store.tell(Event::Change("123".into(), Op::Add(8)), None);
+        subscribers.publish(Event::Create(count).into(), 0);
+        assert!(entity_subs.lock().unwrap().is_empty());
+    }
 
-        let result: Vec<TestCount> = block_on(ask(&sys, &store, Utc::now()));
-        assert_eq!(result.len(), 3);
-        let some_counter_snapshot = result.iter().find(|s| s.id() == "123".into()).unwrap();
-        assert_eq!(some_counter_snapshot.count, 50);
+    #[test]
+    fn subscribe_to_prefix() {
+        #[derive(Clone, Debug)]
+        struct Doc(String);
+        impl Model for Doc {
+            type Id = String;
+            type Change = ();
+            fn id(&self) -> String {
+                self.0.clone()
+            }
+            fn apply_change(&mut self, _change: &()) {}
+        }
+
+        let sys = ActorSystem::new().unwrap();
+        let store = sys
+            .actor_of_args::<Store<Doc, _>, _>("docs", MemStore::new())
+            .unwrap();
+        let (sub, received) = collect::<Event<Doc>>(&sys, 2);
+        store.tell(
+            StoreMsg::SubscribePrefix("acme/".into(), Box::new(sub)),
+            None,
+        );
+        store.tell(Event::Create(Doc("acme/1".into())), None);
+        store.tell(Event::Create(Doc("other/1".into())), None);
+        store.tell(Event::Create(Doc("acme/2".into())), None);
+
+        let mut received: Vec<String> = block_on(received).iter().map(|e| e.entity_id()).collect();
+        received.sort();
+        assert_eq!(received, vec!["acme/1".to_string(), "acme/2".to_string()]);
     }
 
     #[test]
@@ -395,46 +3927,164 @@ pub(crate) mod tests {
             .actor_of_args::<Store<TestCount, _>, _>(store_name, (MemStore::new(), bus.clone()))
             .unwrap();
 
-        #[derive(Clone, Debug)]
-        enum TestSubMsg {
-            Event(Event<TestCount>),
-            Get,
-        }
-        impl From<Event<TestCount>> for TestSubMsg {
-            fn from(event: Event<TestCount>) -> Self {
-                TestSubMsg::Event(event)
-            }
-        }
+        let events = collect_events(&sys, &bus, &format!("{}-events", store_name), 1);
+        let count = TestCount::default();
+        let id = count.id();
+        store.tell(Event::Create(count), None);
 
-        #[derive(Default)]
-        struct TestSub(Option<Event<TestCount>>);
-        impl Actor for TestSub {
-            type Msg = TestSubMsg;
-            fn recv(&mut self, _cx: &Context<Self::Msg>, msg: Self::Msg, sender: Sender) {
-                match msg {
-                    TestSubMsg::Get => {
-                        sender.unwrap().try_tell(self.0.clone(), None).unwrap();
-                    }
-                    TestSubMsg::Event(e) => {
-                        self.0 = Some(e);
-                    }
-                }
-            }
-        }
+        let events = block_on(events);
+        assert!(matches!(&events[..], [Event::Create(c)] if c.id() == id));
+    }
 
-        let sub = sys.actor_of::<TestSub>("subscriber").unwrap();
-        bus.tell(
-            Subscribe {
-                topic: format!("{}-events", store_name).into(),
-                actor: Box::new(sub.clone()),
-            },
+    #[test]
+    fn publish_commits_with_metadata() {
+        let sys = ActorSystem::new().unwrap();
+        let bus: CommitBus<_> = channel("commits", &sys).unwrap();
+        let store = sys
+            .actor_of_args::<Store<TestCount, _>, _>(
+                "counts",
+                (
+                    MemStore::new(),
+                    StoreConfig::default().commit_bus(bus.clone()),
+                ),
+            )
+            .unwrap();
+
+        let received = collect_events(&sys, &bus, "counts-events", 2);
+
+        let count = TestCount::default();
+        let id = count.id();
+        store.tell(Event::Create(count), None);
+        store.tell(
+            Commit::new(
+                Event::Change(id, Op::Add(1)),
+                Some("alice".into()),
+                Some("one more".into()),
+            ),
             None,
         );
 
-        store.tell(Event::Create(TestCount::default()), None);
+        let received = block_on(received);
+        assert_eq!(received.len(), 2);
+        let change = received.iter().find(|c| c.sequence == 1).unwrap();
+        assert_eq!(change.who(), Some("alice"));
+        assert_eq!(change.why(), Some("one more"));
+        assert_eq!(change.entity_id(), id);
+    }
+
+    #[test]
+    fn subscribe_through_channel() {
+        let sys = ActorSystem::new().unwrap();
+        let bus: CommitBus<_> = channel("commits", &sys).unwrap();
+        let store = sys
+            .actor_of_args::<Store<TestCount, _>, _>(
+                "counts",
+                (
+                    MemStore::new(),
+                    StoreConfig::default().commit_bus(bus.clone()),
+                ),
+            )
+            .unwrap();
+        let mut commits = subscribe_channel(&sys, &bus, "counts");
+
+        let count = TestCount::new(1);
+        let id = count.id();
+        store.tell(Event::Create(count), None);
+        let created = block_on(commits.next()).unwrap();
+        assert_eq!(created.entity_id(), id);
+        assert_eq!(created.sequence, 0);
+        store.tell(
+            Commit::new(Event::Change(id, Op::Add(1)), Some("bob".into()), None),
+            None,
+        );
+        let changed = block_on(commits.next()).unwrap();
+        assert_eq!(changed.who(), Some("bob"));
+        assert_eq!(changed.sequence, 1);
+
+        let subscriber = commits.subscriber.name().to_string();
+        let bridged = || sys.temp_root().children().any(|a| a.name() == subscriber);
+        assert!(bridged());
+        drop(commits);
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        assert!(!bridged());
+    }
+
+    #[test]
+    fn stream_changes_of_one_entity() {
+        let sys = ActorSystem::new().unwrap();
+        let bus: CommitBus<_> = channel("commits", &sys).unwrap();
+        let store = sys
+            .actor_of_args::<Store<TestCount, _>, _>(
+                "counts",
+                (
+                    MemStore::new(),
+                    StoreConfig::default().commit_bus(bus.clone()),
+                ),
+            )
+            .unwrap();
+        let count = TestCount::new(1);
+        let other = TestCount::new(2);
+        let (id, other_id) = (count.id(), other.id());
+        let mut changes = change_stream(&sys, &bus, "counts", id);
+
+        store.tell(Event::Create(count), None);
+        store.tell(Event::Create(other), None);
+        store.tell(Event::Change(other_id, Op::Add(5)), None);
+        store.tell(Event::Change(id, Op::Add(1)), None);
+        store.tell(Event::Change(id, Op::Sub(2)), None);
+
+        assert!(matches!(block_on(changes.next()), Some(Op::Add(1))));
+        assert!(matches!(block_on(changes.next()), Some(Op::Sub(2))));
+    }
+
+    #[test]
+    fn stream_events_until_dropped() {
+        let sys = ActorSystem::new().unwrap();
+        let bus: EventBus<_> = channel("bus", &sys).unwrap();
+        let store = sys
+            .actor_of_args::<Store<TestCount, _>, _>("counts", (MemStore::new(), bus.clone()))
+            .unwrap();
+        let mut events = subscribe_stream(&sys, &bus, "counts-events");
+
+        let count = TestCount::new(1);
+        let id = count.id();
+        store.tell(Event::Create(count), None);
+        store.tell(Event::Change(id, Op::Add(2)), None);
+        assert!(matches!(block_on(events.next()), Some(Event::Create(c)) if c.id() == id));
+        assert!(matches!(
+            block_on(events.next()),
+            Some(Event::Change(_, Op::Add(2)))
+        ));
 
-        let result: Option<Event<TestCount>> = block_on(ask(&sys, &sub, TestSubMsg::Get));
+        let subscriber = events.subscriber.name().to_string();
+        let bridged = || sys.temp_root().children().any(|a| a.name() == subscriber);
+        assert!(bridged());
+        drop(events);
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        assert!(!bridged());
+    }
+
+    #[test]
+    fn compensate_the_version_it_was_computed_from() {
+        let store = MemStore::new();
+        let count = TestCount::new(1);
+        let id = count.id();
+        block_on(store.commit(Event::Create(count).into())).unwrap();
+        let at = Utc::now();
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        block_on(store.commit(Event::Change(id, Op::Add(2)).into())).unwrap();
 
-        assert!(result.is_some());
+        let revert = block_on(store.compensate(id, at)).unwrap();
+        assert_eq!(revert.expected_version(), Some(2));
+        block_on(store.commit(Event::Change(id, Op::Add(4)).into())).unwrap();
+        let res = block_on(store.commit(revert));
+        assert!(matches!(
+            res,
+            Err(CommitError::Conflict {
+                expected: 2,
+                actual: 3
+            })
+        ));
+        assert_eq!(block_on(store.snapshot(id, Utc::now())).unwrap().count, 7);
     }
 }
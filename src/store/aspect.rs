@@ -0,0 +1,198 @@
+use super::{Commit, CommitError, CommitResult, CommitStore, Snapshot};
+use crate::{Event, Model};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::lock::Mutex;
+use futures::stream::{BoxStream, TryStreamExt};
+use std::sync::Arc;
+
+/// Names the part of a model a change touches, changes to different aspects
+/// of an entity never conflict with each other.
+pub trait Aspect {
+    fn aspect(&self) -> &str;
+}
+
+/// A store that versions every aspect of an entity on its own. A change
+/// with an expected version is rejected with a `Conflict` only when other
+/// changes to the same aspect were committed after that version, the version
+/// of an aspect being the number of changes made to it.
+#[derive(Debug, Clone)]
+pub struct AspectVersionedStore<S> {
+    backend: S,
+    lock: Arc<Mutex<()>>,
+}
+
+impl<S> AspectVersionedStore<S> {
+    pub fn new(backend: S) -> Self {
+        AspectVersionedStore {
+            backend,
+            lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    async fn aspect_version<M>(&self, id: M::Id, aspect: &str) -> CommitResult<u64>
+    where
+        M: Model,
+        M::Change: Aspect,
+        S: CommitStore<M>,
+    {
+        self.backend
+            .change_list(id)
+            .try_fold(0, |version, c| async move {
+                Ok(match c.change() {
+                    Some(change) if change.aspect() == aspect => version + 1,
+                    _ => version,
+                })
+            })
+            .await
+    }
+}
+
+#[async_trait]
+impl<M, S> CommitStore<M> for AspectVersionedStore<S>
+where
+    M: Model,
+    M::Change: Aspect,
+    S: CommitStore<M>,
+{
+    fn keys(&self) -> BoxStream<CommitResult<M::Id>> {
+        self.backend.keys()
+    }
+
+    fn change_list(&self, id: M::Id) -> BoxStream<CommitResult<Commit<M>>> {
+        self.backend.change_list(id)
+    }
+
+    async fn commit(&self, mut c: Commit<M>) -> CommitResult<()> {
+        let _guard = self.lock.lock().await;
+        if let (Event::Change(id, change), Some(expected)) = (&*c, c.expected_version()) {
+            let actual = self.aspect_version(id.clone(), change.aspect()).await?;
+            if actual != expected {
+                return Err(CommitError::Conflict { expected, actual });
+            }
+        }
+        // the version was already checked per aspect
+        c.set_expected_version(None);
+        self.backend.commit(c).await
+    }
+
+    async fn import_entity(
+        &self,
+        id: M::Id,
+        history: Vec<Commit<M>>,
+        force: bool,
+    ) -> CommitResult<()> {
+        self.backend.import_entity(id, history, force).await
+    }
+
+    async fn purge(&self, id: M::Id) -> CommitResult<()> {
+        self.backend.purge(id).await
+    }
+
+    async fn tag(&self, name: String, at: DateTime<Utc>) -> CommitResult<()> {
+        self.backend.tag(name, at).await
+    }
+
+    async fn resolve_tag(&self, name: &str) -> CommitResult<DateTime<Utc>> {
+        self.backend.resolve_tag(name).await
+    }
+
+    async fn count_at(&self, time: DateTime<Utc>) -> CommitResult<usize> {
+        self.backend.count_at(time).await
+    }
+
+    async fn ping(&self) -> CommitResult<()> {
+        self.backend.ping().await
+    }
+
+    async fn save_snapshot(&self, snapshot: Snapshot<M>) -> CommitResult<()> {
+        self.backend.save_snapshot(snapshot).await
+    }
+
+    async fn load_snapshot(&self, id: M::Id) -> CommitResult<Option<Snapshot<M>>> {
+        self.backend.load_snapshot(id).await
+    }
+
+    async fn checkpoint(&self, at: DateTime<Utc>) -> CommitResult<()> {
+        self.backend.checkpoint(at).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EntityId, MemStore};
+    use futures::executor::block_on;
+    use futures::future::join;
+
+    #[derive(Clone, Debug, Default)]
+    struct Profile {
+        id: EntityId,
+        name: String,
+        email: String,
+    }
+    #[derive(Clone, Debug)]
+    enum ProfileChange {
+        Name(String),
+        Email(String),
+    }
+    impl Aspect for ProfileChange {
+        fn aspect(&self) -> &str {
+            match self {
+                ProfileChange::Name(_) => "name",
+                ProfileChange::Email(_) => "email",
+            }
+        }
+    }
+    impl Model for Profile {
+        type Id = EntityId;
+        type Change = ProfileChange;
+        fn id(&self) -> EntityId {
+            self.id
+        }
+        fn apply_change(&mut self, change: &ProfileChange) {
+            match change {
+                ProfileChange::Name(name) => self.name = name.clone(),
+                ProfileChange::Email(email) => self.email = email.clone(),
+            }
+        }
+    }
+
+    fn change(id: EntityId, change: ProfileChange, version: u64) -> Commit<Profile> {
+        let mut c: Commit<Profile> = Event::Change(id, change).into();
+        c.set_expected_version(Some(version));
+        c
+    }
+
+    #[test]
+    fn conflict_only_on_same_aspect() {
+        let store = AspectVersionedStore::new(MemStore::new());
+        let profile = Profile::default();
+        let id = profile.id();
+        block_on(store.commit(Event::Create(profile).into())).unwrap();
+
+        let (name, email) = block_on(join(
+            store.commit(change(id, ProfileChange::Name("bob".into()), 0)),
+            store.commit(change(id, ProfileChange::Email("bob@x.io".into()), 0)),
+        ));
+        assert!(name.is_ok());
+        assert!(email.is_ok());
+
+        let (first, second) = block_on(join(
+            store.commit(change(id, ProfileChange::Name("alice".into()), 1)),
+            store.commit(change(id, ProfileChange::Name("carol".into()), 1)),
+        ));
+        assert!(first.is_ok());
+        assert!(matches!(
+            second,
+            Err(CommitError::Conflict {
+                expected: 1,
+                actual: 2
+            })
+        ));
+
+        let profile = block_on(async { store.get(id).await?.to_present().await }).unwrap();
+        assert_eq!(profile.name, "alice");
+        assert_eq!(profile.email, "bob@x.io");
+    }
+}
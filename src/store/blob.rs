@@ -0,0 +1,138 @@
+use super::{CommitError, CommitResult};
+use async_trait::async_trait;
+use futures::lock::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Content addressed handle of an attachment kept in a `BlobStore`, the same
+/// bytes always get the same handle.
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
+pub struct BlobRef(String);
+
+impl BlobRef {
+    pub fn of(bytes: &[u8]) -> Self {
+        BlobRef(Uuid::new_v5(&Uuid::NAMESPACE_OID, bytes).to_string())
+    }
+}
+
+impl fmt::Display for BlobRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Keeps the bytes of attachments out of the event log, commits only carry
+/// the `BlobRef` of their attachments.
+#[async_trait]
+pub trait BlobStore: fmt::Debug + Clone + Send + Sync + 'static {
+    async fn put(&self, bytes: Vec<u8>) -> CommitResult<BlobRef>;
+
+    async fn get(&self, blob: &BlobRef) -> CommitResult<Vec<u8>>;
+}
+
+/// Keeps attachments in memory, clones of the store share the same blobs
+#[derive(Debug, Clone, Default)]
+pub struct MemBlobStore(Arc<Mutex<HashMap<BlobRef, Vec<u8>>>>);
+
+impl MemBlobStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl BlobStore for MemBlobStore {
+    async fn put(&self, bytes: Vec<u8>) -> CommitResult<BlobRef> {
+        let blob = BlobRef::of(&bytes);
+        self.0.lock().await.insert(blob.clone(), bytes);
+        Ok(blob)
+    }
+
+    async fn get(&self, blob: &BlobRef) -> CommitResult<Vec<u8>> {
+        self.0
+            .lock()
+            .await
+            .get(blob)
+            .cloned()
+            .ok_or(CommitError::NotFound)
+    }
+}
+
+/// Keeps every attachment in a file of a directory named after its handle
+#[derive(Debug, Clone)]
+pub struct FsBlobStore {
+    dir: PathBuf,
+}
+
+impl FsBlobStore {
+    pub fn new<P: AsRef<Path>>(dir: P) -> CommitResult<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        std::fs::create_dir_all(&dir).map_err(|e| CommitError::Blob(e.to_string()))?;
+        Ok(FsBlobStore { dir })
+    }
+}
+
+#[async_trait]
+impl BlobStore for FsBlobStore {
+    async fn put(&self, bytes: Vec<u8>) -> CommitResult<BlobRef> {
+        let blob = BlobRef::of(&bytes);
+        let path = self.dir.join(&blob.0);
+        if !path.exists() {
+            std::fs::write(path, bytes).map_err(|e| CommitError::Blob(e.to_string()))?;
+        }
+        Ok(blob)
+    }
+
+    async fn get(&self, blob: &BlobRef) -> CommitResult<Vec<u8>> {
+        std::fs::read(self.dir.join(&blob.0)).map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => CommitError::NotFound,
+            _ => CommitError::Blob(e.to_string()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::tests::TestCount;
+    use crate::{Commit, CommitStore, Event, MemStore, Model};
+    use futures::executor::block_on;
+    use futures::stream::TryStreamExt;
+
+    fn commit_with_attachment<B: BlobStore>(blobs: B) {
+        let store = MemStore::new();
+        let count = TestCount::new(1);
+        let id = count.id();
+        let photo = vec![0xff, 0xd8, 0xff, 0xe0];
+        let blob = block_on(blobs.put(photo.clone())).unwrap();
+        let mut c: Commit<TestCount> = Event::Create(count).into();
+        c.attach(blob.clone());
+        block_on(store.commit(c)).unwrap();
+
+        let history: Vec<_> = block_on(store.change_list(id).try_collect()).unwrap();
+        assert_eq!(history[0].attachments(), std::slice::from_ref(&blob));
+        assert_eq!(block_on(blobs.get(&blob)).unwrap(), photo);
+        assert_eq!(block_on(blobs.put(photo)).unwrap(), blob);
+        let missing = BlobRef::of(b"missing");
+        assert!(matches!(
+            block_on(blobs.get(&missing)),
+            Err(CommitError::NotFound)
+        ));
+    }
+
+    #[test]
+    fn attachments_in_memory() {
+        commit_with_attachment(MemBlobStore::new());
+    }
+
+    #[test]
+    fn attachments_in_files() {
+        let dir = std::env::temp_dir().join(format!("blobs-{}", Uuid::new_v4()));
+        commit_with_attachment(FsBlobStore::new(&dir).unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,114 @@
+use super::{Author, Commit, Reason};
+use crate::{Event, Model};
+use chrono::{DateTime, Duration, Utc};
+
+/// Builds the history of an entity, its creation followed by its changes,
+/// to seed stores in tests or to import entities. Commits are timestamped
+/// a millisecond after the previous one unless told otherwise with `at`,
+/// the sequence of a commit being its position in the built history.
+#[derive(Debug, Clone)]
+pub struct CommitChain<M: Model> {
+    id: M::Id,
+    commits: Vec<Commit<M>>,
+}
+
+impl<M: Model> CommitChain<M> {
+    pub fn create(model: M) -> Self {
+        CommitChain {
+            id: model.id(),
+            commits: vec![Event::Create(model).into()],
+        }
+    }
+
+    pub fn change(mut self, change: M::Change) -> Self {
+        let when = self.last().when() + Duration::milliseconds(1);
+        let mut c: Commit<M> = Event::Change(self.id.clone(), change).into();
+        c.set_when(when);
+        self.commits.push(c);
+        self
+    }
+
+    /// Sets when the last commit was made, it can't be before the commit
+    /// that precedes it.
+    pub fn at(mut self, when: DateTime<Utc>) -> Self {
+        if let [.., previous, _] = self.commits.as_slice() {
+            assert!(
+                previous.when() <= when,
+                "commits of {} out of order",
+                self.id
+            );
+        }
+        self.last_mut().set_when(when);
+        self
+    }
+
+    pub fn by(mut self, who: Author) -> Self {
+        self.last_mut().set_who(who);
+        self
+    }
+
+    pub fn because(mut self, why: Reason) -> Self {
+        self.last_mut().set_why(why);
+        self
+    }
+
+    pub fn build(self) -> Vec<Commit<M>> {
+        self.commits
+    }
+
+    fn last(&self) -> &Commit<M> {
+        self.commits.last().expect("chain starts with a creation")
+    }
+
+    fn last_mut(&mut self) -> &mut Commit<M> {
+        self.commits
+            .last_mut()
+            .expect("chain starts with a creation")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::tests::{Op, TestCount};
+    use crate::{CommitStore, MemStore};
+    use chrono::TimeZone;
+    use futures::executor::block_on;
+
+    #[test]
+    fn seed_store_from_chain() {
+        let t = |h| Utc.with_ymd_and_hms(2020, 1, 1, h, 0, 0).unwrap();
+        let count = TestCount::new(1);
+        let id = count.id();
+        let history = CommitChain::create(count)
+            .at(t(0))
+            .change(Op::Add(2))
+            .at(t(1))
+            .by(Some("bob".into()))
+            .change(Op::Sub(1))
+            .change(Op::Add(10))
+            .at(t(2))
+            .build();
+        assert_eq!(history.len(), 4);
+        assert_eq!(history[1].who(), Some("bob"));
+        assert!(history.windows(2).all(|w| w[0].when() <= w[1].when()));
+
+        let store = MemStore::new();
+        block_on(store.import_entity(id, history, false)).unwrap();
+        let at = |when| block_on(async { store.get(id).await?.travel_to(when).await }).unwrap();
+        assert_eq!(at(t(0)).count, 1);
+        assert_eq!(at(t(1)).count, 3);
+        assert_eq!(at(t(1) + Duration::milliseconds(1)).count, 2);
+        assert_eq!(at(t(2)).count, 12);
+    }
+
+    #[test]
+    #[should_panic(expected = "out of order")]
+    fn reject_commits_out_of_order() {
+        let t = |h| Utc.with_ymd_and_hms(2020, 1, 1, h, 0, 0).unwrap();
+        CommitChain::create(TestCount::new(1))
+            .at(t(1))
+            .change(Op::Add(1))
+            .at(t(0));
+    }
+}
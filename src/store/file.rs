@@ -0,0 +1,232 @@
+use super::{check_append, Commit, CommitError, CommitResult, CommitStore};
+use crate::{EventKind, SerializableModel};
+use async_trait::async_trait;
+use futures::lock::Mutex;
+use futures::stream::{self, BoxStream, StreamExt};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Keeps the history of every entity in a file of a directory named
+/// `{entity_id}.log`, one JSON encoded commit per line in the order they were
+/// committed. Logs are easy to inspect and back up, a line left half written
+/// by a crash is dropped the next time the log is opened or appended to.
+#[derive(Debug, Clone)]
+pub struct FileStore<M> {
+    dir: PathBuf,
+    // appends to logs one at a time
+    writing: Arc<Mutex<()>>,
+    model: PhantomData<fn() -> M>,
+}
+
+impl<M> FileStore<M> {
+    /// Uses the logs in the directory, creating it if needed
+    pub fn open<P: AsRef<Path>>(dir: P) -> CommitResult<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir).map_err(log_error)?;
+        for log in logs(&dir)? {
+            repair(&log)?;
+        }
+        Ok(FileStore {
+            dir,
+            writing: Arc::new(Mutex::new(())),
+            model: PhantomData,
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn log<Id: std::fmt::Display>(&self, id: &Id) -> PathBuf {
+        self.dir.join(format!("{}.log", id))
+    }
+}
+
+#[async_trait]
+impl<M: SerializableModel> CommitStore<M> for FileStore<M> {
+    fn keys(&self) -> BoxStream<CommitResult<M::Id>> {
+        let created = || -> CommitResult<Vec<M::Id>> {
+            let mut created = vec![];
+            for log in logs(&self.dir)? {
+                if let Some(first) = read::<M>(&log)?.into_iter().next() {
+                    let first = first?;
+                    created.push((first.when(), first.entity_id()));
+                }
+            }
+            // creation order keeps pages of the list consistent
+            created.sort_by_key(|(when, _)| *when);
+            Ok(created.into_iter().map(|(_, id)| id).collect())
+        };
+        match created() {
+            Ok(ids) => stream::iter(ids.into_iter().map(Ok)).boxed(),
+            Err(e) => stream::once(async { Err(e) }).boxed(),
+        }
+    }
+
+    fn change_list(&self, id: M::Id) -> BoxStream<CommitResult<Commit<M>>> {
+        let log = self.log(&id);
+        match read::<M>(&log) {
+            Ok(commits) if commits.is_empty() => {
+                stream::once(async { Err(CommitError::NotFound) }).boxed()
+            }
+            Ok(commits) => stream::iter(commits).boxed(),
+            Err(e) => stream::once(async { Err(e) }).boxed(),
+        }
+    }
+
+    async fn commit(&self, c: Commit<M>) -> CommitResult<()> {
+        let mut line = serde_json::to_vec(&c).map_err(|e| CommitError::Codec(e.to_string()))?;
+        line.push(b'\n');
+        let log = self.log(&c.entity_id());
+        let _writing = self.writing.lock().await;
+        repair(&log)?;
+        let history = read::<M>(&log)?;
+        let version = history.len() as u64;
+        let deleted = matches!(history.last(), Some(Ok(c)) if c.kind() == EventKind::Delete);
+        check_append(&c, version, deleted)?;
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&log)
+            .and_then(|mut file| file.write_all(&line))
+            .map_err(log_error)
+    }
+
+    async fn purge(&self, id: M::Id) -> CommitResult<()> {
+        let _writing = self.writing.lock().await;
+        match fs::remove_file(self.log(&id)) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Err(CommitError::NotFound),
+            res => res.map_err(log_error),
+        }
+    }
+}
+
+fn log_error(e: io::Error) -> CommitError {
+    CommitError::Log(e.to_string())
+}
+
+/// Every log file of the directory
+fn logs(dir: &Path) -> CommitResult<Vec<PathBuf>> {
+    let mut logs = vec![];
+    for entry in fs::read_dir(dir).map_err(log_error)? {
+        let path = entry.map_err(log_error)?.path();
+        if path.extension().is_some_and(|ext| ext == "log") {
+            logs.push(path);
+        }
+    }
+    Ok(logs)
+}
+
+/// The commits of the complete lines of a log, none if there's no log
+fn read<M: SerializableModel>(log: &Path) -> CommitResult<Vec<CommitResult<Commit<M>>>> {
+    let text = match fs::read_to_string(log) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        res => res.map_err(log_error)?,
+    };
+    let complete = text.rfind('\n').map_or("", |end| &text[..end]);
+    Ok(complete
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| serde_json::from_str(line).map_err(|e| CommitError::Codec(e.to_string())))
+        .collect())
+}
+
+/// Truncates the line a write interrupted halfway at the end of the log
+fn repair(log: &Path) -> CommitResult<()> {
+    let bytes = match fs::read(log) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        res => res.map_err(log_error)?,
+    };
+    let complete = bytes
+        .iter()
+        .rposition(|b| *b == b'\n')
+        .map_or(0, |end| end + 1);
+    if complete < bytes.len() {
+        warn!("Dropping partially written commit at the end of {:?}", log);
+        let file = OpenOptions::new()
+            .write(true)
+            .open(log)
+            .map_err(log_error)?;
+        file.set_len(complete as u64).map_err(log_error)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::tests::{Op, TestCount};
+    use crate::{Event, Model};
+    use chrono::Utc;
+    use futures::executor::block_on;
+    use futures::stream::TryStreamExt;
+    use uuid::Uuid;
+
+    #[test]
+    fn append_commits_to_logs() {
+        let dir = std::env::temp_dir().join(format!("logs-{}", Uuid::new_v4()));
+        let store = FileStore::<TestCount>::open(&dir).unwrap();
+        let (first, second) = (TestCount::new(1), TestCount::new(10));
+        let (a, b) = (first.id(), second.id());
+        block_on(store.commit(Event::Create(first.clone()).into())).unwrap();
+        block_on(store.commit(Event::Create(second).into())).unwrap();
+        block_on(store.commit(Event::Change(a, Op::Add(2)).into())).unwrap();
+        block_on(store.commit(Event::Change(b, Op::Sub(3)).into())).unwrap();
+
+        let keys: Vec<_> = block_on(store.keys().try_collect()).unwrap();
+        assert_eq!(keys, vec![a, b]);
+        let history: Vec<_> = block_on(store.change_list(a).try_collect()).unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(block_on(store.snapshot(a, Utc::now())).unwrap().count, 3);
+        let log = fs::read_to_string(dir.join(format!("{}.log", b))).unwrap();
+        assert_eq!(log.lines().count(), 2);
+
+        let res = block_on(store.commit(Event::Create(first).into()));
+        assert!(matches!(res, Err(CommitError::AlreadyExists)));
+        let res = block_on(store.commit(Event::Change(Uuid::new_v4().into(), Op::Add(1)).into()));
+        assert!(matches!(res, Err(CommitError::CantChange)));
+        let mut stale: Commit<TestCount> = Event::Change(a, Op::Add(1)).into();
+        stale.set_expected_version(Some(1));
+        let res = block_on(store.commit(stale));
+        assert!(matches!(
+            res,
+            Err(CommitError::Conflict {
+                expected: 1,
+                actual: 2
+            })
+        ));
+
+        block_on(store.purge(b)).unwrap();
+        let keys: Vec<_> = block_on(store.keys().try_collect()).unwrap();
+        assert_eq!(keys, vec![a]);
+        assert!(block_on(store.get(b)).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn drop_partially_written_commits() {
+        let dir = std::env::temp_dir().join(format!("logs-{}", Uuid::new_v4()));
+        let store = FileStore::<TestCount>::open(&dir).unwrap();
+        let count = TestCount::new(1);
+        let id = count.id();
+        block_on(store.commit(Event::Create(count).into())).unwrap();
+        block_on(store.commit(Event::Change(id, Op::Add(2)).into())).unwrap();
+        let log = dir.join(format!("{}.log", id));
+        let mut file = OpenOptions::new().append(true).open(&log).unwrap();
+        file.write_all(b"{\"event\":{\"Change\"").unwrap();
+
+        let history: Vec<_> = block_on(store.change_list(id).try_collect()).unwrap();
+        assert_eq!(history.len(), 2);
+        block_on(store.commit(Event::Change(id, Op::Add(3)).into())).unwrap();
+        assert_eq!(block_on(store.snapshot(id, Utc::now())).unwrap().count, 6);
+
+        file.write_all(b"{\"event\"").unwrap();
+        let store = FileStore::<TestCount>::open(&dir).unwrap();
+        assert!(fs::read_to_string(&log).unwrap().ends_with('\n'));
+        assert_eq!(block_on(store.snapshot(id, Utc::now())).unwrap().count, 6);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,73 @@
+use super::{CommitError, CommitResult};
+use crate::EntityId;
+use std::fmt;
+use std::str::FromStr;
+use uuid::Uuid;
+
+/// Encodes the ids of entities the way a backend indexes them best, e.g.
+/// as a `uuid` column in SQL or raw bytes as a Redis key.
+pub trait IdCodec<Id>: fmt::Debug + Clone + Send + Sync + 'static {
+    fn encode_id(&self, id: &Id) -> Vec<u8>;
+    fn decode_id(&self, bytes: &[u8]) -> CommitResult<Id>;
+}
+
+/// Stores ids as their text representation, it works with any id that can
+/// be parsed back from what it displays.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StringId;
+
+impl<Id> IdCodec<Id> for StringId
+where
+    Id: fmt::Display + FromStr,
+    Id::Err: fmt::Display,
+{
+    fn encode_id(&self, id: &Id) -> Vec<u8> {
+        id.to_string().into_bytes()
+    }
+
+    fn decode_id(&self, bytes: &[u8]) -> CommitResult<Id> {
+        let text = std::str::from_utf8(bytes).map_err(|e| CommitError::Codec(e.to_string()))?;
+        text.parse()
+            .map_err(|e: Id::Err| CommitError::Codec(e.to_string()))
+    }
+}
+
+/// Stores `EntityId`s as the 16 bytes of their UUID
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UuidBytes;
+
+impl IdCodec<EntityId> for UuidBytes {
+    fn encode_id(&self, id: &EntityId) -> Vec<u8> {
+        id.uuid().as_bytes().to_vec()
+    }
+
+    fn decode_id(&self, bytes: &[u8]) -> CommitResult<EntityId> {
+        Uuid::from_slice(bytes)
+            .map(EntityId::from)
+            .map_err(|e| CommitError::Codec(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_ids() {
+        let id = EntityId::new();
+        let bytes = UuidBytes.encode_id(&id);
+        assert_eq!(bytes.len(), 16);
+        assert_eq!(UuidBytes.decode_id(&bytes).unwrap(), id);
+        assert!(UuidBytes.decode_id(b"short").is_err());
+
+        let text = StringId.encode_id(&id);
+        assert_eq!(text, id.to_string().into_bytes());
+        assert_eq!(
+            IdCodec::<EntityId>::decode_id(&StringId, &text).unwrap(),
+            id
+        );
+        let number: u64 = StringId.decode_id(&StringId.encode_id(&42u64)).unwrap();
+        assert_eq!(number, 42);
+        assert!(IdCodec::<u64>::decode_id(&StringId, b"nope").is_err());
+    }
+}
@@ -1,38 +1,311 @@
-use super::{Commit, CommitError, CommitResult, CommitStore, Event};
-use crate::{EntityId, Model};
+use super::{check_history, Commit, CommitError, CommitResult, CommitStore, Event, Snapshot};
+use crate::{EventKind, Model};
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use futures::lock::Mutex;
 use futures::stream::{self, BoxStream, StreamExt, TryStreamExt};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::iter;
+use std::mem::{size_of, size_of_val};
 use std::sync::Arc;
 
+/// How many idempotency keys are remembered before forgetting the oldest
+const SEEN_KEYS: usize = 1000;
+
+/// Keeps commits in memory, clones of the store share the same data so a
+/// store can be handed to several entities or even actor systems.
+/// Commits with an idempotency key already seen are not stored again, the
+/// result of the first one is returned instead.
+#[derive(Debug)]
+pub struct MemStore<M: Model>(
+    Arc<Mutex<Entities<M>>>,
+    Arc<Mutex<SeenKeys>>,
+    Arc<Mutex<HashMap<String, DateTime<Utc>>>>,
+    Option<MemLimit>,
+    Option<usize>,
+);
+
+/// How much a `MemStore` holds, the byte size is an estimate that counts
+/// the commits and their metadata but not what models or changes allocate.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct MemStats {
+    pub entities: usize,
+    pub commits: usize,
+    pub bytes: usize,
+}
+
+/// Caps the estimated byte size of a `MemStore`
+#[derive(Debug, Clone, Copy)]
+pub struct MemLimit {
+    pub max_bytes: usize,
+    pub policy: MemPolicy,
+}
+
+/// What a `MemStore` does with commits that don't fit in its limit
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum MemPolicy {
+    /// Don't store the commit, returning `CommitError::MemoryLimit`
+    Reject,
+    /// Purge the entities committed to least recently until the commit fits,
+    /// for stores used as a cache of data living somewhere else.
+    EvictIdlest,
+}
+
+/// The commit creating an entity followed by its changes
+type Stored<M> = (Commit<M>, Vec<Commit<M>>);
+
 #[derive(Debug)]
-pub struct MemStore<M: Model>(Arc<Mutex<HashMap<EntityId, (Commit<M>, Vec<Commit<M>>)>>>);
+struct Entities<M: Model> {
+    by_id: HashMap<M::Id, Stored<M>>,
+    snapshots: HashMap<M::Id, Snapshot<M>>,
+    commits: usize,
+    bytes: usize,
+}
+
+impl<M: Model> Default for Entities<M> {
+    fn default() -> Self {
+        Entities {
+            by_id: HashMap::new(),
+            snapshots: HashMap::new(),
+            commits: 0,
+            bytes: 0,
+        }
+    }
+}
+
+impl<M: Model> Entities<M> {
+    fn insert(&mut self, c: Commit<M>) -> CommitResult<()> {
+        let id = c.event.entity_id();
+        let size = commit_size(&c);
+        match c.event {
+            Event::Create(_) => {
+                self.remove(&id);
+                self.by_id.insert(id, (c, vec![]));
+            }
+            Event::Change(_, _) | Event::Delete(_) => {
+                let (_, updates) = match self.by_id.get_mut(&id) {
+                    Some(stored) if !is_deleted(stored) => stored,
+                    _ => return Err(CommitError::CantChange),
+                };
+                check_version(&c, 1 + updates.len() as u64)?;
+                // changes are kept in domain order, the order of commits by default
+                let key = c.order_key();
+                let at = updates.partition_point(|u| u.order_key() <= key);
+                if at < updates.len() {
+                    // the snapshot might not include changes that go before
+                    self.snapshots.remove(&id);
+                }
+                updates.insert(at, c);
+            }
+        }
+        self.commits += 1;
+        self.bytes += size;
+        Ok(())
+    }
+
+    fn remove(&mut self, id: &M::Id) -> Option<Stored<M>> {
+        self.snapshots.remove(id);
+        let removed = self.by_id.remove(id)?;
+        let (created, changes) = &removed;
+        self.commits -= 1 + changes.len();
+        self.bytes -= iter::once(created)
+            .chain(changes)
+            .map(commit_size)
+            .sum::<usize>();
+        Some(removed)
+    }
+
+    /// Snapshots the entity once it has `every` commits more than its latest
+    /// snapshot, applying only the changes made after that snapshot.
+    fn snapshot_every(&mut self, id: &M::Id, every: Option<usize>) {
+        let every = match every {
+            Some(every) if every > 0 => every,
+            _ => return,
+        };
+        let (created, changes) = match self.by_id.get(id) {
+            Some(stored) if !is_deleted(stored) => stored,
+            _ => return,
+        };
+        let (mut model, from, mut when) = match self.snapshots.get(id) {
+            Some(s) => (s.model.clone(), s.version as usize, s.when),
+            None => (created.entity().unwrap(), 1, created.when()),
+        };
+        let version = 1 + changes.len();
+        if version - from < every {
+            return;
+        }
+        for c in &changes[from - 1..] {
+            model.apply_change(&c.change().unwrap());
+            when = when.max(c.when());
+        }
+        let snapshot = Snapshot {
+            model,
+            version: version as u64,
+            when,
+        };
+        self.snapshots.insert(id.clone(), snapshot);
+    }
+
+    /// Makes sure `size` more bytes fit in the limit, evicting entities other
+    /// than `keep` if the policy allows it.
+    fn make_room(
+        &mut self,
+        size: usize,
+        limit: Option<MemLimit>,
+        keep: &[M::Id],
+    ) -> CommitResult<()> {
+        let limit = match limit {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+        if size > limit.max_bytes {
+            return Err(CommitError::MemoryLimit(limit.max_bytes));
+        }
+        while self.bytes + size > limit.max_bytes {
+            let idlest = match limit.policy {
+                MemPolicy::Reject => None,
+                MemPolicy::EvictIdlest => self
+                    .by_id
+                    .iter()
+                    .filter(|(id, _)| !keep.contains(id))
+                    .min_by_key(|(_, (created, changes))| changes.last().unwrap_or(created).when())
+                    .map(|(id, _)| id.clone()),
+            };
+            match idlest {
+                Some(id) => {
+                    debug!("evicting {} to stay under {} bytes", id, limit.max_bytes);
+                    self.remove(&id);
+                }
+                None => return Err(CommitError::MemoryLimit(limit.max_bytes)),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Rejects changes based on a version other than the current one, the
+/// version of an entity being the number of commits it has.
+fn check_version<M: Model>(c: &Commit<M>, actual: u64) -> CommitResult<()> {
+    match c.expected_version() {
+        Some(expected) if expected != actual => Err(CommitError::Conflict { expected, actual }),
+        _ => Ok(()),
+    }
+}
+
+/// Whether the last commit of the entity deleted it
+fn is_deleted<M: Model>((_, changes): &Stored<M>) -> bool {
+    changes
+        .last()
+        .is_some_and(|c| c.kind() == EventKind::Delete)
+}
+
+/// Estimated bytes a commit takes in memory, including what its model or
+/// change owns on the heap, see `Model::heap_size`.
+fn commit_size<M: Model>(c: &Commit<M>) -> usize {
+    let text = |t: Option<&str>| t.map_or(0, str::len);
+    let event = match &**c {
+        Event::Create(model) => model.heap_size(),
+        Event::Change(_, change) => M::change_heap_size(change),
+        Event::Delete(_) => 0,
+    };
+    size_of::<Commit<M>>()
+        + event
+        + text(c.who())
+        + text(c.why())
+        + text(c.idempotency_key())
+        + size_of_val(c.attachments())
+}
 
 impl<M: Model> MemStore<M> {
     pub fn new() -> Self {
-        MemStore(Arc::new(Mutex::new(HashMap::new())))
+        MemStore(
+            Arc::new(Mutex::new(Entities::default())),
+            Arc::new(Mutex::new(SeenKeys::default())),
+            Arc::new(Mutex::new(HashMap::new())),
+            None,
+            None,
+        )
+    }
+
+    /// A store that keeps its estimated size under the limit
+    pub fn with_limit(limit: MemLimit) -> Self {
+        MemStore(
+            Arc::new(Mutex::new(Entities::default())),
+            Arc::new(Mutex::new(SeenKeys::default())),
+            Arc::new(Mutex::new(HashMap::new())),
+            Some(limit),
+            None,
+        )
+    }
+
+    /// Snapshots entities every so many commits so reading them only
+    /// replays the commits made after their latest snapshot.
+    pub fn snapshot_every(mut self, commits: usize) -> Self {
+        self.4 = Some(commits);
+        self
+    }
+
+    pub async fn stats(&self) -> MemStats {
+        let entities = self.0.lock().await;
+        MemStats {
+            entities: entities.by_id.len(),
+            commits: entities.commits,
+            bytes: entities.bytes,
+        }
+    }
+
+    async fn append(&self, c: Commit<M>) -> CommitResult<()> {
+        let mut entities = self.0.lock().await;
+        let id = c.entity_id();
+        entities.make_room(commit_size(&c), self.3, std::slice::from_ref(&id))?;
+        entities.insert(c)?;
+        entities.snapshot_every(&id, self.4);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+struct SeenKeys {
+    results: HashMap<String, CommitResult<()>>,
+    order: VecDeque<String>,
+}
+
+impl SeenKeys {
+    fn remember(&mut self, key: String, result: CommitResult<()>) {
+        if self.order.len() >= SEEN_KEYS {
+            if let Some(oldest) = self.order.pop_front() {
+                self.results.remove(&oldest);
+            }
+        }
+        self.order.push_back(key.clone());
+        self.results.insert(key, result);
     }
 }
 
 #[async_trait]
 impl<M: Model> CommitStore<M> for MemStore<M> {
-    fn keys(&self) -> BoxStream<CommitResult<EntityId>> {
+    fn keys(&self) -> BoxStream<CommitResult<M::Id>> {
         let map = self.0.clone();
         stream::once(async move {
-            let keys = map.lock().await.keys().map(|k| Ok(*k)).collect::<Vec<_>>();
-            stream::iter(keys)
+            let entities = map.lock().await;
+            // in the order entities were created so pages of lists are stable
+            let mut keys = entities
+                .by_id
+                .iter()
+                .map(|(id, (created, _))| (created.when(), id.clone()))
+                .collect::<Vec<_>>();
+            keys.sort_by_key(|(created, _)| *created);
+            stream::iter(keys.into_iter().map(|(_, id)| Ok(id)))
         })
         .flatten()
         .boxed()
     }
 
-    fn change_list(&self, id: EntityId) -> BoxStream<CommitResult<Commit<M>>> {
+    fn change_list(&self, id: M::Id) -> BoxStream<CommitResult<Commit<M>>> {
         let map = self.0.clone();
         stream::once(async move {
             let map = map.lock().await;
-            let (initial_commit, changes) = map.get(&id).ok_or(CommitError::NotFound)?;
+            let (initial_commit, changes) = map.by_id.get(&id).ok_or(CommitError::NotFound)?;
             let changes = changes.to_owned();
             let commits =
                 iter::once(Ok(initial_commit.clone())).chain(changes.into_iter().map(Result::Ok));
@@ -43,23 +316,429 @@ impl<M: Model> CommitStore<M> for MemStore<M> {
     }
 
     async fn commit(&self, c: Commit<M>) -> Result<(), CommitError> {
-        let id = c.event.entity_id();
+        let key = match c.idempotency_key() {
+            Some(key) => key.to_string(),
+            None => return self.append(c).await,
+        };
+        let mut seen = self.1.lock().await;
+        if let Some(result) = seen.results.get(&key) {
+            debug!("already committed {}", key);
+            return result.clone();
+        }
+        let result = self.append(c).await;
+        seen.remember(key, result.clone());
+        result
+    }
+
+    /// Commits the whole batch or nothing, idempotency keys aren't checked
+    async fn commit_all(&self, commits: Vec<Commit<M>>) -> CommitResult<()> {
         let mut entities = self.0.lock().await;
-        match c.event {
-            Event::Create(_) => {
-                entities.insert(id, (c, vec![]));
-            }
-            Event::Change(_, _) => {
-                let (_, updates) = entities.get_mut(&id).ok_or(CommitError::CantChange)?;
-                updates.push(c);
+        // versions entities will have as the batch is applied and whether
+        // they will be deleted
+        let mut versions = HashMap::new();
+        for c in commits.iter() {
+            let id = c.entity_id();
+            let version = match &c.event {
+                Event::Create(_) => 0,
+                Event::Change(_, _) | Event::Delete(_) => {
+                    let stored = entities
+                        .by_id
+                        .get(&id)
+                        .map(|stored| (1 + stored.1.len() as u64, is_deleted(stored)));
+                    let version = match versions.get(&id).copied().or(stored) {
+                        Some((version, false)) => version,
+                        _ => return Err(CommitError::CantChange),
+                    };
+                    check_version(c, version)?;
+                    version
+                }
+            };
+            let deleted = c.kind() == EventKind::Delete;
+            versions.insert(id, (version + 1, deleted));
+        }
+        let size = commits.iter().map(commit_size).sum();
+        let ids: Vec<_> = commits.iter().map(|c| c.entity_id()).collect();
+        entities.make_room(size, self.3, &ids)?;
+        for c in commits {
+            entities.insert(c)?;
+        }
+        for id in ids.iter() {
+            entities.snapshot_every(id, self.4);
+        }
+        Ok(())
+    }
+
+    async fn import_entity(
+        &self,
+        id: M::Id,
+        history: Vec<Commit<M>>,
+        force: bool,
+    ) -> CommitResult<()> {
+        check_history(&id, &history)?;
+        let mut entities = self.0.lock().await;
+        if !force && entities.by_id.contains_key(&id) {
+            return Err(CommitError::AlreadyExists);
+        }
+        let size = history.iter().map(commit_size).sum();
+        let replaced = entities.remove(&id);
+        if let Err(err) = entities.make_room(size, self.3, &[]) {
+            if let Some((created, changes)) = replaced {
+                entities.insert(created)?;
+                changes.into_iter().try_for_each(|c| entities.insert(c))?;
             }
+            return Err(err);
         }
+        let mut history = history.into_iter();
+        let initial_commit = history.next().unwrap();
+        let mut changes: Vec<_> = history.collect();
+        changes.sort_by_key(Commit::order_key);
+        entities.insert(initial_commit)?;
+        changes.into_iter().try_for_each(|c| entities.insert(c))?;
+        entities.snapshot_every(&id, self.4);
+        Ok(())
+    }
+
+    async fn purge(&self, id: M::Id) -> CommitResult<()> {
+        self.0
+            .lock()
+            .await
+            .remove(&id)
+            .ok_or(CommitError::NotFound)?;
+        Ok(())
+    }
+
+    async fn save_snapshot(&self, snapshot: Snapshot<M>) -> CommitResult<()> {
+        let mut entities = self.0.lock().await;
+        let id = snapshot.model.id();
+        let (_, changes) = entities.by_id.get(&id).ok_or(CommitError::NotFound)?;
+        if snapshot.version == 0 || snapshot.version > 1 + changes.len() as u64 {
+            return Err(CommitError::CantChange);
+        }
+        entities.snapshots.insert(id, snapshot);
+        Ok(())
+    }
+
+    async fn load_snapshot(&self, id: M::Id) -> CommitResult<Option<Snapshot<M>>> {
+        Ok(self.0.lock().await.snapshots.get(&id).cloned())
+    }
+
+    async fn count_at(&self, time: DateTime<Utc>) -> CommitResult<usize> {
+        let entities = self.0.lock().await;
+        let deleted = |c: &Commit<M>| c.kind() == EventKind::Delete && c.when() <= time;
+        Ok(entities
+            .by_id
+            .values()
+            .filter(|(created, changes)| created.when() <= time && !changes.iter().any(deleted))
+            .count())
+    }
+
+    async fn tag(&self, name: String, at: DateTime<Utc>) -> CommitResult<()> {
+        self.2.lock().await.insert(name, at);
         Ok(())
     }
+
+    async fn resolve_tag(&self, name: &str) -> CommitResult<DateTime<Utc>> {
+        self.2
+            .lock()
+            .await
+            .get(name)
+            .copied()
+            .ok_or(CommitError::NotFound)
+    }
 }
 
 impl<M: Model> Clone for MemStore<M> {
     fn clone(&self) -> Self {
-        Self(self.0.clone())
+        Self(
+            self.0.clone(),
+            self.1.clone(),
+            self.2.clone(),
+            self.3,
+            self.4,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::tests::{Op, TestCount};
+    use crate::EntityId;
+    use chrono::{Duration, Utc};
+    use futures::executor::block_on;
+
+    #[derive(Clone, Debug, Default)]
+    struct Doc {
+        id: EntityId,
+        lines: Vec<String>,
+    }
+    impl Model for Doc {
+        type Id = EntityId;
+        type Change = (u64, String);
+        fn id(&self) -> EntityId {
+            self.id
+        }
+        fn apply_change(&mut self, (_, line): &(u64, String)) {
+            self.lines.push(line.clone());
+        }
+        fn change_order((version, _): &(u64, String)) -> Option<u64> {
+            Some(*version)
+        }
+        fn heap_size(&self) -> usize {
+            self.lines.iter().map(String::capacity).sum::<usize>()
+                + self.lines.capacity() * size_of::<String>()
+        }
+        fn change_heap_size((_, line): &(u64, String)) -> usize {
+            line.capacity()
+        }
+    }
+
+    #[test]
+    fn replay_in_domain_order() {
+        let store = MemStore::new();
+        let doc = Doc::default();
+        let id = doc.id();
+        block_on(store.commit(Event::Create(doc).into())).unwrap();
+        for (version, line) in [(2, "b"), (1, "a"), (3, "c")] {
+            let change = Event::Change(id, (version, line.to_string()));
+            block_on(store.commit(change.into())).unwrap();
+        }
+
+        let doc = block_on(async { store.get(id).await?.to_present().await }).unwrap();
+        assert_eq!(doc.lines, vec!["a", "b", "c"]);
+        let keys: Vec<_> = block_on(
+            store
+                .change_list(id)
+                .map_ok(|c| c.order_key())
+                .try_collect(),
+        )
+        .unwrap();
+        assert_eq!(keys, vec![None, Some(1), Some(2), Some(3)]);
+        let before_last = Utc::now();
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        block_on(store.commit(Event::Change(id, (0, "intro".into())).into())).unwrap();
+        let doc = block_on(async { store.get(id).await?.travel_to(before_last).await }).unwrap();
+        assert_eq!(doc.lines, vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn enforce_memory_limit() {
+        let one = size_of::<Commit<TestCount>>();
+        let limit = |policy| MemLimit {
+            max_bytes: 3 * one,
+            policy,
+        };
+        let store = MemStore::with_limit(limit(MemPolicy::Reject));
+        let count = TestCount::new(1);
+        let id = count.id();
+        block_on(store.commit(Event::Create(count).into())).unwrap();
+        block_on(store.commit(Event::Change(id, Op::Add(1)).into())).unwrap();
+        let stats = block_on(store.stats());
+        assert_eq!(stats.entities, 1);
+        assert_eq!(stats.commits, 2);
+        assert_eq!(stats.bytes, 2 * one);
+        block_on(store.commit(Event::Create(TestCount::new(2)).into())).unwrap();
+        let res = block_on(store.commit(Event::Change(id, Op::Add(1)).into()));
+        assert!(matches!(res, Err(CommitError::MemoryLimit(_))));
+        assert_eq!(block_on(store.stats()).commits, 3);
+        block_on(store.purge(id)).unwrap();
+        assert_eq!(block_on(store.stats()).bytes, one);
+
+        let store = MemStore::with_limit(limit(MemPolicy::EvictIdlest));
+        let counts: Vec<_> = (0..3).map(TestCount::new).collect();
+        for count in counts.iter() {
+            block_on(store.commit(Event::Create(count.clone()).into())).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(2));
+        }
+        block_on(store.commit(Event::Change(counts[0].id(), Op::Add(1)).into())).unwrap();
+        assert_eq!(block_on(store.stats()).entities, 2);
+        assert!(block_on(store.get(counts[1].id())).is_err());
+        assert_eq!(
+            block_on(store.snapshot(counts[0].id(), Utc::now()))
+                .unwrap()
+                .count,
+            1
+        );
+    }
+
+    #[test]
+    fn count_heap_of_models_and_changes() {
+        let one = size_of::<Commit<Doc>>();
+        let store = MemStore::new();
+        let doc = Doc {
+            lines: vec!["title".into()],
+            ..Default::default()
+        };
+        let id = doc.id();
+        let created = one + doc.heap_size();
+        block_on(store.commit(Event::Create(doc).into())).unwrap();
+        assert_eq!(block_on(store.stats()).bytes, created);
+        let line = String::from("a long enough line");
+        let changed = one + line.capacity();
+        block_on(store.commit(Event::Change(id, (1, line)).into())).unwrap();
+        assert_eq!(block_on(store.stats()).bytes, created + changed);
+    }
+
+    #[test]
+    fn read_from_latest_snapshot() {
+        let store = MemStore::new().snapshot_every(3);
+        let count = TestCount::new(0);
+        let id = count.id();
+        block_on(store.commit(Event::Create(count).into())).unwrap();
+        let mut times = vec![];
+        for _ in 0..4 {
+            let change: Commit<TestCount> = Event::Change(id, Op::Add(1)).into();
+            times.push(change.when());
+            block_on(store.commit(change)).unwrap();
+        }
+        let snapshot = block_on(store.load_snapshot(id)).unwrap().unwrap();
+        assert_eq!(snapshot.version, 4);
+        assert_eq!(snapshot.model.count, 3);
+        assert_eq!(block_on(store.snapshot(id, Utc::now())).unwrap().count, 4);
+        // moments before the snapshot replay the history
+        assert_eq!(block_on(store.snapshot(id, times[1])).unwrap().count, 2);
+        assert_eq!(block_on(store.snapshot_at_version(id, 3)).unwrap().count, 2);
+        assert_eq!(block_on(store.snapshot_at_version(id, 5)).unwrap().count, 4);
+
+        // only the changes after the snapshot are applied to it
+        let mut snapshot = snapshot;
+        snapshot.model.count = 100;
+        block_on(store.save_snapshot(snapshot)).unwrap();
+        assert_eq!(block_on(store.snapshot(id, Utc::now())).unwrap().count, 101);
+        block_on(store.purge(id)).unwrap();
+        assert!(block_on(store.load_snapshot(id)).unwrap().is_none());
+    }
+
+    #[test]
+    fn keep_commit_metadata() {
+        let store = MemStore::new();
+        let count = TestCount::new(1);
+        let id = count.id();
+        block_on(store.commit(Event::Create(count).into())).unwrap();
+        let change = Commit::<TestCount>::new(
+            Event::Change(id, Op::Add(1)),
+            Some("auditor".into()),
+            Some("yearly review".into()),
+        );
+        let at = change.timestamp();
+        block_on(store.commit(change)).unwrap();
+
+        let commits: Vec<_> = block_on(store.change_list(id).try_collect()).unwrap();
+        assert_eq!(commits[0].author(), None);
+        assert_eq!(commits[1].author(), Some("auditor"));
+        assert_eq!(commits[1].reason(), Some("yearly review"));
+        assert_eq!(commits[1].timestamp(), at);
+    }
+
+    #[test]
+    fn reject_stale_versions() {
+        let store = MemStore::new();
+        let count = TestCount::new(1);
+        let id = count.id();
+        block_on(store.commit(Event::Create(count).into())).unwrap();
+        let based_on = |version| {
+            let mut c: Commit<TestCount> = Event::Change(id, Op::Add(1)).into();
+            c.set_expected_version(Some(version));
+            c
+        };
+        block_on(store.commit(based_on(1))).unwrap();
+        let stale = block_on(store.commit(based_on(1)));
+        assert!(matches!(
+            stale,
+            Err(CommitError::Conflict {
+                expected: 1,
+                actual: 2
+            })
+        ));
+        let batch = vec![based_on(2), based_on(2)];
+        let stale = block_on(store.commit_all(batch));
+        assert!(matches!(stale, Err(CommitError::Conflict { .. })));
+        assert_eq!(block_on(store.stats()).commits, 2);
+        block_on(store.commit_all(vec![based_on(2), based_on(3)])).unwrap();
+        assert_eq!(block_on(store.snapshot(id, Utc::now())).unwrap().count, 4);
+    }
+
+    #[test]
+    fn commit_batches_atomically() {
+        let store = MemStore::new();
+        let (first, second) = (TestCount::new(1), TestCount::new(10));
+        let (a, b) = (first.id(), second.id());
+        let batch: Vec<Commit<TestCount>> = vec![
+            Event::Create(first).into(),
+            Event::Change(a, Op::Add(1)).into(),
+            Event::Create(second).into(),
+            Event::Change(b, Op::Sub(1)).into(),
+        ];
+        // a change of an unknown entity fails the whole batch
+        let mut failing = batch.clone();
+        failing.push(Event::Change(EntityId::new(), Op::Add(1)).into());
+        let res = block_on(store.commit_all(failing));
+        assert!(matches!(res, Err(CommitError::CantChange)));
+        assert_eq!(block_on(store.stats()).commits, 0);
+        assert!(block_on(store.get(a)).is_err());
+
+        block_on(store.commit_all(batch)).unwrap();
+        assert_eq!(block_on(store.stats()).commits, 4);
+        assert_eq!(block_on(store.snapshot(a, Utc::now())).unwrap().count, 2);
+        assert_eq!(block_on(store.snapshot(b, Utc::now())).unwrap().count, 9);
+    }
+
+    #[test]
+    fn import_history_atomically() {
+        let store = MemStore::new();
+        let count = TestCount::new(1);
+        let id = count.id();
+        let start = Utc::now() - Duration::days(30);
+        let history: Vec<Commit<TestCount>> = vec![
+            Event::Create(count).into(),
+            Event::Change(id, Op::Add(2)).into(),
+            Event::Change(id, Op::Add(3)).into(),
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(i, mut c): (usize, Commit<TestCount>)| {
+            c.set_when(start + Duration::days(i as i64 * 10));
+            c.set_who(Some("legacy".into()));
+            c
+        })
+        .collect();
+
+        block_on(store.import_entity(id, history.clone(), false)).unwrap();
+        let at = |days| block_on(store.snapshot(id, start + Duration::days(days))).unwrap();
+        assert_eq!(at(0).count, 1);
+        assert_eq!(at(15).count, 3);
+        assert_eq!(at(25).count, 6);
+
+        let res = block_on(store.import_entity(id, history.clone(), false));
+        assert!(matches!(res, Err(CommitError::AlreadyExists)));
+        block_on(store.import_entity(id, history[..2].to_vec(), true)).unwrap();
+        assert_eq!(at(25).count, 3);
+
+        let res = block_on(store.import_entity(id, history[1..].to_vec(), true));
+        assert!(matches!(res, Err(CommitError::InvalidHistory(_))));
+    }
+
+    #[test]
+    fn commit_once_per_idempotency_key() {
+        let store = MemStore::new();
+        let count = TestCount::new(1);
+        let id = count.id();
+        block_on(store.commit(Event::Create(count).into())).unwrap();
+
+        let mut add: Commit<TestCount> = Event::Change(id, Op::Add(2)).into();
+        add.set_idempotency_key(Some("req-1".into()));
+        block_on(store.commit(add.clone())).unwrap();
+        block_on(store.commit(add)).unwrap();
+
+        let commits: Vec<_> = block_on(store.change_list(id).try_collect()).unwrap();
+        assert_eq!(commits.len(), 2);
+        assert_eq!(block_on(store.snapshot(id, Utc::now())).unwrap().count, 3);
+
+        let mut orphan: Commit<TestCount> = Event::Change(EntityId::new(), Op::Add(1)).into();
+        orphan.set_idempotency_key(Some("req-2".into()));
+        let res = block_on(store.commit(orphan.clone()));
+        assert!(matches!(res, Err(CommitError::CantChange)));
+        let res = block_on(store.commit(orphan));
+        assert!(matches!(res, Err(CommitError::CantChange)));
     }
 }
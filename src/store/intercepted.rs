@@ -0,0 +1,161 @@
+use super::{Commit, CommitResult, CommitStore, Snapshot};
+use crate::Model;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::stream::BoxStream;
+use std::fmt;
+use std::sync::Arc;
+
+/// Enriches commits right before they are persisted, e.g. to set the author
+/// or a server assigned timestamp.
+pub trait CommitInterceptor<M: Model>: Send + Sync + 'static {
+    fn before_commit(&self, commit: &mut Commit<M>);
+}
+
+impl<M, F> CommitInterceptor<M> for F
+where
+    M: Model,
+    F: Fn(&mut Commit<M>) + Send + Sync + 'static,
+{
+    fn before_commit(&self, commit: &mut Commit<M>) {
+        self(commit)
+    }
+}
+
+/// A store that runs every commit through a list of interceptors, in the
+/// order they were added, before passing it to the wrapped backend.
+pub struct InterceptedStore<M: Model, S> {
+    backend: S,
+    interceptors: Vec<Arc<dyn CommitInterceptor<M>>>,
+}
+
+impl<M: Model, S: CommitStore<M>> InterceptedStore<M, S> {
+    pub fn new(backend: S) -> Self {
+        InterceptedStore {
+            backend,
+            interceptors: vec![],
+        }
+    }
+
+    pub fn with<I: CommitInterceptor<M>>(mut self, interceptor: I) -> Self {
+        self.interceptors.push(Arc::new(interceptor));
+        self
+    }
+}
+
+#[async_trait]
+impl<M: Model, S: CommitStore<M>> CommitStore<M> for InterceptedStore<M, S> {
+    fn keys(&self) -> BoxStream<CommitResult<M::Id>> {
+        self.backend.keys()
+    }
+
+    fn change_list(&self, id: M::Id) -> BoxStream<CommitResult<Commit<M>>> {
+        self.backend.change_list(id)
+    }
+
+    async fn commit(&self, mut c: Commit<M>) -> CommitResult<()> {
+        for interceptor in self.interceptors.iter() {
+            interceptor.before_commit(&mut c);
+        }
+        self.backend.commit(c).await
+    }
+
+    async fn import_entity(
+        &self,
+        id: M::Id,
+        history: Vec<Commit<M>>,
+        force: bool,
+    ) -> CommitResult<()> {
+        self.backend.import_entity(id, history, force).await
+    }
+
+    async fn commit_all(&self, mut commits: Vec<Commit<M>>) -> CommitResult<()> {
+        for c in commits.iter_mut() {
+            for interceptor in self.interceptors.iter() {
+                interceptor.before_commit(c);
+            }
+        }
+        self.backend.commit_all(commits).await
+    }
+
+    async fn purge(&self, id: M::Id) -> CommitResult<()> {
+        self.backend.purge(id).await
+    }
+
+    async fn tag(&self, name: String, at: DateTime<Utc>) -> CommitResult<()> {
+        self.backend.tag(name, at).await
+    }
+
+    async fn resolve_tag(&self, name: &str) -> CommitResult<DateTime<Utc>> {
+        self.backend.resolve_tag(name).await
+    }
+
+    async fn count_at(&self, time: DateTime<Utc>) -> CommitResult<usize> {
+        self.backend.count_at(time).await
+    }
+
+    async fn ping(&self) -> CommitResult<()> {
+        self.backend.ping().await
+    }
+
+    async fn save_snapshot(&self, snapshot: Snapshot<M>) -> CommitResult<()> {
+        self.backend.save_snapshot(snapshot).await
+    }
+
+    async fn load_snapshot(&self, id: M::Id) -> CommitResult<Option<Snapshot<M>>> {
+        self.backend.load_snapshot(id).await
+    }
+
+    async fn checkpoint(&self, at: DateTime<Utc>) -> CommitResult<()> {
+        self.backend.checkpoint(at).await
+    }
+}
+
+impl<M: Model, S: Clone> Clone for InterceptedStore<M, S> {
+    fn clone(&self) -> Self {
+        InterceptedStore {
+            backend: self.backend.clone(),
+            interceptors: self.interceptors.clone(),
+        }
+    }
+}
+
+impl<M: Model, S: fmt::Debug> fmt::Debug for InterceptedStore<M, S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "InterceptedStore({:?}, {} interceptors)",
+            self.backend,
+            self.interceptors.len()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::tests::{Op, TestCount};
+    use crate::{Event, MemStore};
+    use futures::executor::block_on;
+    use futures::stream::TryStreamExt;
+
+    #[test]
+    fn intercept_commits_in_order() {
+        let backend = MemStore::new();
+        let store = InterceptedStore::new(backend.clone())
+            .with(|c: &mut Commit<TestCount>| c.set_who(Some("first".into())))
+            .with(|c: &mut Commit<TestCount>| {
+                let who = format!("{} then second", c.who().unwrap_or_default());
+                c.set_who(Some(who));
+            });
+
+        let count = TestCount::new(1);
+        let id = count.id();
+        block_on(store.commit(Event::Create(count).into())).unwrap();
+        block_on(store.commit(Event::Change(id, Op::Add(1)).into())).unwrap();
+
+        let commits: Vec<_> = block_on(backend.change_list(id).try_collect()).unwrap();
+        assert_eq!(commits.len(), 2);
+        assert!(commits.iter().all(|c| c.who() == Some("first then second")));
+    }
+}
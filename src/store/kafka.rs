@@ -0,0 +1,348 @@
+use super::{check_append, Commit, CommitError, CommitResult, CommitStore, IdCodec, StringId};
+use crate::{EventKind, SerializableModel};
+use async_trait::async_trait;
+use futures::channel::oneshot;
+use futures::lock::Mutex;
+use futures::stream::{self, BoxStream, StreamExt, TryStreamExt};
+use rdkafka::consumer::{BaseConsumer, Consumer};
+use rdkafka::error::{KafkaError, RDKafkaErrorCode};
+use rdkafka::message::Message as _;
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use rdkafka::{ClientConfig, Offset, TopicPartitionList};
+use std::collections::HashMap;
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// How long to wait for the brokers to answer a request
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Produces commits to a Kafka topic keyed by the id of their entity, every
+/// commit of an entity goes to the same partition so they keep their order.
+/// Kafka only appends so reading an entity consumes its partition from the
+/// beginning and listing entities consumes the whole topic, reads get slower
+/// as the topic grows unless it's compacted. Versions are checked reading
+/// the history before producing, which only holds while the store is the
+/// only one producing to the topic. Purging produces a tombstone, a
+/// compacted topic drops the history of the entity eventually.
+#[derive(Clone)]
+pub struct KafkaStore<M, C = StringId> {
+    brokers: String,
+    topic: String,
+    partitions: i32,
+    producer: FutureProducer,
+    // produces to the topic one commit at a time
+    writing: Arc<Mutex<()>>,
+    ids: C,
+    model: PhantomData<fn() -> M>,
+}
+
+/// A record of the topic, a tombstone has no payload
+struct Record {
+    key: Vec<u8>,
+    payload: Option<Vec<u8>>,
+}
+
+impl<M> KafkaStore<M> {
+    /// Produces to the existing topic of the brokers, it blocks until the
+    /// brokers tell how many partitions the topic has.
+    pub fn new(brokers: &str, topic: &str) -> CommitResult<Self> {
+        let producer: FutureProducer = ClientConfig::new()
+            .set("bootstrap.servers", brokers)
+            .create()
+            .map_err(kafka_error)?;
+        let metadata = producer
+            .client()
+            .fetch_metadata(Some(topic), TIMEOUT)
+            .map_err(kafka_error)?;
+        let partitions = metadata
+            .topics()
+            .iter()
+            .find(|t| t.name() == topic && t.error().is_none())
+            .map_or(0, |t| t.partitions().len() as i32);
+        if partitions == 0 {
+            return Err(CommitError::Log(format!(
+                "no partitions for topic {}",
+                topic
+            )));
+        }
+        Ok(KafkaStore {
+            brokers: brokers.into(),
+            topic: topic.into(),
+            partitions,
+            producer,
+            writing: Arc::new(Mutex::new(())),
+            ids: StringId,
+            model: PhantomData,
+        })
+    }
+}
+
+impl<M, C> KafkaStore<M, C> {
+    /// Keys the records with the entity ids the codec encodes
+    pub fn id_codec<D>(self, ids: D) -> KafkaStore<M, D> {
+        KafkaStore {
+            brokers: self.brokers,
+            topic: self.topic,
+            partitions: self.partitions,
+            producer: self.producer,
+            writing: self.writing,
+            ids,
+            model: PhantomData,
+        }
+    }
+
+    /// The partition of the records with the key, FNV-1a hashing keeps it
+    /// the same across builds unlike the hasher of the standard library.
+    fn partition(&self, key: &[u8]) -> i32 {
+        let hash = key.iter().fold(0xcbf2_9ce4_8422_2325_u64, |hash, b| {
+            (hash ^ u64::from(*b)).wrapping_mul(0x0100_0000_01b3)
+        });
+        (hash % self.partitions as u64) as i32
+    }
+
+    /// Every record of the partitions from their beginning to where they end
+    /// by the time they are read
+    async fn records(&self, partitions: Vec<i32>) -> CommitResult<Vec<Record>> {
+        let (brokers, topic) = (self.brokers.clone(), self.topic.clone());
+        blocking(move || read(&brokers, &topic, &partitions)).await
+    }
+
+    async fn produce(&self, key: &[u8], payload: Option<&str>) -> CommitResult<()> {
+        let mut record = FutureRecord::to(&self.topic)
+            .partition(self.partition(key))
+            .key(key);
+        if let Some(payload) = payload {
+            record = record.payload(payload);
+        }
+        let delivery = self
+            .producer
+            .send_result(record)
+            .map_err(|(e, _)| kafka_error(e))?;
+        match delivery.await {
+            Ok(Ok(_)) => Ok(()),
+            Ok(Err((e, _))) => Err(kafka_error(e)),
+            Err(_) => Err(CommitError::Unavailable),
+        }
+    }
+}
+
+impl<M: SerializableModel, C: IdCodec<M::Id>> KafkaStore<M, C> {
+    async fn history(&self, id: M::Id) -> CommitResult<Vec<CommitResult<Commit<M>>>> {
+        let key = self.ids.encode_id(&id);
+        let records = self.records(vec![self.partition(&key)]).await?;
+        let history = histories(records).remove(&key).unwrap_or_default();
+        if history.is_empty() {
+            return Err(CommitError::NotFound);
+        }
+        Ok(history.iter().map(|payload| decode(payload)).collect())
+    }
+}
+
+impl<M, C: fmt::Debug> fmt::Debug for KafkaStore<M, C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("KafkaStore")
+            .field("brokers", &self.brokers)
+            .field("topic", &self.topic)
+            .field("partitions", &self.partitions)
+            .field("ids", &self.ids)
+            .finish()
+    }
+}
+
+#[async_trait]
+impl<M, C> CommitStore<M> for KafkaStore<M, C>
+where
+    M: SerializableModel,
+    C: IdCodec<M::Id>,
+{
+    fn keys(&self) -> BoxStream<CommitResult<M::Id>> {
+        stream::once(async move {
+            let records = self.records((0..self.partitions).collect()).await?;
+            let mut created = vec![];
+            for (key, history) in histories(records) {
+                if let Some(first) = history.first() {
+                    created.push((decode::<M>(first)?.when(), key));
+                }
+            }
+            // creation order keeps pages of the list consistent
+            created.sort_by_key(|(when, _)| *when);
+            Ok(stream::iter(
+                created
+                    .into_iter()
+                    .map(move |(_, key)| self.ids.decode_id(&key)),
+            ))
+        })
+        .try_flatten()
+        .boxed()
+    }
+
+    fn change_list(&self, id: M::Id) -> BoxStream<CommitResult<Commit<M>>> {
+        stream::once(self.history(id))
+            .map_ok(stream::iter)
+            .try_flatten()
+            .boxed()
+    }
+
+    async fn commit(&self, c: Commit<M>) -> CommitResult<()> {
+        let payload = serde_json::to_string(&c).map_err(|e| CommitError::Codec(e.to_string()))?;
+        let _writing = self.writing.lock().await;
+        let history = match self.history(c.entity_id()).await {
+            Err(CommitError::NotFound) => vec![],
+            history => history?,
+        };
+        let deleted = matches!(history.last(), Some(Ok(c)) if c.kind() == EventKind::Delete);
+        check_append(&c, history.len() as u64, deleted)?;
+        self.produce(&self.ids.encode_id(&c.entity_id()), Some(&payload))
+            .await
+    }
+
+    async fn ping(&self) -> CommitResult<()> {
+        let (producer, topic) = (self.producer.clone(), self.topic.clone());
+        blocking(move || {
+            producer
+                .client()
+                .fetch_metadata(Some(&topic), TIMEOUT)
+                .map(|_| ())
+                .map_err(|_| CommitError::Unavailable)
+        })
+        .await
+    }
+
+    async fn purge(&self, id: M::Id) -> CommitResult<()> {
+        let _writing = self.writing.lock().await;
+        self.history(id.clone()).await?;
+        self.produce(&self.ids.encode_id(&id), None).await
+    }
+}
+
+/// Runs a call to the brokers that blocks on a thread of its own
+async fn blocking<T, F>(call: F) -> CommitResult<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> CommitResult<T> + Send + 'static,
+{
+    let (done, result) = oneshot::channel();
+    thread::spawn(move || {
+        let _ = done.send(call());
+    });
+    result.await.unwrap_or(Err(CommitError::Unavailable))
+}
+
+fn read(brokers: &str, topic: &str, partitions: &[i32]) -> CommitResult<Vec<Record>> {
+    let consumer: BaseConsumer = ClientConfig::new()
+        .set("bootstrap.servers", brokers)
+        .set("group.id", "actor-es")
+        .set("enable.auto.commit", "false")
+        .create()
+        .map_err(kafka_error)?;
+    let mut records = vec![];
+    for &partition in partitions {
+        let (low, high) = consumer
+            .fetch_watermarks(topic, partition, TIMEOUT)
+            .map_err(kafka_error)?;
+        if high <= low {
+            continue;
+        }
+        let mut assigned = TopicPartitionList::new();
+        assigned
+            .add_partition_offset(topic, partition, Offset::Beginning)
+            .map_err(kafka_error)?;
+        consumer.assign(&assigned).map_err(kafka_error)?;
+        loop {
+            let message = match consumer.poll(TIMEOUT) {
+                Some(message) => message.map_err(kafka_error)?,
+                None => return Err(CommitError::Unavailable),
+            };
+            records.push(Record {
+                key: message.key().map(<[u8]>::to_vec).unwrap_or_default(),
+                payload: message.payload().map(<[u8]>::to_vec),
+            });
+            if message.offset() + 1 >= high {
+                break;
+            }
+        }
+    }
+    Ok(records)
+}
+
+/// The payloads of the commits of every key, a tombstone forgets the ones
+/// before it
+fn histories(records: Vec<Record>) -> HashMap<Vec<u8>, Vec<Vec<u8>>> {
+    let mut histories: HashMap<_, Vec<_>> = HashMap::new();
+    for Record { key, payload } in records {
+        match payload {
+            Some(payload) => histories.entry(key).or_default().push(payload),
+            None => {
+                histories.remove(&key);
+            }
+        }
+    }
+    histories
+}
+
+fn decode<M: SerializableModel>(payload: &[u8]) -> CommitResult<Commit<M>> {
+    serde_json::from_slice(payload).map_err(|e| CommitError::Codec(e.to_string()))
+}
+
+/// Brokers that can't be reached leave the store unavailable, anything else
+/// is a failure of the log.
+fn kafka_error(e: KafkaError) -> CommitError {
+    match e.rdkafka_error_code() {
+        Some(
+            RDKafkaErrorCode::AllBrokersDown
+            | RDKafkaErrorCode::BrokerTransportFailure
+            | RDKafkaErrorCode::MessageTimedOut
+            | RDKafkaErrorCode::OperationTimedOut,
+        ) => CommitError::Unavailable,
+        _ => CommitError::Log(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::tests::{Op, TestCount};
+    use crate::{Event, Model};
+    use chrono::Utc;
+    use futures::executor::block_on;
+
+    /// A store producing to `KAFKA_TOPIC` of the brokers at `KAFKA_BROKERS`
+    fn store() -> KafkaStore<TestCount> {
+        let var = |name, default: &str| std::env::var(name).unwrap_or_else(|_| default.into());
+        KafkaStore::new(
+            &var("KAFKA_BROKERS", "localhost:9092"),
+            &var("KAFKA_TOPIC", "actor-es-commits"),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn round_trip_commits() {
+        let store = store();
+        let count = TestCount::new(1);
+        let id = count.id();
+        block_on(store.commit(Event::Create(count.clone()).into())).unwrap();
+        let changed = Commit::new(Event::Change(id, Op::Add(2)), Some("tester".into()), None);
+        block_on(store.commit(changed)).unwrap();
+
+        let history: Vec<_> = block_on(store.change_list(id).try_collect()).unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[1].who(), Some("tester"));
+        assert_eq!(block_on(store.snapshot(id, Utc::now())).unwrap().count, 3);
+        let keys: Vec<_> = block_on(store.keys().try_collect()).unwrap();
+        assert!(keys.contains(&id));
+        let res = block_on(store.commit(Event::Create(count).into()));
+        assert!(matches!(res, Err(CommitError::AlreadyExists)));
+
+        block_on(store.purge(id)).unwrap();
+        assert!(matches!(
+            block_on(store.get(id)),
+            Err(CommitError::NotFound)
+        ));
+        let keys: Vec<_> = block_on(store.keys().try_collect()).unwrap();
+        assert!(!keys.contains(&id));
+    }
+}
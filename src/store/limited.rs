@@ -0,0 +1,146 @@
+use super::{Codec, Commit, CommitError, CommitResult, CommitStore, Snapshot};
+use crate::{Event, Model};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::stream::BoxStream;
+use serde::Serialize;
+
+/// A store that rejects commits whose event takes more than `limit` bytes
+/// once serialized with the given codec.
+#[derive(Debug, Clone)]
+pub struct SizeLimitedStore<S, C> {
+    backend: S,
+    codec: C,
+    limit: usize,
+}
+
+impl<S, C: Codec> SizeLimitedStore<S, C> {
+    fn check_size<M>(&self, c: &Commit<M>) -> CommitResult<()>
+    where
+        M: Model + Serialize,
+        M::Change: Serialize,
+    {
+        let size = match &**c {
+            Event::Create(model) => self.codec.encode(model)?.len(),
+            Event::Change(_, change) => self.codec.encode(change)?.len(),
+            Event::Delete(_) => 0,
+        };
+        if size > self.limit {
+            return Err(CommitError::PayloadTooLarge {
+                size,
+                limit: self.limit,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn new(backend: S, codec: C, limit: usize) -> Self {
+        SizeLimitedStore {
+            backend,
+            codec,
+            limit,
+        }
+    }
+}
+
+#[async_trait]
+impl<M, S, C> CommitStore<M> for SizeLimitedStore<S, C>
+where
+    M: Model + Serialize,
+    M::Change: Serialize,
+    S: CommitStore<M>,
+    C: Codec,
+{
+    fn keys(&self) -> BoxStream<CommitResult<M::Id>> {
+        self.backend.keys()
+    }
+
+    fn change_list(&self, id: M::Id) -> BoxStream<CommitResult<Commit<M>>> {
+        self.backend.change_list(id)
+    }
+
+    async fn commit(&self, c: Commit<M>) -> CommitResult<()> {
+        self.check_size(&c)?;
+        self.backend.commit(c).await
+    }
+
+    async fn import_entity(
+        &self,
+        id: M::Id,
+        history: Vec<Commit<M>>,
+        force: bool,
+    ) -> CommitResult<()> {
+        for c in history.iter() {
+            self.check_size(c)?;
+        }
+        self.backend.import_entity(id, history, force).await
+    }
+
+    async fn commit_all(&self, commits: Vec<Commit<M>>) -> CommitResult<()> {
+        for c in commits.iter() {
+            self.check_size(c)?;
+        }
+        self.backend.commit_all(commits).await
+    }
+
+    async fn purge(&self, id: M::Id) -> CommitResult<()> {
+        self.backend.purge(id).await
+    }
+
+    async fn tag(&self, name: String, at: DateTime<Utc>) -> CommitResult<()> {
+        self.backend.tag(name, at).await
+    }
+
+    async fn resolve_tag(&self, name: &str) -> CommitResult<DateTime<Utc>> {
+        self.backend.resolve_tag(name).await
+    }
+
+    async fn count_at(&self, time: DateTime<Utc>) -> CommitResult<usize> {
+        self.backend.count_at(time).await
+    }
+
+    async fn ping(&self) -> CommitResult<()> {
+        self.backend.ping().await
+    }
+
+    async fn save_snapshot(&self, snapshot: Snapshot<M>) -> CommitResult<()> {
+        self.backend.save_snapshot(snapshot).await
+    }
+
+    async fn load_snapshot(&self, id: M::Id) -> CommitResult<Option<Snapshot<M>>> {
+        self.backend.load_snapshot(id).await
+    }
+
+    async fn checkpoint(&self, at: DateTime<Utc>) -> CommitResult<()> {
+        self.backend.checkpoint(at).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::tests::{Op, TestCount};
+    use crate::{Json, MemStore};
+    use futures::executor::block_on;
+
+    #[test]
+    fn reject_large_events() {
+        let store = SizeLimitedStore::new(MemStore::new(), Json, 64);
+        let count = TestCount::new(1);
+        let id = count.id();
+        block_on(store.commit(Event::Create(count).into())).unwrap();
+
+        let oversized = TestCount::new(i16::MAX);
+        let limit = Json.encode(&oversized).unwrap().len() - 1;
+        let store = SizeLimitedStore::new(MemStore::new(), Json, limit);
+        let res = block_on(store.commit(Event::Create(oversized).into()));
+        assert!(matches!(res, Err(CommitError::PayloadTooLarge { size, .. }) if size == limit + 1));
+
+        let store = SizeLimitedStore::new(MemStore::<TestCount>::new(), Json, 2);
+        let res = block_on(store.commit(Event::Change(id, Op::Add(1)).into()));
+        assert!(matches!(
+            res,
+            Err(CommitError::PayloadTooLarge { limit: 2, .. })
+        ));
+    }
+}
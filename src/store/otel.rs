@@ -0,0 +1,282 @@
+use super::{Commit, CommitError, CommitResult, CommitStore, Snapshot, TimeTraveler};
+use crate::Model;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::stream::{BoxStream, Stream, StreamExt};
+use std::fmt;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tracing::field::{display, Empty};
+use tracing::{Instrument, Span};
+
+/// A store that wraps the operations of the backend in `tracing` spans with
+/// the `operation`, the `entity.id` when there's one and the `result`, with
+/// the `error` of failed ones. Spans are children of the span the operation
+/// is called in and parents of the ones the backend makes, a subscriber
+/// like `tracing-opentelemetry` exports them to an OpenTelemetry pipeline.
+/// Streams are traced until they are exhausted or dropped.
+#[derive(Clone)]
+pub struct OtelStore<S> {
+    backend: S,
+}
+
+impl<S> OtelStore<S> {
+    pub fn new(backend: S) -> Self {
+        OtelStore { backend }
+    }
+}
+
+fn span(operation: &'static str, id: Option<&dyn fmt::Display>) -> Span {
+    let span = tracing::info_span!(
+        "store",
+        otel.name = operation,
+        operation,
+        entity.id = Empty,
+        result = Empty,
+        error = Empty,
+        otel.status_code = Empty,
+    );
+    if let Some(id) = id {
+        span.record("entity.id", display(id));
+    }
+    span
+}
+
+fn record<T>(span: &Span, result: &CommitResult<T>) {
+    match result {
+        Ok(_) => {
+            span.record("result", "ok");
+        }
+        Err(e) => failed(span, e),
+    }
+}
+
+fn failed(span: &Span, e: &CommitError) {
+    span.record("result", "error");
+    span.record("error", display(e));
+    span.record("otel.status_code", "ERROR");
+}
+
+/// Polls the stream within its span, the first error fails the span
+struct Traced<'a, T> {
+    stream: BoxStream<'a, CommitResult<T>>,
+    span: Span,
+    failed: bool,
+}
+
+impl<'a, T: Send + 'a> Traced<'a, T> {
+    fn boxed(stream: BoxStream<'a, CommitResult<T>>, span: Span) -> BoxStream<'a, CommitResult<T>> {
+        Traced {
+            stream,
+            span,
+            failed: false,
+        }
+        .boxed()
+    }
+}
+
+impl<T> Stream for Traced<'_, T> {
+    type Item = CommitResult<T>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        let _entered = this.span.enter();
+        let polled = this.stream.poll_next_unpin(cx);
+        match &polled {
+            Poll::Ready(Some(Err(e))) if !this.failed => {
+                this.failed = true;
+                failed(&this.span, e);
+            }
+            Poll::Ready(None) if !this.failed => {
+                this.span.record("result", "ok");
+            }
+            _ => {}
+        }
+        polled
+    }
+}
+
+#[async_trait]
+impl<M: Model, S: CommitStore<M>> CommitStore<M> for OtelStore<S> {
+    fn keys(&self) -> BoxStream<CommitResult<M::Id>> {
+        Traced::boxed(self.backend.keys(), span("keys", None))
+    }
+
+    fn change_list(&self, id: M::Id) -> BoxStream<CommitResult<Commit<M>>> {
+        let span = span("change_list", Some(&id));
+        Traced::boxed(self.backend.change_list(id), span)
+    }
+
+    async fn commit(&self, c: Commit<M>) -> CommitResult<()> {
+        let span = span("commit", Some(&c.entity_id()));
+        let result = self.backend.commit(c).instrument(span.clone()).await;
+        record(&span, &result);
+        result
+    }
+
+    async fn import_entity(
+        &self,
+        id: M::Id,
+        history: Vec<Commit<M>>,
+        force: bool,
+    ) -> CommitResult<()> {
+        let span = span("import_entity", Some(&id));
+        let result = self
+            .backend
+            .import_entity(id, history, force)
+            .instrument(span.clone())
+            .await;
+        record(&span, &result);
+        result
+    }
+
+    async fn commit_all(&self, commits: Vec<Commit<M>>) -> CommitResult<()> {
+        let span = span("commit_all", None);
+        let result = self
+            .backend
+            .commit_all(commits)
+            .instrument(span.clone())
+            .await;
+        record(&span, &result);
+        result
+    }
+
+    async fn purge(&self, id: M::Id) -> CommitResult<()> {
+        let span = span("purge", Some(&id));
+        let result = self.backend.purge(id).instrument(span.clone()).await;
+        record(&span, &result);
+        result
+    }
+
+    async fn get(&self, id: M::Id) -> CommitResult<TimeTraveler<'_, M>> {
+        let span = span("get", Some(&id));
+        let result = self.backend.get(id).instrument(span.clone()).await;
+        record(&span, &result);
+        result
+    }
+
+    async fn ping(&self) -> CommitResult<()> {
+        self.backend.ping().await
+    }
+
+    async fn checkpoint(&self, at: DateTime<Utc>) -> CommitResult<()> {
+        self.backend.checkpoint(at).await
+    }
+
+    async fn tag(&self, name: String, at: DateTime<Utc>) -> CommitResult<()> {
+        self.backend.tag(name, at).await
+    }
+
+    async fn resolve_tag(&self, name: &str) -> CommitResult<DateTime<Utc>> {
+        self.backend.resolve_tag(name).await
+    }
+
+    async fn count_at(&self, time: DateTime<Utc>) -> CommitResult<usize> {
+        self.backend.count_at(time).await
+    }
+
+    async fn save_snapshot(&self, snapshot: Snapshot<M>) -> CommitResult<()> {
+        self.backend.save_snapshot(snapshot).await
+    }
+
+    async fn load_snapshot(&self, id: M::Id) -> CommitResult<Option<Snapshot<M>>> {
+        self.backend.load_snapshot(id).await
+    }
+}
+
+impl<S: fmt::Debug> fmt::Debug for OtelStore<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "OtelStore({:?})", self.backend)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::tests::{Op, TestCount};
+    use crate::{Event, MemStore};
+    use futures::executor::block_on;
+    use futures::stream::TryStreamExt;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::Subscriber;
+    use tracing_subscriber::layer::{Context as LayerContext, SubscriberExt};
+    use tracing_subscriber::registry::LookupSpan;
+    use tracing_subscriber::Layer;
+
+    type Fields = HashMap<String, String>;
+
+    /// Keeps the fields of every span in the order they were made
+    #[derive(Clone, Default)]
+    struct Spans(Arc<Mutex<Vec<(Id, Fields)>>>);
+
+    struct Recorder<'a>(&'a mut Fields);
+
+    impl Visit for Recorder<'_> {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0.insert(field.name().into(), value.into());
+        }
+
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            self.0.insert(field.name().into(), format!("{:?}", value));
+        }
+    }
+
+    impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for Spans {
+        fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, _cx: LayerContext<'_, S>) {
+            let mut fields = Fields::new();
+            attrs.record(&mut Recorder(&mut fields));
+            self.0.lock().unwrap().push((id.clone(), fields));
+        }
+
+        fn on_record(&self, id: &Id, values: &Record<'_>, _cx: LayerContext<'_, S>) {
+            let mut spans = self.0.lock().unwrap();
+            if let Some((_, fields)) = spans.iter_mut().rev().find(|(span, _)| span == id) {
+                values.record(&mut Recorder(fields));
+            }
+        }
+    }
+
+    #[test]
+    fn trace_operations_in_spans() {
+        let spans = Spans::default();
+        let subscriber = tracing_subscriber::registry().with(spans.clone());
+        let store = OtelStore::new(MemStore::new());
+        let count = TestCount::new(1);
+        let id = count.id();
+
+        tracing::subscriber::with_default(subscriber, || {
+            block_on(store.commit(Event::Create(count).into())).unwrap();
+            block_on(store.commit(Event::Change(id, Op::Add(1)).into())).unwrap();
+            let _: Vec<_> = block_on(store.change_list(id).try_collect()).unwrap();
+            block_on(store.get(id)).unwrap();
+            let missing = TestCount::new(2).id();
+            assert!(block_on(store.commit(Event::Change(missing, Op::Add(1)).into())).is_err());
+        });
+
+        let spans = spans.0.lock().unwrap();
+        let field = |i: usize, name| spans[i].1.get(name).map(String::as_str);
+        let ops: Vec<_> = (0..spans.len()).map(|i| field(i, "operation")).collect();
+        assert_eq!(
+            ops,
+            vec![
+                Some("commit"),
+                Some("commit"),
+                Some("change_list"),
+                Some("get"),
+                Some("commit")
+            ]
+        );
+        let id = id.to_string();
+        assert!((0..4).all(|i| field(i, "entity.id") == Some(id.as_str())));
+        assert!((0..4).all(|i| field(i, "result") == Some("ok")));
+        assert_eq!(field(4, "result"), Some("error"));
+        assert_eq!(
+            field(4, "error"),
+            Some(CommitError::CantChange.to_string().as_str())
+        );
+        assert_eq!(field(4, "otel.status_code"), Some("ERROR"));
+    }
+}
@@ -0,0 +1,311 @@
+use super::sql::{check_table, db_error, decode, encode};
+use super::{check_append, Commit, CommitError, CommitResult, CommitStore, IdCodec, StringId};
+use crate::{EventKind, SerializableModel};
+use async_trait::async_trait;
+use futures::future::ready;
+use futures::stream::{self, BoxStream, StreamExt, TryStreamExt};
+use sqlx::postgres::{PgConnection, PgPool};
+use sqlx::Row;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+/// Keeps the commits of every entity in a table of a Postgres database, one
+/// row per commit with its metadata in columns and the whole commit JSON
+/// encoded in `payload`. Entity types can share a database as long as each
+/// one has its own table.
+#[derive(Debug, Clone)]
+pub struct PgStore<M, C = StringId> {
+    pool: PgPool,
+    table: String,
+    queries: Arc<Queries>,
+    ids: C,
+    model: PhantomData<fn() -> M>,
+}
+
+#[derive(Debug)]
+struct Queries {
+    keys: String,
+    history: String,
+    lock: String,
+    last: String,
+    insert: String,
+    purge: String,
+}
+
+impl Queries {
+    fn new(table: &str) -> Self {
+        Queries {
+            // every entity has one creation, listed in the order they were created
+            keys: format!(
+                "SELECT entity_id FROM {} WHERE sequence = 0 ORDER BY position",
+                table
+            ),
+            history: format!(
+                "SELECT payload FROM {} WHERE entity_id = $1 ORDER BY sequence",
+                table
+            ),
+            // commits to the same entity are appended one at a time across
+            // connections until the transaction ends
+            lock: format!(
+                "SELECT pg_advisory_xact_lock(hashtextextended('{}:' || encode($1, 'hex'), 0))",
+                table
+            ),
+            last: format!(
+                "SELECT sequence, payload FROM {} WHERE entity_id = $1 ORDER BY sequence DESC LIMIT 1",
+                table
+            ),
+            insert: format!(
+                "INSERT INTO {} (entity_id, sequence, time, author, reason, payload) VALUES ($1, $2, $3, $4, $5, $6)",
+                table
+            ),
+            purge: format!("DELETE FROM {} WHERE entity_id = $1", table),
+        }
+    }
+}
+
+impl<M> PgStore<M> {
+    /// Keeps the commits in the table of the database, creating it if needed
+    pub async fn new(pool: PgPool, table: &str) -> CommitResult<Self> {
+        PgStore::with_id_codec(pool, table, StringId).await
+    }
+}
+
+impl<M, C> PgStore<M, C> {
+    /// Like `new` with the entity ids stored the way the codec encodes them
+    pub async fn with_id_codec(pool: PgPool, table: &str, ids: C) -> CommitResult<Self> {
+        check_table(table)?;
+        let schema = format!(
+            "CREATE TABLE IF NOT EXISTS {} (
+                position BIGSERIAL PRIMARY KEY,
+                entity_id BYTEA NOT NULL,
+                sequence BIGINT NOT NULL,
+                time TIMESTAMPTZ NOT NULL,
+                author TEXT,
+                reason TEXT,
+                payload TEXT NOT NULL,
+                UNIQUE (entity_id, sequence)
+            )",
+            table
+        );
+        sqlx::query(&schema)
+            .execute(&pool)
+            .await
+            .map_err(db_error)?;
+        Ok(PgStore {
+            pool,
+            table: table.into(),
+            queries: Arc::new(Queries::new(table)),
+            ids,
+            model: PhantomData,
+        })
+    }
+
+    pub fn table(&self) -> &str {
+        &self.table
+    }
+}
+
+impl<M: SerializableModel, C: IdCodec<M::Id>> PgStore<M, C> {
+    /// Appends the commit to the history of its entity within the
+    /// transaction the connection is in.
+    async fn append(&self, conn: &mut PgConnection, c: &Commit<M>) -> CommitResult<()> {
+        let id = self.ids.encode_id(&c.entity_id());
+        sqlx::query(&self.queries.lock)
+            .bind(id.clone())
+            .execute(&mut *conn)
+            .await
+            .map_err(db_error)?;
+        let last = sqlx::query(&self.queries.last)
+            .bind(id.clone())
+            .fetch_optional(&mut *conn)
+            .await
+            .map_err(db_error)?;
+        let (version, deleted) = match last {
+            Some(row) => {
+                let sequence: i64 = row.try_get("sequence").map_err(db_error)?;
+                let payload: String = row.try_get("payload").map_err(db_error)?;
+                let last = decode::<M>(&payload)?;
+                (sequence as u64 + 1, last.kind() == EventKind::Delete)
+            }
+            None => (0, false),
+        };
+        check_append(c, version, deleted)?;
+        sqlx::query(&self.queries.insert)
+            .bind(id)
+            .bind(version as i64)
+            .bind(c.when())
+            .bind(c.who())
+            .bind(c.why())
+            .bind(encode(c)?)
+            .execute(&mut *conn)
+            .await
+            .map_err(db_error)?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<M, C> CommitStore<M> for PgStore<M, C>
+where
+    M: SerializableModel,
+    C: IdCodec<M::Id>,
+{
+    fn keys(&self) -> BoxStream<CommitResult<M::Id>> {
+        sqlx::query(&self.queries.keys)
+            .fetch(&self.pool)
+            .map_err(db_error)
+            .and_then(move |row| {
+                ready(
+                    row.try_get::<Vec<u8>, _>("entity_id")
+                        .map_err(db_error)
+                        .and_then(|id| self.ids.decode_id(&id)),
+                )
+            })
+            .boxed()
+    }
+
+    fn change_list(&self, id: M::Id) -> BoxStream<CommitResult<Commit<M>>> {
+        let mut commits = sqlx::query(&self.queries.history)
+            .bind(self.ids.encode_id(&id))
+            .fetch(&self.pool)
+            .map_err(db_error)
+            .and_then(|row| {
+                ready(
+                    row.try_get::<String, _>("payload")
+                        .map_err(db_error)
+                        .and_then(|payload| decode(&payload)),
+                )
+            });
+        stream::once(async move {
+            match commits.try_next().await {
+                Ok(Some(first)) => stream::once(ready(Ok(first))).chain(commits).boxed(),
+                Ok(None) => stream::once(ready(Err(CommitError::NotFound))).boxed(),
+                Err(e) => stream::once(ready(Err(e))).boxed(),
+            }
+        })
+        .flatten()
+        .boxed()
+    }
+
+    async fn commit(&self, c: Commit<M>) -> CommitResult<()> {
+        self.commit_all(vec![c]).await
+    }
+
+    /// Commits the whole batch in one transaction
+    async fn commit_all(&self, commits: Vec<Commit<M>>) -> CommitResult<()> {
+        let mut tx = self.pool.begin().await.map_err(db_error)?;
+        for c in &commits {
+            self.append(&mut tx, c).await?;
+        }
+        tx.commit().await.map_err(db_error)
+    }
+
+    async fn ping(&self) -> CommitResult<()> {
+        sqlx::query("SELECT 1")
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+            .map_err(|_| CommitError::Unavailable)
+    }
+
+    async fn purge(&self, id: M::Id) -> CommitResult<()> {
+        let purged = sqlx::query(&self.queries.purge)
+            .bind(self.ids.encode_id(&id))
+            .execute(&self.pool)
+            .await
+            .map_err(db_error)?;
+        match purged.rows_affected() {
+            0 => Err(CommitError::NotFound),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::tests::{Op, TestCount};
+    use crate::{Event, Model};
+    use chrono::Utc;
+    use futures::executor::block_on;
+    use uuid::Uuid;
+
+    /// A store with a table of its own in the database at `DATABASE_URL`
+    fn store() -> PgStore<TestCount> {
+        let url = std::env::var("DATABASE_URL")
+            .unwrap_or_else(|_| "postgres://localhost/actor_es".into());
+        let table = format!("counts_{}", Uuid::new_v4().to_simple());
+        block_on(async {
+            let pool = PgPool::connect(&url).await.expect("connect to database");
+            PgStore::new(pool, &table).await
+        })
+        .unwrap()
+    }
+
+    fn drop_table(store: PgStore<TestCount>) {
+        let drop = format!("DROP TABLE {}", store.table());
+        block_on(sqlx::query(&drop).execute(&store.pool)).unwrap();
+    }
+
+    #[test]
+    fn persist_commits_in_table() {
+        let store = store();
+        let (first, second) = (TestCount::new(1), TestCount::new(10));
+        let (a, b) = (first.id(), second.id());
+        block_on(store.commit(Event::Create(first.clone()).into())).unwrap();
+        block_on(store.commit_all(vec![
+            Event::Create(second).into(),
+            Event::Change(b, Op::Sub(3)).into(),
+        ]))
+        .unwrap();
+        let changed = Commit::new(Event::Change(a, Op::Add(2)), Some("tester".into()), None);
+        block_on(store.commit(changed)).unwrap();
+
+        let keys: Vec<_> = block_on(store.keys().try_collect()).unwrap();
+        assert_eq!(keys, vec![a, b]);
+        let history: Vec<_> = block_on(store.change_list(a).try_collect()).unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[1].who(), Some("tester"));
+        assert_eq!(block_on(store.snapshot(a, Utc::now())).unwrap().count, 3);
+        assert_eq!(block_on(store.snapshot(b, Utc::now())).unwrap().count, 7);
+
+        let res = block_on(store.commit(Event::Create(first).into()));
+        assert!(matches!(res, Err(CommitError::AlreadyExists)));
+        let res = block_on(store.commit(Event::Change(Uuid::new_v4().into(), Op::Add(1)).into()));
+        assert!(matches!(res, Err(CommitError::CantChange)));
+        let mut stale: Commit<TestCount> = Event::Change(a, Op::Add(1)).into();
+        stale.set_expected_version(Some(1));
+        let res = block_on(store.commit(stale));
+        assert!(matches!(
+            res,
+            Err(CommitError::Conflict {
+                expected: 1,
+                actual: 2
+            })
+        ));
+        // a failing commit leaves the rest of its batch out
+        let res = block_on(store.commit_all(vec![
+            Event::Change(a, Op::Add(1)).into(),
+            Event::Change(Uuid::new_v4().into(), Op::Add(1)).into(),
+        ]));
+        assert!(res.is_err());
+        assert_eq!(block_on(store.snapshot(a, Utc::now())).unwrap().count, 3);
+
+        block_on(store.purge(b)).unwrap();
+        let keys: Vec<_> = block_on(store.keys().try_collect()).unwrap();
+        assert_eq!(keys, vec![a]);
+        assert!(matches!(block_on(store.get(b)), Err(CommitError::NotFound)));
+        drop_table(store);
+    }
+
+    #[test]
+    fn reject_invalid_table_names() {
+        let store = store();
+        let res = block_on(PgStore::<TestCount>::new(
+            store.pool.clone(),
+            "counts; DROP TABLE counts",
+        ));
+        assert!(matches!(res, Err(CommitError::Database(_))));
+        drop_table(store);
+    }
+}
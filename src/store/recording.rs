@@ -0,0 +1,212 @@
+use super::{Commit, CommitError, CommitResult, CommitStore, Snapshot};
+use crate::Model;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::lock::Mutex;
+use futures::stream::BoxStream;
+use serde::{de::DeserializeOwned, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// A store that appends every successful commit to a recording on disk, one
+/// JSON encoded commit per line, so a scenario can be replayed later with
+/// `replay_recording`.
+#[derive(Debug, Clone)]
+pub struct RecordingStore<S> {
+    backend: S,
+    path: PathBuf,
+    file: Arc<Mutex<File>>,
+}
+
+impl<S> RecordingStore<S> {
+    pub fn new<P: AsRef<Path>>(backend: S, path: P) -> CommitResult<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| CommitError::Recording(e.to_string()))?;
+        Ok(RecordingStore {
+            backend,
+            path,
+            file: Arc::new(Mutex::new(file)),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[async_trait]
+impl<M, S> CommitStore<M> for RecordingStore<S>
+where
+    M: Model + Serialize,
+    M::Id: Serialize,
+    M::Change: Serialize,
+    S: CommitStore<M>,
+{
+    fn keys(&self) -> BoxStream<CommitResult<M::Id>> {
+        self.backend.keys()
+    }
+
+    fn change_list(&self, id: M::Id) -> BoxStream<CommitResult<Commit<M>>> {
+        self.backend.change_list(id)
+    }
+
+    async fn commit(&self, c: Commit<M>) -> CommitResult<()> {
+        let line = encode_line(&c)?;
+        self.backend.commit(c).await?;
+        self.record(&line).await
+    }
+
+    async fn import_entity(
+        &self,
+        id: M::Id,
+        history: Vec<Commit<M>>,
+        force: bool,
+    ) -> CommitResult<()> {
+        let mut lines = vec![];
+        for c in history.iter() {
+            lines.extend(encode_line(c)?);
+        }
+        self.backend.import_entity(id, history, force).await?;
+        self.record(&lines).await
+    }
+
+    async fn commit_all(&self, commits: Vec<Commit<M>>) -> CommitResult<()> {
+        let mut lines = vec![];
+        for c in commits.iter() {
+            lines.extend(encode_line(c)?);
+        }
+        self.backend.commit_all(commits).await?;
+        self.record(&lines).await
+    }
+
+    async fn purge(&self, id: M::Id) -> CommitResult<()> {
+        self.backend.purge(id).await
+    }
+
+    async fn tag(&self, name: String, at: DateTime<Utc>) -> CommitResult<()> {
+        self.backend.tag(name, at).await
+    }
+
+    async fn resolve_tag(&self, name: &str) -> CommitResult<DateTime<Utc>> {
+        self.backend.resolve_tag(name).await
+    }
+
+    async fn count_at(&self, time: DateTime<Utc>) -> CommitResult<usize> {
+        self.backend.count_at(time).await
+    }
+
+    async fn ping(&self) -> CommitResult<()> {
+        self.backend.ping().await
+    }
+
+    async fn save_snapshot(&self, snapshot: Snapshot<M>) -> CommitResult<()> {
+        self.backend.save_snapshot(snapshot).await
+    }
+
+    async fn load_snapshot(&self, id: M::Id) -> CommitResult<Option<Snapshot<M>>> {
+        self.backend.load_snapshot(id).await
+    }
+
+    async fn checkpoint(&self, at: DateTime<Utc>) -> CommitResult<()> {
+        self.backend.checkpoint(at).await
+    }
+}
+
+impl<S> RecordingStore<S> {
+    async fn record(&self, lines: &[u8]) -> CommitResult<()> {
+        self.file
+            .lock()
+            .await
+            .write_all(lines)
+            .map_err(|e| CommitError::Recording(e.to_string()))
+    }
+}
+
+fn encode_line<M>(c: &Commit<M>) -> CommitResult<Vec<u8>>
+where
+    M: Model + Serialize,
+    M::Id: Serialize,
+    M::Change: Serialize,
+{
+    let mut line = serde_json::to_vec(c).map_err(|e| CommitError::Codec(e.to_string()))?;
+    line.push(b'\n');
+    Ok(line)
+}
+
+/// Commits everything in a recording to the given store in the order it was
+/// recorded, keeping the original timestamps and metadata. Returns the number
+/// of replayed commits.
+pub async fn replay_recording<M, S, P>(path: P, store: &S) -> CommitResult<usize>
+where
+    M: Model + DeserializeOwned,
+    M::Id: DeserializeOwned,
+    M::Change: DeserializeOwned,
+    S: CommitStore<M>,
+    P: AsRef<Path>,
+{
+    let file = File::open(path).map_err(|e| CommitError::Recording(e.to_string()))?;
+    let mut replayed = 0;
+    for line in BufReader::new(file).lines() {
+        let line = line.map_err(|e| CommitError::Recording(e.to_string()))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let commit: Commit<M> =
+            serde_json::from_str(&line).map_err(|e| CommitError::Codec(e.to_string()))?;
+        store.commit(commit).await?;
+        replayed += 1;
+    }
+    Ok(replayed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::tests::{Op, TestCount};
+    use crate::{Event, MemStore};
+    use futures::executor::block_on;
+    use futures::{TryFutureExt, TryStreamExt};
+
+    #[test]
+    fn record_and_replay_scenario() {
+        let path = std::env::temp_dir().join(format!("{}.rec", uuid::Uuid::new_v4()));
+        let recorded = MemStore::new();
+        let store = RecordingStore::new(recorded.clone(), &path).unwrap();
+
+        let count = TestCount::new(1);
+        let id = count.id();
+        block_on(store.commit(Event::Create(count).into())).unwrap();
+        let mut add = Commit::new(
+            Event::Change(id, Op::Add(5)),
+            Some("tester".into()),
+            Some("add 5".into()),
+        );
+        add.set_transaction(Some(uuid::Uuid::new_v4()));
+        block_on(store.commit(add)).unwrap();
+        block_on(store.commit(Event::Change(id, Op::Sub(2)).into())).unwrap();
+
+        let replayed = MemStore::new();
+        let n = block_on(replay_recording::<TestCount, _, _>(&path, &replayed)).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(n, 3);
+
+        let original = block_on(recorded.get(id).and_then(|e| e.to_present())).unwrap();
+        let copy = block_on(replayed.get(id).and_then(|e| e.to_present())).unwrap();
+        assert_eq!(copy.count, original.count);
+
+        let original: Vec<_> = block_on(recorded.change_list(id).try_collect()).unwrap();
+        let copy: Vec<_> = block_on(replayed.change_list(id).try_collect()).unwrap();
+        for (a, b) in original.iter().zip(copy.iter()) {
+            assert_eq!(a.when(), b.when());
+            assert_eq!(a.who(), b.who());
+            assert_eq!(a.why(), b.why());
+            assert_eq!(a.transaction(), b.transaction());
+        }
+    }
+}
@@ -0,0 +1,362 @@
+use super::{Commit, CommitError, CommitResult, CommitStore, IdCodec, StringId};
+use crate::{EventKind, SerializableModel};
+use async_trait::async_trait;
+use futures::lock::Mutex;
+use futures::stream::{self, BoxStream, StreamExt, TryStreamExt};
+use redis::aio::MultiplexedConnection;
+use redis::streams::StreamRangeReply;
+use redis::{Client, FromRedisValue, RedisError, Script};
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+/// Appends commits to the stream of their entity only when they can follow
+/// its history, the whole check and append happening atomically.
+/// KEYS[1] is the stream, ARGV the kind of event, the expected version or an
+/// empty string and the encoded commit.
+const APPEND: &str = r"
+local version = redis.call('XLEN', KEYS[1])
+if ARGV[1] == 'create' then
+    if version > 0 then return {'exists', version} end
+else
+    if version == 0 then return {'missing', version} end
+    local last = redis.call('XREVRANGE', KEYS[1], '+', '-', 'COUNT', 1)
+    if last[1][2][2] == 'delete' then return {'missing', version} end
+end
+if ARGV[2] ~= '' and tonumber(ARGV[2]) ~= version then
+    return {'conflict', version}
+end
+redis.call('XADD', KEYS[1], '*', 'kind', ARGV[1], 'commit', ARGV[3])
+return {'ok', version}
+";
+
+/// Keeps the history of every entity in a Redis stream keyed
+/// `commits:{entity_id}`, one entry per commit with the commit JSON encoded
+/// in its `commit` field. Entity types sharing a Redis need a `prefix` each.
+/// Batches are committed one commit at a time.
+#[derive(Clone)]
+pub struct RedisStore<M, C = StringId> {
+    client: Client,
+    connection: Arc<Mutex<Option<MultiplexedConnection>>>,
+    append: Script,
+    prefix: String,
+    ids: C,
+    model: PhantomData<fn() -> M>,
+}
+
+impl<M> RedisStore<M> {
+    pub fn new(client: Client) -> Self {
+        RedisStore {
+            client,
+            connection: Arc::new(Mutex::new(None)),
+            append: Script::new(APPEND),
+            prefix: "commits".into(),
+            ids: StringId,
+            model: PhantomData,
+        }
+    }
+}
+
+impl<M, C> RedisStore<M, C> {
+    /// Keys the streams `{prefix}:{entity_id}` instead, the prefix is matched
+    /// as is when scanning so it shouldn't have glob characters.
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// Encodes the entity ids of the keys with the codec
+    pub fn id_codec<D>(self, ids: D) -> RedisStore<M, D> {
+        RedisStore {
+            client: self.client,
+            connection: self.connection,
+            append: self.append,
+            prefix: self.prefix,
+            ids,
+            model: PhantomData,
+        }
+    }
+
+    /// The connection shared by every operation, made the first time it's
+    /// needed and again after it's lost.
+    async fn connection(&self) -> CommitResult<MultiplexedConnection> {
+        let mut connection = self.connection.lock().await;
+        if let Some(connection) = &*connection {
+            return Ok(connection.clone());
+        }
+        let made = self
+            .client
+            .get_multiplexed_async_connection()
+            .await
+            .map_err(redis_error)?;
+        *connection = Some(made.clone());
+        Ok(made)
+    }
+
+    async fn failed(&self, e: RedisError) -> CommitError {
+        let e = redis_error(e);
+        if let CommitError::Unavailable = e {
+            self.connection.lock().await.take();
+        }
+        e
+    }
+
+    async fn query<T: FromRedisValue>(&self, cmd: &redis::Cmd) -> CommitResult<T> {
+        let mut connection = self.connection().await?;
+        match cmd.query_async(&mut connection).await {
+            Ok(reply) => Ok(reply),
+            Err(e) => Err(self.failed(e).await),
+        }
+    }
+
+    /// Keys of every stream of the prefix with the id of their first entry
+    async fn scan(&self) -> CommitResult<Vec<(Vec<u8>, String)>> {
+        let mut connection = self.connection().await?;
+        let mut keys = vec![];
+        let scanned = redis::cmd("SCAN")
+            .cursor_arg(0)
+            .arg("MATCH")
+            .arg(format!("{}:*", self.prefix))
+            .iter_async::<Vec<u8>>(&mut connection)
+            .await;
+        match scanned {
+            Ok(mut scanned) => {
+                while let Some(key) = scanned.next_item().await {
+                    keys.push(key);
+                }
+            }
+            Err(e) => return Err(self.failed(e).await),
+        }
+        let mut created = vec![];
+        for key in keys {
+            let first: StreamRangeReply = self
+                .query(
+                    redis::cmd("XRANGE")
+                        .arg(&key)
+                        .arg("-")
+                        .arg("+")
+                        .arg("COUNT")
+                        .arg(1),
+                )
+                .await?;
+            // purged while scanning
+            if let Some(first) = first.ids.into_iter().next() {
+                created.push((key, first.id));
+            }
+        }
+        Ok(created)
+    }
+}
+
+impl<M: SerializableModel, C: IdCodec<M::Id>> RedisStore<M, C> {
+    fn key(&self, id: &M::Id) -> Vec<u8> {
+        let mut key = format!("{}:", self.prefix).into_bytes();
+        key.extend(self.ids.encode_id(id));
+        key
+    }
+
+    async fn history(&self, id: M::Id) -> CommitResult<Vec<CommitResult<Commit<M>>>> {
+        let entries: StreamRangeReply = self
+            .query(redis::cmd("XRANGE").arg(self.key(&id)).arg("-").arg("+"))
+            .await?;
+        if entries.ids.is_empty() {
+            return Err(CommitError::NotFound);
+        }
+        Ok(entries
+            .ids
+            .into_iter()
+            .map(|entry| {
+                let commit = entry.get::<String>("commit").ok_or_else(|| {
+                    CommitError::Codec(format!("entry {} without commit", entry.id))
+                })?;
+                serde_json::from_str(&commit).map_err(|e| CommitError::Codec(e.to_string()))
+            })
+            .collect())
+    }
+}
+
+impl<M, C: fmt::Debug> fmt::Debug for RedisStore<M, C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RedisStore")
+            .field("client", &self.client)
+            .field("prefix", &self.prefix)
+            .field("ids", &self.ids)
+            .finish()
+    }
+}
+
+#[async_trait]
+impl<M, C> CommitStore<M> for RedisStore<M, C>
+where
+    M: SerializableModel,
+    C: IdCodec<M::Id>,
+{
+    fn keys(&self) -> BoxStream<CommitResult<M::Id>> {
+        let prefix = self.prefix.len() + 1;
+        stream::once(async move {
+            let mut created = self.scan().await?;
+            // stream ids tell when an entry was appended, in the order they
+            // were created pages of lists are stable
+            created.sort_by_key(|(_, first)| entry_order(first));
+            Ok(stream::iter(
+                created
+                    .into_iter()
+                    .map(move |(key, _)| self.ids.decode_id(&key[prefix..])),
+            ))
+        })
+        .try_flatten()
+        .boxed()
+    }
+
+    fn change_list(&self, id: M::Id) -> BoxStream<CommitResult<Commit<M>>> {
+        stream::once(self.history(id))
+            .map_ok(stream::iter)
+            .try_flatten()
+            .boxed()
+    }
+
+    async fn commit(&self, c: Commit<M>) -> CommitResult<()> {
+        let kind = match c.kind() {
+            EventKind::Create => "create",
+            EventKind::Change => "change",
+            EventKind::Delete => "delete",
+        };
+        let expected = c
+            .expected_version()
+            .map_or_else(String::new, |v| v.to_string());
+        let payload = serde_json::to_string(&c).map_err(|e| CommitError::Codec(e.to_string()))?;
+        let mut connection = self.connection().await?;
+        let appended = self
+            .append
+            .key(self.key(&c.entity_id()))
+            .arg(kind)
+            .arg(expected)
+            .arg(payload)
+            .invoke_async::<_, (String, u64)>(&mut connection)
+            .await;
+        match appended {
+            Ok((status, version)) => match status.as_str() {
+                "ok" => Ok(()),
+                "exists" => Err(CommitError::AlreadyExists),
+                "missing" => Err(CommitError::CantChange),
+                _ => Err(CommitError::Conflict {
+                    expected: c.expected_version().unwrap_or_default(),
+                    actual: version,
+                }),
+            },
+            Err(e) => Err(self.failed(e).await),
+        }
+    }
+
+    async fn ping(&self) -> CommitResult<()> {
+        self.query::<String>(&redis::cmd("PING"))
+            .await
+            .map(|_| ())
+            .map_err(|_| CommitError::Unavailable)
+    }
+
+    async fn purge(&self, id: M::Id) -> CommitResult<()> {
+        let purged: u64 = self.query(redis::cmd("DEL").arg(self.key(&id))).await?;
+        match purged {
+            0 => Err(CommitError::NotFound),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Stream entry ids are the millisecond they were appended at followed by a
+/// sequence number within it
+fn entry_order(id: &str) -> (u64, u64) {
+    let (millis, sequence) = id.split_once('-').unwrap_or((id, "0"));
+    (
+        millis.parse().unwrap_or_default(),
+        sequence.parse().unwrap_or_default(),
+    )
+}
+
+/// Connections that can't be made or were lost leave Redis unavailable,
+/// anything else is a failure of the command.
+fn redis_error(e: RedisError) -> CommitError {
+    if e.is_io_error() || e.is_connection_refusal() || e.is_connection_dropped() || e.is_timeout() {
+        CommitError::Unavailable
+    } else {
+        CommitError::Database(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::tests::{Op, TestCount};
+    use crate::store::UuidBytes;
+    use crate::{EntityId, Event, Model};
+    use chrono::Utc;
+    use futures::executor::block_on;
+    use uuid::Uuid;
+
+    /// A store with a prefix of its own in the Redis at `REDIS_URL`
+    fn store() -> RedisStore<TestCount, UuidBytes> {
+        let url = std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1/".into());
+        RedisStore::new(Client::open(url).unwrap())
+            .prefix(&format!("counts-{}", Uuid::new_v4()))
+            .id_codec(UuidBytes)
+    }
+
+    #[test]
+    fn append_commits_to_streams() {
+        let store = store();
+        let (first, second) = (TestCount::new(1), TestCount::new(10));
+        let (a, b) = (first.id(), second.id());
+        block_on(store.commit(Event::Create(first.clone()).into())).unwrap();
+        block_on(store.commit(Event::Create(second).into())).unwrap();
+        let changed = Commit::new(Event::Change(a, Op::Add(2)), Some("tester".into()), None);
+        block_on(store.commit(changed)).unwrap();
+        block_on(store.commit(Event::Change(b, Op::Sub(3)).into())).unwrap();
+
+        let keys: Vec<_> = block_on(store.keys().try_collect()).unwrap();
+        assert_eq!(keys, vec![a, b]);
+        let history: Vec<_> = block_on(store.change_list(a).try_collect()).unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[1].who(), Some("tester"));
+        assert_eq!(block_on(store.snapshot(a, Utc::now())).unwrap().count, 3);
+        assert_eq!(block_on(store.snapshot(b, Utc::now())).unwrap().count, 7);
+
+        let res = block_on(store.commit(Event::Create(first).into()));
+        assert!(matches!(res, Err(CommitError::AlreadyExists)));
+        let missing: EntityId = Uuid::new_v4().into();
+        let res = block_on(store.commit(Event::Change(missing, Op::Add(1)).into()));
+        assert!(matches!(res, Err(CommitError::CantChange)));
+        let mut stale: Commit<TestCount> = Event::Change(a, Op::Add(1)).into();
+        stale.set_expected_version(Some(1));
+        let res = block_on(store.commit(stale));
+        assert!(matches!(
+            res,
+            Err(CommitError::Conflict {
+                expected: 1,
+                actual: 2
+            })
+        ));
+        block_on(store.commit(Event::Delete(b).into())).unwrap();
+        let res = block_on(store.commit(Event::Change(b, Op::Add(1)).into()));
+        assert!(matches!(res, Err(CommitError::CantChange)));
+
+        block_on(store.purge(b)).unwrap();
+        let keys: Vec<_> = block_on(store.keys().try_collect()).unwrap();
+        assert_eq!(keys, vec![a]);
+        assert!(matches!(block_on(store.get(b)), Err(CommitError::NotFound)));
+        block_on(store.purge(a)).unwrap();
+    }
+
+    #[test]
+    fn report_unreachable_redis() {
+        // nothing listens on the discard port
+        let store: RedisStore<TestCount> =
+            RedisStore::new(Client::open("redis://127.0.0.1:9/").unwrap());
+        assert!(matches!(
+            block_on(store.ping()),
+            Err(CommitError::Unavailable)
+        ));
+        let res = block_on(store.commit(Event::Create(TestCount::new(1)).into()));
+        assert!(matches!(res, Err(CommitError::Unavailable)));
+    }
+}
@@ -0,0 +1,175 @@
+use super::{Commit, CommitResult, CommitStore, Snapshot};
+use crate::Model;
+use async_trait::async_trait;
+use chrono::{prelude::*, Duration};
+use futures::stream::BoxStream;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// A store that sends commits to a primary backend and reads from a replica.
+/// Since replicas lag behind, entities changed more recently than the
+/// configured max staleness are read from the primary to stay consistent.
+#[derive(Debug)]
+pub struct ReplicatedStore<P, R> {
+    primary: P,
+    replica: R,
+    max_staleness: Duration,
+    // keyed by the string form of the entity id to not depend on its type
+    last_writes: Arc<Mutex<HashMap<String, DateTime<Utc>>>>,
+}
+
+impl<P, R> ReplicatedStore<P, R> {
+    pub fn new(primary: P, replica: R) -> Self {
+        ReplicatedStore {
+            primary,
+            replica,
+            max_staleness: Duration::seconds(1),
+            last_writes: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// How long it can take for a commit to show up in the replica
+    pub fn with_max_staleness(mut self, max_staleness: Duration) -> Self {
+        self.max_staleness = max_staleness;
+        self
+    }
+
+    fn is_fresh(&self, written: &DateTime<Utc>) -> bool {
+        Utc::now() - *written < self.max_staleness
+    }
+
+    fn recently_written(&self, id: Option<String>) -> bool {
+        let writes = self.last_writes.lock().unwrap();
+        match id {
+            Some(id) => matches!(writes.get(&id), Some(w) if self.is_fresh(w)),
+            None => writes.values().any(|w| self.is_fresh(w)),
+        }
+    }
+}
+
+#[async_trait]
+impl<M, P, R> CommitStore<M> for ReplicatedStore<P, R>
+where
+    M: Model,
+    P: CommitStore<M>,
+    R: CommitStore<M>,
+{
+    fn keys(&self) -> BoxStream<CommitResult<M::Id>> {
+        if self.recently_written(None) {
+            self.primary.keys()
+        } else {
+            self.replica.keys()
+        }
+    }
+
+    fn change_list(&self, id: M::Id) -> BoxStream<CommitResult<Commit<M>>> {
+        if self.recently_written(Some(id.to_string())) {
+            self.primary.change_list(id)
+        } else {
+            self.replica.change_list(id)
+        }
+    }
+
+    async fn commit(&self, c: Commit<M>) -> CommitResult<()> {
+        let id = c.entity_id().to_string();
+        self.primary.commit(c).await?;
+        self.last_writes.lock().unwrap().insert(id, Utc::now());
+        Ok(())
+    }
+
+    async fn import_entity(
+        &self,
+        id: M::Id,
+        history: Vec<Commit<M>>,
+        force: bool,
+    ) -> CommitResult<()> {
+        let key = id.to_string();
+        self.primary.import_entity(id, history, force).await?;
+        self.last_writes.lock().unwrap().insert(key, Utc::now());
+        Ok(())
+    }
+
+    async fn commit_all(&self, commits: Vec<Commit<M>>) -> CommitResult<()> {
+        let keys: Vec<_> = commits.iter().map(|c| c.entity_id().to_string()).collect();
+        self.primary.commit_all(commits).await?;
+        let mut last_writes = self.last_writes.lock().unwrap();
+        for key in keys {
+            last_writes.insert(key, Utc::now());
+        }
+        Ok(())
+    }
+
+    async fn purge(&self, id: M::Id) -> CommitResult<()> {
+        self.primary.purge(id).await
+    }
+
+    async fn tag(&self, name: String, at: DateTime<Utc>) -> CommitResult<()> {
+        self.primary.tag(name, at).await
+    }
+
+    async fn resolve_tag(&self, name: &str) -> CommitResult<DateTime<Utc>> {
+        self.primary.resolve_tag(name).await
+    }
+
+    async fn count_at(&self, time: DateTime<Utc>) -> CommitResult<usize> {
+        self.primary.count_at(time).await
+    }
+
+    async fn ping(&self) -> CommitResult<()> {
+        self.primary.ping().await
+    }
+
+    async fn checkpoint(&self, at: DateTime<Utc>) -> CommitResult<()> {
+        self.primary.checkpoint(at).await
+    }
+
+    async fn save_snapshot(&self, snapshot: Snapshot<M>) -> CommitResult<()> {
+        self.primary.save_snapshot(snapshot).await
+    }
+
+    async fn load_snapshot(&self, id: M::Id) -> CommitResult<Option<Snapshot<M>>> {
+        if self.recently_written(Some(id.to_string())) {
+            self.primary.load_snapshot(id).await
+        } else {
+            self.replica.load_snapshot(id).await
+        }
+    }
+}
+
+impl<P: Clone, R: Clone> Clone for ReplicatedStore<P, R> {
+    fn clone(&self) -> Self {
+        ReplicatedStore {
+            primary: self.primary.clone(),
+            replica: self.replica.clone(),
+            max_staleness: self.max_staleness,
+            last_writes: self.last_writes.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::tests::TestCount;
+    use crate::{CommitError, Event, MemStore};
+    use futures::executor::block_on;
+
+    #[test]
+    fn writes_to_primary_reads_from_replica() {
+        let primary = MemStore::new();
+        let replica = MemStore::new();
+        let store = ReplicatedStore::new(primary.clone(), replica.clone());
+
+        let count = TestCount::new(42);
+        let id = count.id();
+        block_on(store.commit(Event::Create(count).into())).unwrap();
+        assert!(block_on(primary.get(id)).is_ok());
+        assert!(block_on(replica.get(id)).is_err());
+        // recently written entities are read from the primary
+        assert!(block_on(store.get(id)).is_ok());
+
+        let store = store.with_max_staleness(Duration::zero());
+        let res = block_on(store.get(id));
+        assert!(matches!(res, Err(CommitError::NotFound)));
+    }
+}
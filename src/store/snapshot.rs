@@ -0,0 +1,260 @@
+use super::{Commit, CommitError, CommitResult, CommitStore, TimeTraveler};
+use crate::Model;
+use async_trait::async_trait;
+use chrono::prelude::*;
+use futures::lock::Mutex;
+use futures::stream::{BoxStream, TryStreamExt};
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// Encodes values to bytes before persisting them
+pub trait Codec: fmt::Debug + Clone + Send + Sync + 'static {
+    fn encode<T: Serialize>(&self, value: &T) -> CommitResult<Vec<u8>>;
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> CommitResult<T>;
+}
+
+/// Human readable encoding, handy for debugging
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Json;
+
+impl Codec for Json {
+    fn encode<T: Serialize>(&self, value: &T) -> CommitResult<Vec<u8>> {
+        serde_json::to_vec(value).map_err(|e| CommitError::Codec(e.to_string()))
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> CommitResult<T> {
+        serde_json::from_slice(bytes).map_err(|e| CommitError::Codec(e.to_string()))
+    }
+}
+
+/// Compact binary encoding, values have to serialize as documents(structs or maps)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Bson;
+
+impl Codec for Bson {
+    fn encode<T: Serialize>(&self, value: &T) -> CommitResult<Vec<u8>> {
+        let doc = bson::to_document(value).map_err(|e| CommitError::Codec(e.to_string()))?;
+        let mut bytes = vec![];
+        doc.to_writer(&mut bytes)
+            .map_err(|e| CommitError::Codec(e.to_string()))?;
+        Ok(bytes)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, mut bytes: &[u8]) -> CommitResult<T> {
+        let doc = bson::Document::from_reader(&mut bytes)
+            .map_err(|e| CommitError::Codec(e.to_string()))?;
+        bson::from_document(doc).map_err(|e| CommitError::Codec(e.to_string()))
+    }
+}
+
+/// The state of an entity after applying its first `version` commits
+#[derive(Debug, Clone)]
+pub struct Snapshot<M> {
+    pub model: M,
+    pub version: u64,
+    pub when: DateTime<Utc>,
+}
+
+/// Persists the latest snapshot of entities to avoid replaying their whole history
+#[async_trait]
+pub trait SnapshotStore<M: Model>: fmt::Debug + Clone + Send + Sync + 'static {
+    async fn save(&self, snapshot: Snapshot<M>) -> CommitResult<()>;
+
+    async fn load(&self, id: M::Id) -> CommitResult<Snapshot<M>>;
+}
+
+/// Encoded snapshots by entity along with their version and time
+type EncodedSnapshots<Id> = Arc<Mutex<HashMap<Id, (u64, DateTime<Utc>, Vec<u8>)>>>;
+
+/// Keeps encoded snapshots in memory
+#[derive(Debug)]
+pub struct MemSnapshotStore<M: Model, C> {
+    codec: C,
+    snapshots: EncodedSnapshots<M::Id>,
+}
+
+impl<M: Model, C: Codec> MemSnapshotStore<M, C> {
+    pub fn new(codec: C) -> Self {
+        MemSnapshotStore {
+            codec,
+            snapshots: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+}
+
+#[async_trait]
+impl<M, C> SnapshotStore<M> for MemSnapshotStore<M, C>
+where
+    M: Model + Serialize + DeserializeOwned,
+    C: Codec,
+{
+    async fn save(&self, snapshot: Snapshot<M>) -> CommitResult<()> {
+        let bytes = self.codec.encode(&snapshot.model)?;
+        self.snapshots.lock().await.insert(
+            snapshot.model.id(),
+            (snapshot.version, snapshot.when, bytes),
+        );
+        Ok(())
+    }
+
+    async fn load(&self, id: M::Id) -> CommitResult<Snapshot<M>> {
+        let snapshots = self.snapshots.lock().await;
+        let (version, when, bytes) = snapshots.get(&id).ok_or(CommitError::NotFound)?;
+        Ok(Snapshot {
+            model: self.codec.decode(bytes)?,
+            version: *version,
+            when: *when,
+        })
+    }
+}
+
+impl<M: Model, C: Clone> Clone for MemSnapshotStore<M, C> {
+    fn clone(&self) -> Self {
+        MemSnapshotStore {
+            codec: self.codec.clone(),
+            snapshots: self.snapshots.clone(),
+        }
+    }
+}
+
+/// A store that saves a snapshot of every entity to the snapshot store
+/// on each checkpoint.
+#[derive(Debug, Clone)]
+pub struct SnapshottingStore<S, SS> {
+    backend: S,
+    snapshots: SS,
+}
+
+impl<S, SS> SnapshottingStore<S, SS> {
+    pub fn new(backend: S, snapshots: SS) -> Self {
+        SnapshottingStore { backend, snapshots }
+    }
+}
+
+#[async_trait]
+impl<M, S, SS> CommitStore<M> for SnapshottingStore<S, SS>
+where
+    M: Model,
+    S: CommitStore<M>,
+    SS: SnapshotStore<M>,
+{
+    fn keys(&self) -> BoxStream<CommitResult<M::Id>> {
+        self.backend.keys()
+    }
+
+    fn change_list(&self, id: M::Id) -> BoxStream<CommitResult<Commit<M>>> {
+        self.backend.change_list(id)
+    }
+
+    async fn commit(&self, c: Commit<M>) -> CommitResult<()> {
+        self.backend.commit(c).await
+    }
+
+    async fn import_entity(
+        &self,
+        id: M::Id,
+        history: Vec<Commit<M>>,
+        force: bool,
+    ) -> CommitResult<()> {
+        self.backend.import_entity(id, history, force).await
+    }
+
+    async fn commit_all(&self, commits: Vec<Commit<M>>) -> CommitResult<()> {
+        self.backend.commit_all(commits).await
+    }
+
+    async fn purge(&self, id: M::Id) -> CommitResult<()> {
+        self.backend.purge(id).await
+    }
+
+    async fn tag(&self, name: String, at: DateTime<Utc>) -> CommitResult<()> {
+        self.backend.tag(name, at).await
+    }
+
+    async fn resolve_tag(&self, name: &str) -> CommitResult<DateTime<Utc>> {
+        self.backend.resolve_tag(name).await
+    }
+
+    async fn count_at(&self, time: DateTime<Utc>) -> CommitResult<usize> {
+        self.backend.count_at(time).await
+    }
+
+    async fn ping(&self) -> CommitResult<()> {
+        self.backend.ping().await
+    }
+
+    async fn save_snapshot(&self, snapshot: Snapshot<M>) -> CommitResult<()> {
+        self.backend.save_snapshot(snapshot).await
+    }
+
+    async fn load_snapshot(&self, id: M::Id) -> CommitResult<Option<Snapshot<M>>> {
+        self.backend.load_snapshot(id).await
+    }
+
+    async fn checkpoint(&self, at: DateTime<Utc>) -> CommitResult<()> {
+        self.backend.checkpoint(at).await?;
+        let mut keys = self.backend.keys();
+        while let Some(id) = keys.try_next().await? {
+            let commits = self
+                .backend
+                .change_list(id)
+                .try_take_while(|c| futures::future::ok(c.when() <= at))
+                .try_collect::<Vec<_>>()
+                .await?;
+            let first = match commits.first().and_then(|c| c.entity()) {
+                Some(model) => model,
+                // created after the checkpoint
+                None => continue,
+            };
+            let version = commits.len() as u64;
+            let changes = futures::stream::iter(commits.into_iter().skip(1).map(Ok));
+            let model = TimeTraveler::new(first, Box::pin(changes))
+                .to_present()
+                .await?;
+            self.snapshots
+                .save(Snapshot {
+                    model,
+                    version,
+                    when: at,
+                })
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::tests::TestCount;
+    use futures::executor::block_on;
+
+    fn round_trip<C: Codec>(codec: C) {
+        let store = MemSnapshotStore::new(codec);
+        let count = TestCount::new(42);
+        let id = count.id();
+        let snapshot = Snapshot {
+            model: count,
+            version: 3,
+            when: Utc::now(),
+        };
+        block_on(store.save(snapshot)).unwrap();
+
+        let loaded = block_on(store.load(id)).unwrap();
+        assert_eq!(loaded.model.id(), id);
+        assert_eq!(loaded.model.count, 42);
+        assert_eq!(loaded.version, 3);
+    }
+
+    #[test]
+    fn json_snapshot() {
+        round_trip(Json);
+    }
+
+    #[test]
+    fn bson_snapshot() {
+        round_trip(Bson);
+    }
+}
@@ -0,0 +1,38 @@
+use super::{Commit, CommitError, CommitResult};
+use crate::SerializableModel;
+
+/// Table names are part of the queries so only plain identifiers are taken
+pub(crate) fn check_table(table: &str) -> CommitResult<()> {
+    let mut chars = table.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(CommitError::Database(format!(
+            "invalid table name {:?}",
+            table
+        )))
+    }
+}
+
+/// Connections that can't be made or were lost leave the database
+/// unavailable, anything else is a failure of the query.
+pub(crate) fn db_error(e: sqlx::Error) -> CommitError {
+    match e {
+        sqlx::Error::Io(_) | sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed => {
+            CommitError::Unavailable
+        }
+        e => CommitError::Database(e.to_string()),
+    }
+}
+
+pub(crate) fn encode<M: SerializableModel>(c: &Commit<M>) -> CommitResult<String> {
+    serde_json::to_string(c).map_err(|e| CommitError::Codec(e.to_string()))
+}
+
+pub(crate) fn decode<M: SerializableModel>(payload: &str) -> CommitResult<Commit<M>> {
+    serde_json::from_str(payload).map_err(|e| CommitError::Codec(e.to_string()))
+}
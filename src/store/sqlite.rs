@@ -0,0 +1,309 @@
+use super::sql::{check_table, db_error, decode, encode};
+use super::{check_append, Commit, CommitError, CommitResult, CommitStore, IdCodec, StringId};
+use crate::{EventKind, SerializableModel};
+use async_trait::async_trait;
+use futures::future::ready;
+use futures::lock::Mutex;
+use futures::stream::{self, BoxStream, StreamExt, TryStreamExt};
+use sqlx::sqlite::{SqliteConnectOptions, SqliteConnection, SqlitePool, SqlitePoolOptions};
+use sqlx::Row;
+use std::marker::PhantomData;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Keeps the commits of every entity in a table of a SQLite database, one
+/// row per commit in the order they were committed with the whole commit
+/// JSON encoded in `payload`. Made for embedded and single node deployments,
+/// the store appends commits one at a time.
+#[derive(Debug, Clone)]
+pub struct SqliteStore<M, C = StringId> {
+    pool: SqlitePool,
+    queries: Arc<Queries>,
+    // appends to the table one at a time
+    writing: Arc<Mutex<()>>,
+    ids: C,
+    model: PhantomData<fn() -> M>,
+}
+
+#[derive(Debug)]
+struct Queries {
+    keys: String,
+    history: String,
+    last: String,
+    insert: String,
+    purge: String,
+}
+
+impl Queries {
+    fn new(table: &str) -> Self {
+        Queries {
+            // every entity has one creation, listed in the order they were created
+            keys: format!(
+                "SELECT entity_id FROM {} WHERE sequence = 0 ORDER BY position",
+                table
+            ),
+            history: format!(
+                "SELECT payload FROM {} WHERE entity_id = ? ORDER BY sequence",
+                table
+            ),
+            last: format!(
+                "SELECT sequence, payload FROM {} WHERE entity_id = ? ORDER BY sequence DESC LIMIT 1",
+                table
+            ),
+            insert: format!(
+                "INSERT INTO {} (entity_id, sequence, time, author, reason, payload) VALUES (?, ?, ?, ?, ?, ?)",
+                table
+            ),
+            purge: format!("DELETE FROM {} WHERE entity_id = ?", table),
+        }
+    }
+}
+
+impl<M> SqliteStore<M> {
+    /// Keeps the commits in the database file, creating it if needed
+    pub async fn open<P: AsRef<Path>>(path: P) -> CommitResult<Self> {
+        let options = SqliteConnectOptions::new()
+            .filename(path)
+            .create_if_missing(true);
+        let pool = SqlitePool::connect_with(options).await.map_err(db_error)?;
+        SqliteStore::with_pool(pool, "commits", StringId).await
+    }
+
+    /// A database that lives as long as the store, handy for tests
+    pub async fn in_memory() -> CommitResult<Self> {
+        // every connection to memory opens a database of its own so the one
+        // connection of the pool is kept open
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect("sqlite::memory:")
+            .await
+            .map_err(db_error)?;
+        SqliteStore::with_pool(pool, "commits", StringId).await
+    }
+}
+
+impl<M, C> SqliteStore<M, C> {
+    /// Keeps the commits in the table of the database, creating it if needed,
+    /// with the entity ids stored the way the codec encodes them.
+    pub async fn with_pool(pool: SqlitePool, table: &str, ids: C) -> CommitResult<Self> {
+        check_table(table)?;
+        let schema = format!(
+            "CREATE TABLE IF NOT EXISTS {} (
+                position INTEGER PRIMARY KEY AUTOINCREMENT,
+                entity_id BLOB NOT NULL,
+                sequence INTEGER NOT NULL,
+                time TEXT NOT NULL,
+                author TEXT,
+                reason TEXT,
+                payload TEXT NOT NULL,
+                UNIQUE (entity_id, sequence)
+            )",
+            table
+        );
+        sqlx::query(&schema)
+            .execute(&pool)
+            .await
+            .map_err(db_error)?;
+        Ok(SqliteStore {
+            pool,
+            queries: Arc::new(Queries::new(table)),
+            writing: Arc::new(Mutex::new(())),
+            ids,
+            model: PhantomData,
+        })
+    }
+}
+
+impl<M: SerializableModel, C: IdCodec<M::Id>> SqliteStore<M, C> {
+    /// Appends the commit to the history of its entity within the
+    /// transaction the connection is in.
+    async fn append(&self, conn: &mut SqliteConnection, c: &Commit<M>) -> CommitResult<()> {
+        let id = self.ids.encode_id(&c.entity_id());
+        let last = sqlx::query(&self.queries.last)
+            .bind(id.clone())
+            .fetch_optional(&mut *conn)
+            .await
+            .map_err(db_error)?;
+        let (version, deleted) = match last {
+            Some(row) => {
+                let sequence: i64 = row.try_get("sequence").map_err(db_error)?;
+                let payload: String = row.try_get("payload").map_err(db_error)?;
+                let last = decode::<M>(&payload)?;
+                (sequence as u64 + 1, last.kind() == EventKind::Delete)
+            }
+            None => (0, false),
+        };
+        check_append(c, version, deleted)?;
+        sqlx::query(&self.queries.insert)
+            .bind(id)
+            .bind(version as i64)
+            .bind(c.when())
+            .bind(c.who())
+            .bind(c.why())
+            .bind(encode(c)?)
+            .execute(&mut *conn)
+            .await
+            .map_err(db_error)?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<M, C> CommitStore<M> for SqliteStore<M, C>
+where
+    M: SerializableModel,
+    C: IdCodec<M::Id>,
+{
+    fn keys(&self) -> BoxStream<CommitResult<M::Id>> {
+        sqlx::query(&self.queries.keys)
+            .fetch(&self.pool)
+            .map_err(db_error)
+            .and_then(move |row| {
+                ready(
+                    row.try_get::<Vec<u8>, _>("entity_id")
+                        .map_err(db_error)
+                        .and_then(|id| self.ids.decode_id(&id)),
+                )
+            })
+            .boxed()
+    }
+
+    fn change_list(&self, id: M::Id) -> BoxStream<CommitResult<Commit<M>>> {
+        let mut commits = sqlx::query(&self.queries.history)
+            .bind(self.ids.encode_id(&id))
+            .fetch(&self.pool)
+            .map_err(db_error)
+            .and_then(|row| {
+                ready(
+                    row.try_get::<String, _>("payload")
+                        .map_err(db_error)
+                        .and_then(|payload| decode(&payload)),
+                )
+            });
+        stream::once(async move {
+            match commits.try_next().await {
+                Ok(Some(first)) => stream::once(ready(Ok(first))).chain(commits).boxed(),
+                Ok(None) => stream::once(ready(Err(CommitError::NotFound))).boxed(),
+                Err(e) => stream::once(ready(Err(e))).boxed(),
+            }
+        })
+        .flatten()
+        .boxed()
+    }
+
+    async fn commit(&self, c: Commit<M>) -> CommitResult<()> {
+        self.commit_all(vec![c]).await
+    }
+
+    /// Commits the whole batch in one transaction
+    async fn commit_all(&self, commits: Vec<Commit<M>>) -> CommitResult<()> {
+        let _writing = self.writing.lock().await;
+        let mut tx = self.pool.begin().await.map_err(db_error)?;
+        for c in &commits {
+            self.append(&mut tx, c).await?;
+        }
+        tx.commit().await.map_err(db_error)
+    }
+
+    async fn purge(&self, id: M::Id) -> CommitResult<()> {
+        let _writing = self.writing.lock().await;
+        let purged = sqlx::query(&self.queries.purge)
+            .bind(self.ids.encode_id(&id))
+            .execute(&self.pool)
+            .await
+            .map_err(db_error)?;
+        match purged.rows_affected() {
+            0 => Err(CommitError::NotFound),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::tests::{Op, TestCount};
+    use crate::store::UuidBytes;
+    use crate::{Event, Model};
+    use chrono::Utc;
+    use futures::executor::block_on;
+    use uuid::Uuid;
+
+    #[test]
+    fn persist_commits_in_table() {
+        let store = block_on(SqliteStore::<TestCount>::in_memory()).unwrap();
+        let (first, second) = (TestCount::new(1), TestCount::new(10));
+        let (a, b) = (first.id(), second.id());
+        block_on(store.commit(Event::Create(first.clone()).into())).unwrap();
+        block_on(store.commit_all(vec![
+            Event::Create(second).into(),
+            Event::Change(b, Op::Sub(3)).into(),
+        ]))
+        .unwrap();
+        let changed = Commit::new(Event::Change(a, Op::Add(2)), Some("tester".into()), None);
+        block_on(store.commit(changed)).unwrap();
+
+        let keys: Vec<_> = block_on(store.keys().try_collect()).unwrap();
+        assert_eq!(keys, vec![a, b]);
+        let history: Vec<_> = block_on(store.change_list(a).try_collect()).unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[1].who(), Some("tester"));
+        assert_eq!(block_on(store.snapshot(a, Utc::now())).unwrap().count, 3);
+        assert_eq!(block_on(store.snapshot(b, Utc::now())).unwrap().count, 7);
+
+        let res = block_on(store.commit(Event::Create(first).into()));
+        assert!(matches!(res, Err(CommitError::AlreadyExists)));
+        let mut stale: Commit<TestCount> = Event::Change(a, Op::Add(1)).into();
+        stale.set_expected_version(Some(1));
+        let res = block_on(store.commit(stale));
+        assert!(matches!(
+            res,
+            Err(CommitError::Conflict {
+                expected: 1,
+                actual: 2
+            })
+        ));
+        // a failing commit leaves the rest of its batch out
+        let res = block_on(store.commit_all(vec![
+            Event::Change(a, Op::Add(1)).into(),
+            Event::Change(Uuid::new_v4().into(), Op::Add(1)).into(),
+        ]));
+        assert!(matches!(res, Err(CommitError::CantChange)));
+        assert_eq!(block_on(store.snapshot(a, Utc::now())).unwrap().count, 3);
+
+        block_on(store.purge(b)).unwrap();
+        let keys: Vec<_> = block_on(store.keys().try_collect()).unwrap();
+        assert_eq!(keys, vec![a]);
+        assert!(matches!(block_on(store.get(b)), Err(CommitError::NotFound)));
+    }
+
+    #[test]
+    fn reopen_database_file() {
+        let path = std::env::temp_dir().join(format!("commits-{}.db", Uuid::new_v4()));
+        let count = TestCount::new(1);
+        let id = count.id();
+        {
+            let store = block_on(SqliteStore::<TestCount>::open(&path)).unwrap();
+            block_on(store.commit(Event::Create(count).into())).unwrap();
+            block_on(store.commit(Event::Change(id, Op::Add(2)).into())).unwrap();
+        }
+
+        let store = block_on(SqliteStore::<TestCount>::open(&path)).unwrap();
+        assert_eq!(block_on(store.snapshot(id, Utc::now())).unwrap().count, 3);
+        // entity types can share the file with a table each
+        let pool = block_on(SqlitePool::connect_with(
+            SqliteConnectOptions::new().filename(&path),
+        ))
+        .unwrap();
+        let others = block_on(SqliteStore::<TestCount, _>::with_pool(
+            pool, "others", UuidBytes,
+        ))
+        .unwrap();
+        assert!(block_on(others.keys().try_collect::<Vec<_>>())
+            .unwrap()
+            .is_empty());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,214 @@
+use super::{Commit, CommitResult, CommitStore, Snapshot, TimeTraveler};
+use crate::Model;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::stream::{BoxStream, StreamExt};
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Operations of a backend whose latency is reported
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum Operation {
+    Keys,
+    ChangeList,
+    Commit,
+    Get,
+    Import,
+    Purge,
+    Checkpoint,
+    Tag,
+    Count,
+    Ping,
+}
+
+/// Receives how long every operation on the backend took, streams are
+/// measured until they are exhausted or dropped.
+pub trait LatencyObserver: Send + Sync + 'static {
+    fn observe(&self, op: Operation, took: Duration);
+}
+
+impl<F> LatencyObserver for F
+where
+    F: Fn(Operation, Duration) + Send + Sync + 'static,
+{
+    fn observe(&self, op: Operation, took: Duration) {
+        self(op, took)
+    }
+}
+
+/// A store that measures the time the wrapped backend takes to do its work
+#[derive(Clone)]
+pub struct TimedStore<S> {
+    backend: S,
+    observer: Arc<dyn LatencyObserver>,
+}
+
+impl<S> TimedStore<S> {
+    pub fn new<O: LatencyObserver>(backend: S, observer: O) -> Self {
+        TimedStore {
+            backend,
+            observer: Arc::new(observer),
+        }
+    }
+
+    fn timer(&self, op: Operation) -> Timer {
+        Timer {
+            op,
+            start: Instant::now(),
+            observer: self.observer.clone(),
+        }
+    }
+}
+
+/// Reports the time elapsed since it started once it's dropped
+struct Timer {
+    op: Operation,
+    start: Instant,
+    observer: Arc<dyn LatencyObserver>,
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        self.observer.observe(self.op, self.start.elapsed());
+    }
+}
+
+#[async_trait]
+impl<M: Model, S: CommitStore<M>> CommitStore<M> for TimedStore<S> {
+    fn keys(&self) -> BoxStream<CommitResult<M::Id>> {
+        let timer = self.timer(Operation::Keys);
+        self.backend
+            .keys()
+            .map(move |k| {
+                let _ = &timer;
+                k
+            })
+            .boxed()
+    }
+
+    fn change_list(&self, id: M::Id) -> BoxStream<CommitResult<Commit<M>>> {
+        let timer = self.timer(Operation::ChangeList);
+        self.backend
+            .change_list(id)
+            .map(move |c| {
+                let _ = &timer;
+                c
+            })
+            .boxed()
+    }
+
+    async fn commit(&self, c: Commit<M>) -> CommitResult<()> {
+        let _timer = self.timer(Operation::Commit);
+        self.backend.commit(c).await
+    }
+
+    async fn import_entity(
+        &self,
+        id: M::Id,
+        history: Vec<Commit<M>>,
+        force: bool,
+    ) -> CommitResult<()> {
+        let _timer = self.timer(Operation::Import);
+        self.backend.import_entity(id, history, force).await
+    }
+
+    async fn commit_all(&self, commits: Vec<Commit<M>>) -> CommitResult<()> {
+        let _timer = self.timer(Operation::Commit);
+        self.backend.commit_all(commits).await
+    }
+
+    async fn purge(&self, id: M::Id) -> CommitResult<()> {
+        let _timer = self.timer(Operation::Purge);
+        self.backend.purge(id).await
+    }
+
+    async fn tag(&self, name: String, at: DateTime<Utc>) -> CommitResult<()> {
+        let _timer = self.timer(Operation::Tag);
+        self.backend.tag(name, at).await
+    }
+
+    async fn resolve_tag(&self, name: &str) -> CommitResult<DateTime<Utc>> {
+        let _timer = self.timer(Operation::Tag);
+        self.backend.resolve_tag(name).await
+    }
+
+    async fn count_at(&self, time: DateTime<Utc>) -> CommitResult<usize> {
+        let _timer = self.timer(Operation::Count);
+        self.backend.count_at(time).await
+    }
+
+    async fn ping(&self) -> CommitResult<()> {
+        let _timer = self.timer(Operation::Ping);
+        self.backend.ping().await
+    }
+
+    async fn checkpoint(&self, at: DateTime<Utc>) -> CommitResult<()> {
+        let _timer = self.timer(Operation::Checkpoint);
+        self.backend.checkpoint(at).await
+    }
+
+    async fn get(&self, id: M::Id) -> CommitResult<TimeTraveler<'_, M>> {
+        let _timer = self.timer(Operation::Get);
+        self.backend.get(id).await
+    }
+
+    async fn save_snapshot(&self, snapshot: Snapshot<M>) -> CommitResult<()> {
+        let _timer = self.timer(Operation::Commit);
+        self.backend.save_snapshot(snapshot).await
+    }
+
+    async fn load_snapshot(&self, id: M::Id) -> CommitResult<Option<Snapshot<M>>> {
+        let _timer = self.timer(Operation::Get);
+        self.backend.load_snapshot(id).await
+    }
+}
+
+impl<S: fmt::Debug> fmt::Debug for TimedStore<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "TimedStore({:?})", self.backend)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::tests::{Op, TestCount};
+    use crate::{Event, MemStore};
+    use futures::executor::block_on;
+    use futures::stream::TryStreamExt;
+    use std::sync::Mutex;
+
+    #[test]
+    fn observe_latency_of_operations() {
+        let timings = Arc::new(Mutex::new(vec![]));
+        let observed = timings.clone();
+        let store = TimedStore::new(MemStore::new(), move |op, took| {
+            observed.lock().unwrap().push((op, took))
+        });
+
+        let start = Instant::now();
+        let count = TestCount::new(1);
+        let id = count.id();
+        block_on(store.commit(Event::Create(count).into())).unwrap();
+        block_on(store.commit(Event::Change(id, Op::Add(1)).into())).unwrap();
+        let _: Vec<_> = block_on(store.change_list(id).try_collect()).unwrap();
+        block_on(store.get(id)).unwrap();
+        let _: Vec<_> = block_on(store.keys().try_collect()).unwrap();
+        let elapsed = start.elapsed();
+
+        let timings = timings.lock().unwrap();
+        let ops: Vec<_> = timings.iter().map(|(op, _)| *op).collect();
+        assert_eq!(
+            ops,
+            vec![
+                Operation::Commit,
+                Operation::Commit,
+                Operation::ChangeList,
+                Operation::Get,
+                Operation::Keys
+            ]
+        );
+        assert!(timings.iter().all(|(_, took)| *took <= elapsed));
+    }
+}
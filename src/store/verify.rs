@@ -0,0 +1,117 @@
+use super::Commit;
+use crate::{Event, Model};
+use std::collections::HashSet;
+
+/// Problems found in the history of an entity, each one listing the
+/// positions of the offending commits, the creation being the commit 0.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+    /// The history is empty or doesn't start with the creation of the entity
+    pub not_created: bool,
+    /// Creations after the first commit
+    pub creations: Vec<u64>,
+    /// Commits of other entities
+    pub foreign: Vec<u64>,
+    /// Commits made before the commit that precedes them
+    pub out_of_order: Vec<u64>,
+    /// Commits whose idempotency key was already used by a previous commit
+    pub duplicates: Vec<u64>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        *self == VerifyReport::default()
+    }
+}
+
+pub(crate) fn verify_history<M: Model>(id: &M::Id, history: &[Commit<M>]) -> VerifyReport {
+    let mut report = VerifyReport {
+        not_created: !matches!(history.first().map(|c| &**c), Some(Event::Create(_))),
+        ..Default::default()
+    };
+    let mut keys = HashSet::new();
+    for (n, c) in history.iter().enumerate() {
+        let seq = n as u64;
+        if n > 0 && matches!(&**c, Event::Create(_)) {
+            report.creations.push(seq);
+        }
+        if c.entity_id() != *id {
+            report.foreign.push(seq);
+        }
+        if n > 0 && c.when() < history[n - 1].when() {
+            report.out_of_order.push(seq);
+        }
+        if let Some(key) = c.idempotency_key() {
+            if !keys.insert(key) {
+                report.duplicates.push(seq);
+            }
+        }
+    }
+    report
+}
+
+/// Makes timestamps follow the order of the commits, a commit made before
+/// the one that precedes it takes the timestamp of its predecessor.
+pub(crate) fn reorder_timestamps<M: Model>(history: &mut [Commit<M>]) {
+    for n in 1..history.len() {
+        let previous = history[n - 1].when();
+        if history[n].when() < previous {
+            history[n].set_when(previous);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::tests::{Op, TestCount};
+    use crate::{CommitChain, CommitError, CommitStore, MemStore};
+    use chrono::{TimeZone, Utc};
+    use futures::executor::block_on;
+    use futures::stream::TryStreamExt;
+
+    #[test]
+    fn report_and_repair_corrupt_history() {
+        let t = |h| Utc.with_ymd_and_hms(2020, 1, 1, h, 0, 0).unwrap();
+        let count = TestCount::new(1);
+        let id = count.id();
+        let mut history = CommitChain::create(count)
+            .at(t(1))
+            .change(Op::Add(1))
+            .at(t(2))
+            .change(Op::Add(2))
+            .at(t(3))
+            .change(Op::Add(3))
+            .at(t(4))
+            .build();
+        history[2].set_when(t(0));
+        history[1].set_idempotency_key(Some("add".into()));
+        history[3].set_idempotency_key(Some("add".into()));
+        let store = MemStore::new();
+        block_on(store.import_entity(id, history, false)).unwrap();
+
+        let report = block_on(store.verify(id)).unwrap();
+        assert!(!report.is_ok());
+        assert!(!report.not_created);
+        assert_eq!(report.out_of_order, vec![2]);
+        assert_eq!(report.duplicates, vec![3]);
+
+        let before = block_on(store.repair(id)).unwrap();
+        assert_eq!(before, report);
+        let after = block_on(store.verify(id)).unwrap();
+        assert!(after.out_of_order.is_empty());
+        assert_eq!(after.duplicates, vec![3]);
+        let history: Vec<_> = block_on(store.change_list(id).try_collect()).unwrap();
+        assert_eq!(history[2].when(), t(2));
+        assert_eq!(
+            history[2].change().map(|op| matches!(op, Op::Add(2))),
+            Some(true)
+        );
+
+        let unknown = TestCount::new(2).id();
+        assert!(matches!(
+            block_on(store.verify(unknown)),
+            Err(CommitError::NotFound)
+        ));
+    }
+}
@@ -0,0 +1,172 @@
+use super::{Batch, Commit, CommitError, CommitResult, CommitStore};
+use crate::{Event, EventKind, Model};
+use futures::stream::TryStreamExt;
+use uuid::Uuid;
+
+/// A copy of an entity where changes are tried out before persisting them,
+/// the preview reflects every change applied so far. Committing persists
+/// the changes in order under the same transaction, it fails with a
+/// `Conflict` when the entity changed since the copy was made.
+#[derive(Debug)]
+pub struct WorkingCopy<M: Model, S> {
+    store: S,
+    id: M::Id,
+    preview: M,
+    version: u64,
+    changes: Vec<M::Change>,
+}
+
+impl<M: Model, S: CommitStore<M>> WorkingCopy<M, S> {
+    pub async fn new(store: S, id: M::Id) -> CommitResult<Self> {
+        let history: Vec<Commit<M>> = store.change_list(id.clone()).try_collect().await?;
+        let mut preview = history
+            .first()
+            .and_then(|c| c.entity())
+            .ok_or(CommitError::NotFound)?;
+        if history.iter().any(|c| c.kind() == EventKind::Delete) {
+            return Err(CommitError::Deleted);
+        }
+        for change in history.iter().filter_map(|c| c.change()) {
+            preview.apply_change(&change);
+        }
+        Ok(WorkingCopy {
+            store,
+            id,
+            preview,
+            version: history.len() as u64,
+            changes: vec![],
+        })
+    }
+
+    pub fn apply(&mut self, change: M::Change) -> &M {
+        self.preview.apply_change(&change);
+        self.changes.push(change);
+        &self.preview
+    }
+
+    pub fn preview(&self) -> &M {
+        &self.preview
+    }
+
+    /// Changes applied so far that would be committed
+    pub fn changes(&self) -> &[M::Change] {
+        &self.changes
+    }
+
+    /// Persists the applied changes at once, returning the id of the
+    /// transaction they were committed in. It goes straight to the backend,
+    /// send the `batch` to the `Store` actor instead to have the changes
+    /// published to its subscribers.
+    pub async fn commit(self) -> CommitResult<Uuid> {
+        let transaction = Uuid::new_v4();
+        let store = self.store.clone();
+        store.commit_all(self.into_commits(transaction)).await?;
+        Ok(transaction)
+    }
+
+    /// The applied changes as commits of the same transaction, each one
+    /// expecting the version the previous one leads to so the batch conflicts
+    /// when the entity changed since the copy was made.
+    pub fn batch(self) -> Batch<M> {
+        Batch(self.into_commits(Uuid::new_v4()))
+    }
+
+    fn into_commits(self, transaction: Uuid) -> Vec<Commit<M>> {
+        let id = self.id;
+        self.changes
+            .into_iter()
+            .zip(self.version..)
+            .map(|(change, version)| {
+                let mut c: Commit<M> = Event::Change(id.clone(), change).into();
+                c.set_transaction(Some(transaction));
+                c.set_expected_version(Some(version));
+                c
+            })
+            .collect()
+    }
+
+    /// Throws away the applied changes
+    pub fn discard(self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::tests::{Op, TestCount};
+    use crate::testing::collect_events;
+    use crate::{EventBus, MemStore, Store, StoreMsg};
+    use futures::executor::block_on;
+    use riker::actors::*;
+    use riker_patterns::ask::ask;
+
+    #[test]
+    fn commit_only_kept_changes() {
+        let store = MemStore::new();
+        let count = TestCount::new(1);
+        let id = count.id();
+        block_on(store.commit(Event::Create(count).into())).unwrap();
+
+        let mut copy = block_on(store.working_copy(id)).unwrap();
+        copy.apply(Op::Add(100));
+        assert_eq!(copy.preview().count, 101);
+        copy.discard();
+
+        let mut copy = block_on(store.working_copy(id)).unwrap();
+        copy.apply(Op::Add(2));
+        assert_eq!(copy.apply(Op::Sub(1)).count, 2);
+        assert_eq!(copy.changes().len(), 2);
+        let tx = block_on(copy.commit()).unwrap();
+
+        let history: Vec<_> = block_on(store.change_list(id).try_collect()).unwrap();
+        assert_eq!(history.len(), 3);
+        assert!(history[1..].iter().all(|c| c.transaction() == Some(tx)));
+        let count = block_on(async { store.get(id).await?.to_present().await }).unwrap();
+        assert_eq!(count.count, 2);
+    }
+
+    #[test]
+    fn publish_batch_committed_through_store() {
+        let sys = ActorSystem::new().unwrap();
+        let bus: EventBus<TestCount> = channel("bus", &sys).unwrap();
+        let backend = MemStore::new();
+        let store = sys
+            .actor_of_args::<Store<TestCount, _>, _>("counts", (backend.clone(), bus.clone()))
+            .unwrap();
+        let events = collect_events(&sys, &bus, "counts-events", 2);
+        let count = TestCount::new(1);
+        let id = count.id();
+        block_on(backend.commit(Event::Create(count).into())).unwrap();
+
+        let mut copy = block_on(backend.working_copy(id)).unwrap();
+        copy.apply(Op::Add(2));
+        copy.apply(Op::Add(3));
+        let batch = copy.batch();
+        assert_eq!(batch.0[0].expected_version(), Some(1));
+        assert_eq!(batch.0[1].expected_version(), Some(2));
+        let committed: CommitResult<()> = block_on(ask(&sys, &store, StoreMsg::from(batch)));
+        committed.unwrap();
+        let events = block_on(events);
+        assert!(matches!(events[1], Event::Change(_, Op::Add(3))));
+        let count = block_on(backend.snapshot(id, chrono::Utc::now())).unwrap();
+        assert_eq!(count.count, 6);
+    }
+
+    #[test]
+    fn conflict_when_entity_changed() {
+        let store = MemStore::new();
+        let count = TestCount::new(1);
+        let id = count.id();
+        block_on(store.commit(Event::Create(count).into())).unwrap();
+
+        let mut copy = block_on(store.working_copy(id)).unwrap();
+        copy.apply(Op::Add(1));
+        block_on(store.commit(Event::Change(id, Op::Add(5)).into())).unwrap();
+        assert!(matches!(
+            block_on(copy.commit()),
+            Err(CommitError::Conflict {
+                expected: 1,
+                actual: 2
+            })
+        ));
+    }
+}
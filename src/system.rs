@@ -0,0 +1,58 @@
+use riker::actors::*;
+use riker::system::SystemError;
+
+/// A thin builder over Riker's `SystemBuilder` that configures an `ActorSystem`
+/// with defaults that suit event sourcing workloads.
+pub struct EsSystem {
+    name: String,
+    pool_size: Option<usize>,
+    log_level: String,
+}
+
+impl EsSystem {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// Number of threads of the executor running actors and store operations,
+    /// by default Riker uses twice the number of cpus.
+    pub fn pool_size(mut self, size: usize) -> Self {
+        self.pool_size = Some(size);
+        self
+    }
+
+    pub fn log_level(mut self, level: &str) -> Self {
+        self.log_level = level.into();
+        self
+    }
+
+    pub fn create(self) -> Result<ActorSystem, SystemError> {
+        let mut cfg = riker::load_config();
+        cfg.set("log.level", self.log_level).unwrap();
+        if let Some(size) = self.pool_size {
+            cfg.set("dispatcher.pool_size", size as i64).unwrap();
+        }
+        SystemBuilder::new().name(&self.name).cfg(cfg).create()
+    }
+}
+
+impl Default for EsSystem {
+    fn default() -> Self {
+        EsSystem {
+            name: "actor-es".into(),
+            pool_size: None,
+            log_level: "info".into(),
+        }
+    }
+}
+
+/// Creates an actor system with the default `EsSystem` configuration,
+/// use it along with a `Manager` that sets up the entities and their buses.
+pub fn es_system() -> ActorSystem {
+    EsSystem::new().create().expect("create actor system")
+}
@@ -0,0 +1,100 @@
+//! Helpers for testing applications built with event sourced entities
+use crate::{Commit, Model};
+use futures::channel::oneshot;
+use futures::future::{BoxFuture, FutureExt};
+use riker::actors::*;
+use std::sync::{Arc, Mutex};
+
+type Done<T> = Arc<Mutex<Option<oneshot::Sender<Vec<T>>>>>;
+
+/// Subscribes to a topic of the bus right away and resolves with the first
+/// `count` messages published to it, e.g. the events of a store.
+pub fn collect_events<T: Message>(
+    sys: &ActorSystem,
+    bus: &ChannelRef<T>,
+    topic: &str,
+    count: usize,
+) -> BoxFuture<'static, Vec<T>> {
+    let (collector, collected) = collect(sys, count);
+    bus.tell(
+        Subscribe {
+            topic: topic.into(),
+            actor: Box::new(collector),
+        },
+        None,
+    );
+    collected
+}
+
+/// Spawns an actor that resolves with the first `count` messages it's told,
+/// for subscriptions that take an actor instead of publishing to a bus.
+pub fn collect<T: Message>(
+    sys: &ActorSystem,
+    count: usize,
+) -> (ActorRef<T>, BoxFuture<'static, Vec<T>>) {
+    let (done, collected) = oneshot::channel();
+    let collector = sys
+        .tmp_actor_of_args::<Collector<T>, _>((count, Arc::new(Mutex::new(Some(done)))))
+        .expect("create collector");
+    let collected = collected.map(|events| events.unwrap_or_default()).boxed();
+    (collector, collected)
+}
+
+struct Collector<T: Message> {
+    count: usize,
+    events: Vec<T>,
+    done: Done<T>,
+}
+
+impl<T: Message> ActorFactoryArgs<(usize, Done<T>)> for Collector<T> {
+    fn create_args((count, done): (usize, Done<T>)) -> Self {
+        Collector {
+            count,
+            events: vec![],
+            done,
+        }
+    }
+}
+
+impl<T: Message> Actor for Collector<T> {
+    type Msg = T;
+
+    fn recv(&mut self, cx: &Context<T>, msg: T, _sender: Sender) {
+        self.events.push(msg);
+        if self.events.len() < self.count {
+            return;
+        }
+        if let Some(done) = self.done.lock().unwrap().take() {
+            let _ = done.send(std::mem::take(&mut self.events));
+        }
+        cx.stop(cx.myself());
+    }
+}
+
+/// Collects the effects commands have on other entities instead of persisting
+/// them, to test what a handler orchestrates given a sequence of commands.
+/// Set it with `EntityConfig::effect_sink`, the outcomes replied by the entity
+/// carry no effects then.
+#[derive(Clone, Debug)]
+pub struct EffectSink<M: Model>(Arc<Mutex<Vec<Commit<M>>>>);
+
+impl<M: Model> EffectSink<M> {
+    pub fn new() -> Self {
+        EffectSink(Arc::new(Mutex::new(vec![])))
+    }
+
+    /// The effects collected so far in the order their commands were handled
+    pub fn effects(&self) -> Vec<Commit<M>> {
+        self.0.lock().unwrap().clone()
+    }
+
+    pub(crate) fn capture(&self, mut effects: Vec<Commit<M>>) {
+        self.0.lock().unwrap().append(&mut effects);
+    }
+}
+
+impl<M: Model> Default for EffectSink<M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}